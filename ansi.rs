@@ -0,0 +1,270 @@
+// ANSI escape sequences in text, as written by compilers and test runners
+// into build logs. SGR (colour and attribute) sequences are turned into
+// styled pieces of text, everything else is stripped.
+
+use ncurses::*;
+
+// First colour pair used for ANSI colours. One pair per foreground and
+// background combination, with "default" as a ninth choice for each.
+const PAIR_BASE: i16 = 16;
+
+// Look of a piece of text
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Style {
+    fg: Option<i16>, // One of the 8 basic colours, None for the viewer's default
+    bg: Option<i16>,
+    bold: bool,
+    underline: bool,
+    reverse: bool,
+}
+
+impl Style {
+    // The default colours in bold
+    pub fn bold() -> Self {
+        Style { bold: true, ..Style::default() }
+    }
+
+    // One of the basic colours on the default background
+    pub fn colored(fg: i16, bold: bool) -> Self {
+        Style { fg: Some(fg), bold, ..Style::default() }
+    }
+
+    // The same style on a basic background colour
+    pub fn on(self, bg: i16) -> Self {
+        Style { bg: Some(bg), ..self }
+    }
+
+    // The ncurses attributes for this style, including its colour pair
+    pub fn attrs(&self) -> attr_t {
+        let mut attrs = COLOR_PAIR(color_pair(self.fg, self.bg));
+        if self.bold {
+            attrs |= A_BOLD;
+        }
+        if self.underline {
+            attrs |= A_UNDERLINE;
+        }
+        if self.reverse {
+            attrs |= A_REVERSE;
+        }
+        attrs
+    }
+
+    // Apply the parameters of one SGR sequence (ESC [ ... m)
+    fn apply(&mut self, params: &str) {
+        let mut codes = params.split([';', ':']).map(|p| p.parse::<u32>().unwrap_or(0));
+        // ESC [ m is the same as ESC [ 0 m
+        let mut next = codes.next();
+        while let Some(code) = next {
+            match code {
+                0 => *self = Style::default(),
+                1 => self.bold = true,
+                4 => self.underline = true,
+                7 => self.reverse = true,
+                22 => self.bold = false,
+                24 => self.underline = false,
+                27 => self.reverse = false,
+                30..=37 => self.fg = Some((code - 30) as i16),
+                39 => self.fg = None,
+                40..=47 => self.bg = Some((code - 40) as i16),
+                49 => self.bg = None,
+                // Bright colours: the basic colour in bold
+                90..=97 => {
+                    self.fg = Some((code - 90) as i16);
+                    self.bold = true;
+                }
+                100..=107 => self.bg = Some((code - 100) as i16),
+                // 256 colours (5;n) or RGB (2;r;g;b), brought down to the basic 8
+                38 | 48 => {
+                    let color = match codes.next() {
+                        // Palette indexes past 255 are ignored
+                        Some(5) => codes.next().filter(|&n| n < 256).map(|n| from_256(n as u16)),
+                        Some(2) => match (codes.next(), codes.next(), codes.next()) {
+                            (Some(r), Some(g), Some(b)) => Some(from_rgb(r, g, b)),
+                            _ => None,
+                        },
+                        _ => None,
+                    };
+                    if code == 38 {
+                        self.fg = color.or(self.fg);
+                    } else {
+                        self.bg = color.or(self.bg);
+                    }
+                }
+                // Blink, italics, fonts and the rest are ignored
+                _ => {}
+            }
+            next = codes.next();
+        }
+    }
+}
+
+// Set up the colour pairs for every foreground and background combination.
+// The default colours are those of the viewer (COLOR_PAIR(1)), `text_colors`.
+pub fn init_colors(text_colors: (i16, i16)) {
+    if !has_colors() || COLOR_PAIRS() < (PAIR_BASE + 81) as i32 {
+        return;
+    }
+    let (default_fg, default_bg) = text_colors;
+    for fg in 0..9 {
+        for bg in 0..9 {
+            let fg_color = if fg == 8 { default_fg } else { fg };
+            let bg_color = if bg == 8 { default_bg } else { bg };
+            init_pair(PAIR_BASE + fg * 9 + bg, fg_color, bg_color);
+        }
+    }
+}
+
+// The colour pair for a foreground and background, or the viewer's own
+// pair if the terminal doesn't have enough of them
+fn color_pair(fg: Option<i16>, bg: Option<i16>) -> i16 {
+    if (fg, bg) == (None, None) || COLOR_PAIRS() < (PAIR_BASE + 81) as i32 {
+        return 1;
+    }
+    PAIR_BASE + fg.unwrap_or(8) * 9 + bg.unwrap_or(8)
+}
+
+// Nearest basic colour to an entry of the xterm 256-colour palette
+fn from_256(n: u16) -> i16 {
+    match n {
+        0..=7 => n as i16,
+        8..=15 => (n - 8) as i16,
+        // 6x6x6 colour cube
+        16..=231 => {
+            let n = n - 16;
+            let level = |v: u16| [0, 95, 135, 175, 215, 255][v as usize];
+            from_rgb(level(n / 36), level(n / 6 % 6), level(n % 6))
+        }
+        // Grey ramp
+        _ => if n < 244 { COLOR_BLACK } else { COLOR_WHITE },
+    }
+}
+
+// Nearest basic colour to an RGB value: each channel on or off
+fn from_rgb(r: u32, g: u32, b: u32) -> i16 {
+    let on = |v: u32| (v >= 128) as i16;
+    // ncurses numbers the basic colours as bits: red 1, green 2, blue 4
+    on(r) | on(g) << 1 | on(b) << 2
+}
+
+// Split a line into pieces of plain text and their styles, starting from
+// the default style. Escape sequences don't appear in the text.
+pub fn parse(line: &str) -> Vec<(Style, String)> {
+    let mut pieces = Vec::new();
+    let mut style = Style::default();
+    let mut text = String::new();
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            text.push(c);
+            continue;
+        }
+        match chars.next() {
+            // Control sequence: parameters, intermediates, then a final byte
+            Some('[') => {
+                let mut params = String::new();
+                let mut last = None;
+                for c in chars.by_ref() {
+                    if ('\x40'..='\x7e').contains(&c) {
+                        last = Some(c);
+                        break;
+                    }
+                    params.push(c);
+                }
+                if last == Some('m') {
+                    let mut new_style = style;
+                    new_style.apply(&params);
+                    if new_style != style {
+                        if !text.is_empty() {
+                            pieces.push((style, std::mem::take(&mut text)));
+                        }
+                        style = new_style;
+                    }
+                }
+            }
+            // Operating system command (window title, hyperlink):
+            // ends with BEL or ESC backslash
+            Some(']') => {
+                while let Some(c) = chars.next() {
+                    if c == '\x07' {
+                        break;
+                    }
+                    if c == '\x1b' && chars.peek() == Some(&'\\') {
+                        chars.next();
+                        break;
+                    }
+                }
+            }
+            // Character set selection takes one more character
+            Some('(' | ')' | '*' | '+') => {
+                chars.next();
+            }
+            // Other two-character sequences
+            _ => {}
+        }
+    }
+    if !text.is_empty() {
+        pieces.push((style, text));
+    }
+    pieces
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn red() -> Style {
+        Style::colored(COLOR_RED, false)
+    }
+
+    #[test]
+    fn colours_and_resets() {
+        let pieces = parse("a\x1b[31mred\x1b[1;44mbold\x1b[mplain\x1b[0m");
+        assert_eq!(pieces, [
+            (Style::default(), "a".to_string()),
+            (red(), "red".to_string()),
+            (Style { bold: true, ..red().on(COLOR_BLUE) }, "bold".to_string()),
+            (Style::default(), "plain".to_string()),
+        ]);
+        // Bright, 256 and RGB colours come down to the basic ones
+        assert_eq!(parse("\x1b[91mx")[0].0, Style { bold: true, ..red() });
+        assert_eq!(parse("\x1b[38;5;196mx")[0].0, red());
+        assert_eq!(parse("\x1b[38;2;200;10;10mx")[0].0, red());
+        assert_eq!(parse("\x1b[48;5;244mx")[0].0, Style::default().on(COLOR_WHITE));
+    }
+
+    #[test]
+    fn other_sequences_are_stripped() {
+        let text = |line: &str| parse(line).into_iter().map(|(_, s)| s).collect::<String>();
+        assert_eq!(text("\x1b[2Ka\x1b]0;title\x07b\x1b]8;;url\x1b\\c\x1b(Bd\x1bMe"), "abcde");
+    }
+
+    #[test]
+    fn malformed_sequences() {
+        let cases: &[(&str, &str, Style)] = &[
+            // Cut off at the end of the line
+            ("a\x1b", "a", Style::default()),
+            ("a\x1b[", "a", Style::default()),
+            ("a\x1b[31", "a", Style::default()),
+            ("a\x1b]0;title", "a", Style::default()),
+            ("a\x1b(", "a", Style::default()),
+            // Colour parameters missing or out of range
+            ("\x1b[31m\x1b[38mx", "x", red()),
+            ("\x1b[31m\x1b[38;5mx", "x", red()),
+            ("\x1b[31m\x1b[38;2;1;2mx", "x", red()),
+            ("\x1b[38;5;99999mx", "x", Style::default()),
+            // Unknown codes and garbage are ignored, garbage counts as 0
+            ("\x1b[31;5;3;999mx", "x", red()),
+            ("\x1b[31;!!mx", "x", Style::default()),
+            // A letter ends the sequence, whatever it is
+            ("\x1b[31;zmx", "mx", Style::default()),
+            ("\x1b[31;;4mx", "x", Style { underline: true, ..Style::default() }),
+        ];
+        for &(line, text, style) in cases {
+            let pieces = parse(line);
+            let joined: String = pieces.iter().map(|(_, s)| s.as_str()).collect();
+            assert_eq!(joined, text, "{:?}", line);
+            assert_eq!(pieces.last().unwrap().0, style, "{:?}", line);
+        }
+        assert!(parse("\x1b[31m").is_empty());
+    }
+}
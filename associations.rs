@@ -0,0 +1,310 @@
+// Which external program opens a file, from ~/.config/norton_commander_rust/associations
+// (or under $XDG_CONFIG_HOME). One association per line, the first match wins:
+//   *.mp4,*.mkv   open       mpv %f
+//   image/*       open,view  feh %f
+//   *.c           edit       $EDITOR %f
+// Patterns are file name globs, or MIME types guessed from the first bytes of
+// the file when they contain a '/'. The actions are open (Enter), view (F3)
+// and edit (F4). In the command, %f is the file, %d its directory and %% a '%'.
+// Files without an association use the built-in viewer and editor.
+
+use crate::settings::config_file;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::Path;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Action {
+    Open,
+    View,
+    Edit,
+}
+
+impl Action {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "open" => Some(Action::Open),
+            "view" => Some(Action::View),
+            "edit" => Some(Action::Edit),
+            _ => None,
+        }
+    }
+}
+
+struct Association {
+    patterns: Vec<String>,
+    actions: Vec<Action>,
+    command: String,
+}
+
+#[derive(Default)]
+pub struct Associations {
+    entries: Vec<Association>,
+}
+
+// Leading bytes of some file types, at an offset
+const MAGIC: &[(usize, &[u8], &str)] = &[
+    (0, b"\x89PNG\r\n\x1a\n", "image/png"),
+    (0, b"\xff\xd8\xff", "image/jpeg"),
+    (0, b"GIF8", "image/gif"),
+    (0, b"%PDF-", "application/pdf"),
+    (0, b"PK\x03\x04", "application/zip"),
+    (0, b"\x1f\x8b", "application/gzip"),
+    (0, b"BZh", "application/x-bzip2"),
+    (0, b"\xfd7zXZ\x00", "application/x-xz"),
+    (0, b"\x28\xb5\x2f\xfd", "application/zstd"),
+    (0, b"\x7fELF", "application/x-executable"),
+    (0, b"ID3", "audio/mpeg"),
+    (0, b"OggS", "audio/ogg"),
+    (0, b"fLaC", "audio/flac"),
+    (4, b"ftyp", "video/mp4"),
+    (0, b"\x1a\x45\xdf\xa3", "video/x-matroska"),
+];
+
+// Bytes looked at to guess the type
+const SNIFF_BYTES: u64 = 512;
+
+// Guess the MIME type of a file from its first bytes: a known file type,
+// otherwise text/plain or application/octet-stream. Directories are inode/directory.
+fn mime_type(path: &Path) -> io::Result<&'static str> {
+    if path.is_dir() {
+        return Ok("inode/directory");
+    }
+    let mut bytes = Vec::new();
+    File::open(path)?.take(SNIFF_BYTES).read_to_end(&mut bytes)?;
+    let known = MAGIC.iter().find(|(offset, magic, _)| bytes.get(*offset..).is_some_and(|b| b.starts_with(magic)));
+    if let Some((_, _, mime)) = known {
+        return Ok(mime);
+    }
+    // A character may be cut at the end
+    let text = match std::str::from_utf8(&bytes) {
+        Ok(_) => true,
+        Err(e) => e.error_len().is_none(),
+    };
+    Ok(if text && !bytes.contains(&0) { "text/plain" } else { "application/octet-stream" })
+}
+
+// Match a glob with * and ?, ignoring case
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let name: Vec<char> = name.to_lowercase().chars().collect();
+    // Where to retry the last * with one more character
+    let mut star: Option<(usize, usize)> = None;
+    let (mut p, mut n) = (0, 0);
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_p, star_n)) => {
+                    star = Some((star_p, star_n + 1));
+                    p = star_p + 1;
+                    n = star_n + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+// Quote text for the shell
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+impl Associations {
+    // Read the associations file. A missing file means no associations.
+    // Returns the lines that couldn't be understood too.
+    pub fn load() -> (Self, Vec<String>) {
+        let Some(path) = config_file("associations") else {
+            return (Associations::default(), Vec::new());
+        };
+        match fs::read_to_string(&path) {
+            Ok(text) => Self::parse(&text, &path),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (Associations::default(), Vec::new()),
+            Err(e) => (Associations::default(), vec![format!("{}: {}", path.display(), e)]),
+        }
+    }
+
+    fn parse(text: &str, path: &Path) -> (Self, Vec<String>) {
+        let mut entries = Vec::new();
+        let mut errors = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (patterns, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let rest = rest.trim_start();
+            let (actions, command) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+            let command = command.trim();
+            let parsed: Option<Vec<Action>> = actions.split(',').map(Action::parse).collect();
+            match parsed {
+                Some(actions) if !command.is_empty() => entries.push(Association {
+                    patterns: patterns.split(',').map(str::to_string).collect(),
+                    actions,
+                    command: command.to_string(),
+                }),
+                Some(_) => errors.push(format!("{}:{}: No command", path.display(), i + 1)),
+                None => errors.push(format!("{}:{}: Actions are open, view and edit, not '{}'",
+                    path.display(), i + 1, actions)),
+            }
+        }
+        (Associations { entries }, errors)
+    }
+
+    // The command that does `action` on a file, with the placeholders filled in
+    pub fn command(&self, path: &Path, action: Action) -> Option<String> {
+        let name = path.file_name()?.to_string_lossy();
+        let mut mime = None;
+        let entry = self.entries.iter().filter(|e| e.actions.contains(&action))
+            .find(|e| matches_any(&e.patterns, path, &name, &mut mime))?;
+        let dir = path.parent().unwrap_or(Path::new("."));
+        Some(expand(&entry.command, &[('f', quote_path(path)), ('d', quote_path(dir))]))
+    }
+}
+
+// Whether a file matches any of the patterns. Its MIME type is guessed at most
+// once, into `mime`, and only if a pattern needs it.
+pub fn matches_any(patterns: &[String], path: &Path, name: &str, mime: &mut Option<&'static str>) -> bool {
+    patterns.iter().any(|pattern| match pattern.contains('/') {
+        true => glob_match(pattern, mime.get_or_insert_with(|| mime_type(path).unwrap_or(""))),
+        false => glob_match(pattern, name),
+    })
+}
+
+pub fn quote_path(path: &Path) -> String {
+    shell_quote(&path.to_string_lossy())
+}
+
+// Replace %x in a command with the text for 'x', and %% with %
+pub fn expand(command: &str, values: &[(char, String)]) -> String {
+    let mut expanded = String::new();
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            expanded.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('%') => expanded.push('%'),
+            Some(x) => match values.iter().find(|(v, _)| *v == x) {
+                Some((_, value)) => expanded.push_str(value),
+                None => {
+                    expanded.push('%');
+                    expanded.push(x);
+                }
+            },
+            None => expanded.push('%'),
+        }
+    }
+    expanded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> (Associations, Vec<String>) {
+        Associations::parse(text, Path::new("associations"))
+    }
+
+    #[test]
+    fn glob_star_and_question_mark() {
+        assert!(glob_match("*.mp4", "film.MP4"));
+        assert!(glob_match("a?c", "abc"));
+        assert!(!glob_match("a?c", "ac"));
+        assert!(glob_match("*.tar.*", "x.tar.tar.gz"));
+        assert!(!glob_match("*.c", "file.cc"));
+        assert!(glob_match("image/*", "image/png"));
+        assert!(!glob_match("image/*", "text/plain"));
+    }
+
+    #[test]
+    fn glob_backtracks() {
+        assert!(glob_match("*ab*ab", "abxabab"));
+        assert!(glob_match("a*b?d", "abbbcd"));
+        assert!(!glob_match("*a*b", "bbba"));
+        assert!(glob_match("**x", "abx"));
+    }
+
+    #[test]
+    fn glob_only_stars() {
+        assert!(glob_match("*", ""));
+        assert!(glob_match("*", "anything"));
+        assert!(glob_match("***", "a"));
+        assert!(!glob_match("", "a"));
+        assert!(glob_match("", ""));
+    }
+
+    #[test]
+    fn expand_placeholders() {
+        let values = [('f', "'a b'".to_string()), ('d', "'/tmp'".to_string())];
+        assert_eq!(expand("cat %f > %d/out", &values), "cat 'a b' > '/tmp'/out");
+        assert_eq!(expand("echo 100%%", &values), "echo 100%");
+        assert_eq!(expand("%%f", &values), "%f");
+        assert_eq!(expand("%x %F", &values), "%x %F");
+        assert_eq!(expand("trailing %", &values), "trailing %");
+        assert_eq!(expand("", &values), "");
+    }
+
+    #[test]
+    fn quotes_for_the_shell() {
+        assert_eq!(quote_path(Path::new("/tmp/it's")), r"'/tmp/it'\''s'");
+    }
+
+    #[test]
+    fn parses_lines() {
+        let (associations, errors) = parse("# comment\n\n  *.mp4,*.mkv   open  mpv  %f \nimage/*\topen,view feh %f\n");
+        assert!(errors.is_empty());
+        assert_eq!(associations.entries.len(), 2);
+        assert_eq!(associations.entries[0].patterns, ["*.mp4", "*.mkv"]);
+        assert_eq!(associations.entries[0].command, "mpv  %f");
+        assert!(associations.entries[1].actions == [Action::Open, Action::View]);
+    }
+
+    #[test]
+    fn reports_bad_lines() {
+        let (associations, errors) = parse("*.c edit\n*.h change vi %f\n*.txt\n");
+        assert!(associations.entries.is_empty());
+        assert_eq!(errors, [
+            "associations:1: No command",
+            "associations:2: Actions are open, view and edit, not 'change'",
+            "associations:3: Actions are open, view and edit, not ''",
+        ]);
+    }
+
+    #[test]
+    fn first_match_wins() {
+        let (associations, _) = parse("*.txt view less %f\n*.TXT open,view more %f\n*.txt edit vi %d\n");
+        let path = Path::new("/home/me/notes.txt");
+        assert_eq!(associations.command(path, Action::View).unwrap(), "less '/home/me/notes.txt'");
+        assert_eq!(associations.command(path, Action::Open).unwrap(), "more '/home/me/notes.txt'");
+        assert_eq!(associations.command(path, Action::Edit).unwrap(), "vi '/home/me'");
+        assert!(associations.command(Path::new("notes.md"), Action::View).is_none());
+    }
+
+    #[test]
+    fn mime_from_content() {
+        let dir = std::env::temp_dir().join(format!("nc-associations-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let png = dir.join("picture");
+        fs::write(&png, b"\x89PNG\r\n\x1a\n....").unwrap();
+        let text = dir.join("notes");
+        fs::write(&text, "plain text").unwrap();
+        assert_eq!(mime_type(&png).unwrap(), "image/png");
+        assert_eq!(mime_type(&text).unwrap(), "text/plain");
+        assert_eq!(mime_type(&dir).unwrap(), "inode/directory");
+        let mut mime = None;
+        assert!(matches_any(&["*.jpg".to_string(), "image/*".to_string()], &png, "picture", &mut mime));
+        assert_eq!(mime, Some("image/png"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,154 @@
+// Byte by byte comparison of two files, e.g. firmware images or build outputs.
+// Both files are read side by side in large blocks. The ranges of differing
+// bytes are listed, up to MAX_RUNS of them, and all of them are counted.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::ops::Range;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+// Further differing ranges are only counted
+pub const MAX_RUNS: usize = 100_000;
+
+// Bytes read from each file at a time
+const BLOCK_SIZE: usize = 1024 * 1024;
+
+pub struct Comparison {
+    pub sizes: [u64; 2],
+    pub runs: Vec<Range<u64>>, // Differing ranges, the first MAX_RUNS of them
+    pub n_runs: u64, // All differing ranges
+    pub differing_bytes: u64, // Including the bytes after the end of the shorter file
+    pending: Option<Range<u64>>, // Range still growing while the files are read
+}
+
+impl Comparison {
+    pub fn is_identical(&self) -> bool {
+        self.n_runs == 0
+    }
+
+    // Add a differing range, joining it to the one before if they touch
+    fn add(&mut self, range: Range<u64>) {
+        self.differing_bytes += range.end - range.start;
+        match &mut self.pending {
+            Some(pending) if pending.end == range.start => pending.end = range.end,
+            _ => {
+                self.finish_run();
+                self.pending = Some(range);
+            }
+        }
+    }
+
+    fn finish_run(&mut self) {
+        if let Some(run) = self.pending.take() {
+            self.n_runs += 1;
+            if self.runs.len() < MAX_RUNS {
+                self.runs.push(run);
+            }
+        }
+    }
+}
+
+// Compare two files. `progress` is set to the number of bytes compared so far.
+// Returns None if `cancel` was set.
+pub fn compare(left: &Path, right: &Path, progress: &AtomicU64, cancel: &AtomicBool) -> io::Result<Option<Comparison>> {
+    let mut files = [File::open(left)?, File::open(right)?];
+    let sizes = [files[0].metadata()?.len(), files[1].metadata()?.len()];
+    let mut comparison = Comparison { sizes, runs: Vec::new(), n_runs: 0, differing_bytes: 0, pending: None };
+    let mut buffers = [vec![0u8; BLOCK_SIZE], vec![0u8; BLOCK_SIZE]];
+    let common = sizes[0].min(sizes[1]);
+    let mut offset = 0;
+    while offset < common {
+        if cancel.load(Ordering::Relaxed) {
+            return Ok(None);
+        }
+        let n = BLOCK_SIZE.min((common - offset) as usize);
+        for (file, buffer) in files.iter_mut().zip(&mut buffers) {
+            file.read_exact(&mut buffer[..n])?;
+        }
+        let [a, b] = &buffers;
+        if a[..n] != b[..n] {
+            for i in (0..n).filter(|&i| a[i] != b[i]) {
+                let pos = offset + i as u64;
+                comparison.add(pos..pos + 1);
+            }
+        }
+        offset += n as u64;
+        progress.store(offset, Ordering::Relaxed);
+    }
+    // The rest of the longer file
+    let longest = sizes[0].max(sizes[1]);
+    if common < longest {
+        comparison.add(common..longest);
+    }
+    comparison.finish_run();
+    Ok(Some(comparison))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    fn compare_bytes(left: &[u8], right: &[u8]) -> Comparison {
+        let files = [left, right].map(|bytes| {
+            let mut file = NamedTempFile::new().unwrap();
+            file.write_all(bytes).unwrap();
+            file
+        });
+        let progress = AtomicU64::new(0);
+        let comparison = compare(files[0].path(), files[1].path(), &progress, &AtomicBool::new(false)).unwrap().unwrap();
+        assert_eq!(progress.load(Ordering::Relaxed), left.len().min(right.len()) as u64);
+        comparison
+    }
+
+    #[test]
+    fn differences() {
+        // Left, right, the differing ranges as (start, end), and the differing bytes
+        type Case = (&'static [u8], &'static [u8], &'static [(u64, u64)], u64);
+        let cases: &[Case] = &[
+            (b"same", b"same", &[], 0),
+            (b"", b"", &[], 0),
+            (b"", b"abc", &[(0, 3)], 3),
+            (b"abcdef", b"aXcdYY", &[(1, 2), (4, 6)], 3),
+            (b"abc", b"abcde", &[(3, 5)], 2),
+            // A difference running into the end of the shorter file joins the rest
+            (b"abcdef", b"abX", &[(2, 6)], 4),
+            (b"Xbc", b"abcd", &[(0, 1), (3, 4)], 2),
+        ];
+        for &(left, right, runs, differing_bytes) in cases {
+            let comparison = compare_bytes(left, right);
+            assert_eq!(comparison.sizes, [left.len() as u64, right.len() as u64]);
+            let found: Vec<(u64, u64)> = comparison.runs.iter().map(|r| (r.start, r.end)).collect();
+            assert_eq!(found, runs, "{:?} {:?}", left, right);
+            assert_eq!(comparison.n_runs, runs.len() as u64);
+            assert_eq!(comparison.differing_bytes, differing_bytes);
+            assert_eq!(comparison.is_identical(), runs.is_empty());
+        }
+    }
+
+    #[test]
+    fn across_blocks() {
+        let left = vec![0u8; BLOCK_SIZE + 10];
+        let mut right = left.clone();
+        right[BLOCK_SIZE - 1] = 1;
+        right[BLOCK_SIZE] = 1;
+        right[BLOCK_SIZE + 9] = 1;
+        let comparison = compare_bytes(&left, &right);
+        let block = BLOCK_SIZE as u64;
+        assert_eq!(comparison.runs, [block - 1..block + 1, block + 9..block + 10]);
+    }
+
+    #[test]
+    fn cancelled() {
+        let file = NamedTempFile::new().unwrap();
+        let result = compare(file.path(), file.path(), &AtomicU64::new(0), &AtomicBool::new(true)).unwrap();
+        // An empty file has nothing to compare before the check
+        assert!(result.is_some());
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(b"x").unwrap();
+        let result = compare(file.path(), file.path(), &AtomicU64::new(0), &AtomicBool::new(true)).unwrap();
+        assert!(result.is_none());
+    }
+}
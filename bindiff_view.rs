@@ -0,0 +1,280 @@
+// Two files compared byte by byte, shown in hex one above the other.
+// The comparison runs in a background thread with its progress on the
+// status line. Identical files are only reported; otherwise the view opens
+// at the first difference, with the differing bytes highlighted.
+
+use crate::ansi::Style;
+use crate::bindiff::{self, Comparison};
+use crate::hex;
+use crate::modal_dialog;
+use crate::settings::ViewCommand;
+use crate::viewer::{close_hint, draw_bottom_line, new_frame, resize_frame, view_key};
+use ncurses::*;
+use std::fs::File;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+
+// How often to show the progress of the comparison, in milliseconds
+const POLL_MS: i32 = 100;
+
+// Differences offered by the 'l' list
+const MAX_LISTED: usize = 1000;
+
+struct BindiffView {
+    superwindow: WINDOW, // Border, title and status line
+    window: WINDOW, // The first file above the second
+    files: [File; 2],
+    names: [String; 2],
+    sizes: [u64; 2],
+    comparison: Option<Comparison>, // None until the comparison has finished
+    percent: u64, // Progress of the comparison
+    top: u64, // Offset of the first visible row
+}
+
+impl BindiffView {
+    fn runs(&self) -> &[Range<u64>] {
+        self.comparison.as_ref().map_or(&[], |c| &c.runs)
+    }
+
+    // Both files in the same layout, wide enough for the larger one's offsets
+    fn layout(&self) -> hex::Layout {
+        hex::Layout::new(self.sizes[0].max(self.sizes[1]), getmaxx(self.window) as usize)
+    }
+
+    fn per_row(&self) -> u64 {
+        self.layout().per_row as u64
+    }
+
+    // Rows of each file on screen, below its name
+    fn pane_rows(&self) -> u64 {
+        ((getmaxy(self.window) - 2) / 2).max(1) as u64
+    }
+
+    // Show the row containing `offset` at the top, at most the last row
+    fn scroll_to(&mut self, offset: u64) {
+        let per_row = self.per_row();
+        let last_row = self.sizes[0].max(self.sizes[1]).saturating_sub(1) / per_row;
+        self.top = (offset / per_row).min(last_row) * per_row;
+    }
+
+    // Show the last rows
+    fn scroll_to_end(&mut self) {
+        let per_row = self.per_row();
+        let last_row = self.sizes[0].max(self.sizes[1]).saturating_sub(1) / per_row;
+        self.top = last_row.saturating_sub(self.pane_rows() - 1) * per_row;
+    }
+
+    // Differences that begin on or above the top row
+    fn differences_above(&self) -> usize {
+        let below_top_row = self.top + self.per_row();
+        self.runs().partition_point(|r| r.start < below_top_row)
+    }
+
+    // Show the next or previous difference on the top row
+    fn jump_to_difference(&mut self, forward: bool) {
+        let i = self.differences_above();
+        let run = match forward {
+            true => self.runs().get(i),
+            false => self.runs()[..i].iter().rev().find(|r| r.start < self.top),
+        };
+        match run.map(|r| r.start) {
+            Some(start) => self.scroll_to(start),
+            None => {
+                beep();
+            }
+        }
+    }
+
+    // Ask for an offset to go to
+    fn prompt_offset(&mut self) {
+        let input = modal_dialog::prompt(self.window, "Go to offset (decimal or 0x hex)", "");
+        let Some(input) = input else {
+            return;
+        };
+        match hex::parse_offset(&input) {
+            Ok(offset) => self.scroll_to(offset),
+            Err(e) => modal_dialog::message(self.window, "Go to", &e),
+        }
+    }
+
+    // Pick one of the first differences from a list
+    fn choose_difference(&mut self) {
+        let items: Vec<String> = self.runs().iter().take(MAX_LISTED)
+            .map(|r| format!("{:08x}  {} bytes", r.start, r.end - r.start))
+            .collect();
+        let items: Vec<&str> = items.iter().map(String::as_str).collect();
+        let current = self.differences_above().saturating_sub(1);
+        if let Some(i) = modal_dialog::choose(self.window, "Differences", &items, current) {
+            self.scroll_to(self.runs()[i].start);
+        }
+    }
+
+    fn draw_frame(&self) {
+        box_(self.superwindow, 0, 0);
+        let Some(c) = &self.comparison else {
+            let _ = mvwaddnstr(self.superwindow, 0, 2, " Compare ", getmaxx(self.superwindow) - 4);
+            draw_bottom_line(self.superwindow, &format!(" Comparing... {}% ", self.percent), &close_hint("cancel"));
+            return;
+        };
+        let more = if c.n_runs > c.runs.len() as u64 { ", the first ones listed" } else { "" };
+        let title = format!(" {} bytes differ in {} places{} ", c.differing_bytes, c.n_runs, more);
+        let _ = mvwaddnstr(self.superwindow, 0, 2, &title, getmaxx(self.superwindow) - 4);
+        let status = format!(" Offset {:08x}  Difference {}/{} ", self.top, self.differences_above(), c.n_runs);
+        draw_bottom_line(self.superwindow, &status, &format!(" n/p l g {}", close_hint("close")));
+    }
+
+    fn draw(&self) {
+        self.draw_frame();
+        werase(self.window);
+        if self.comparison.is_none() {
+            wrefresh(self.window);
+            return;
+        }
+        let layout = self.layout();
+        let per_row = layout.per_row as u64;
+        let pane_rows = self.pane_rows();
+        let len = (per_row * pane_rows) as usize;
+        let bytes = [0, 1].map(|i| hex::read_range(&self.files[i], self.top, len).unwrap_or_default());
+        let highlight = Style::colored(COLOR_WHITE, true).on(COLOR_RED).attrs();
+        for (i, y) in [(0, 0), (1, pane_rows as i32 + 1)] {
+            // Name and size above the bytes
+            wattrset(self.window, COLOR_PAIR(2) | A_BOLD);
+            let _ = mvwaddnstr(self.window, y, 0, &format!("{}  {} bytes", self.names[i], self.sizes[i]), getmaxx(self.window));
+            wattrset(self.window, COLOR_PAIR(1));
+            for row in 0..pane_rows as usize {
+                let start = row * per_row as usize;
+                let Some(row_bytes) = bytes[i].get(start..(start + per_row as usize).min(bytes[i].len())) else {
+                    break; // End of the file
+                };
+                let other = &bytes[1 - i];
+                let offset = self.top + start as u64;
+                hex::draw_row(self.window, y + 1 + row as i32, offset, row_bytes, layout, |j| {
+                    match other.get(start + j) == row_bytes.get(j) {
+                        true => COLOR_PAIR(1),
+                        false => highlight,
+                    }
+                });
+            }
+        }
+        wrefresh(self.window);
+    }
+}
+
+impl Drop for BindiffView {
+    fn drop(&mut self) {
+        delwin(self.window);
+        delwin(self.superwindow);
+    }
+}
+
+// Compare two files byte by byte, and show where they differ
+pub fn compare_files_modal(w_debug: WINDOW, left: &Path, right: &Path) {
+    let opened = File::open(left).and_then(|l| Ok([l, File::open(right)?]))
+        .and_then(|files| Ok((files[0].metadata()?.len(), files[1].metadata()?.len(), files)));
+    let (left_size, right_size, files) = match opened {
+        Ok(opened) => opened,
+        Err(e) => {
+            modal_dialog::message(stdscr(), "Compare", &e.to_string());
+            return;
+        }
+    };
+    let (superwindow, window) = new_frame();
+    let mut view = BindiffView {
+        superwindow,
+        window,
+        files,
+        names: [left, right].map(|p| p.display().to_string()),
+        sizes: [left_size, right_size],
+        comparison: None,
+        percent: 0,
+        top: 0,
+    };
+    view.draw();
+    let _ = waddstr(w_debug, &format!("BINDIFF {} {}\n", left.display(), right.display()));
+    wrefresh(w_debug);
+
+    // Compare in the background
+    let progress = Arc::new(AtomicU64::new(0));
+    let cancel = Arc::new(AtomicBool::new(false));
+    let (sender, receiver) = mpsc::channel();
+    {
+        let (left, right) = (left.to_path_buf(), right.to_path_buf());
+        let (progress, cancel) = (Arc::clone(&progress), Arc::clone(&cancel));
+        thread::spawn(move || {
+            sender.send(bindiff::compare(&left, &right, &progress, &cancel)).ok();
+        });
+    }
+
+    wtimeout(view.window, POLL_MS);
+    loop {
+        if view.comparison.is_none() {
+            match receiver.try_recv() {
+                Ok(Ok(Some(comparison))) if comparison.is_identical() => {
+                    let text = format!("The files are identical\n{} bytes", comparison.sizes[0]);
+                    modal_dialog::message(view.window, "Compare", &text);
+                    break;
+                }
+                Ok(Ok(Some(comparison))) => {
+                    let first = comparison.runs.first().map_or(0, |r| r.start);
+                    view.comparison = Some(comparison);
+                    wtimeout(view.window, -1);
+                    // Start at the first difference
+                    view.scroll_to(first);
+                    view.draw();
+                }
+                Ok(Ok(None)) => break, // Cancelled
+                Ok(Err(e)) => {
+                    modal_dialog::message(view.window, "Compare", &e.to_string());
+                    break;
+                }
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    modal_dialog::message(view.window, "Compare", "The comparison stopped unexpectedly");
+                    break;
+                }
+                Err(mpsc::TryRecvError::Empty) => {
+                    let total = view.sizes[0].min(view.sizes[1]);
+                    view.percent = (progress.load(Ordering::Relaxed) * 100).checked_div(total).unwrap_or(0);
+                    view.draw_frame();
+                }
+            }
+        }
+
+        let ch = wgetch(view.window);
+        let per_row = view.per_row();
+        let page = per_row * view.pane_rows();
+        match ch {
+            // Timeout while comparing
+            ERR => continue,
+            // The viewer's key to close
+            _ if view_key(ch) == Some(ViewCommand::Close) => break,
+            KEY_RESIZE => {
+                resize_frame(w_debug, view.superwindow, view.window);
+                view.scroll_to(view.top);
+            }
+            _ if view.comparison.is_none() => continue,
+            KEY_NPAGE => view.scroll_to(view.top + page),
+            KEY_PPAGE => view.scroll_to(view.top.saturating_sub(page)),
+            // 'n' or Tab for the next difference, 'p' or Shift-Tab for the previous one
+            110 | 9 => view.jump_to_difference(true),
+            112 | KEY_BTAB => view.jump_to_difference(false),
+            // 'l' to list the differences
+            108 => view.choose_difference(),
+            // 'g' to go to an offset
+            103 => view.prompt_offset(),
+            // The viewer's keys to scroll
+            _ => match view_key(ch) {
+                Some(ViewCommand::Down) => view.scroll_to(view.top + per_row),
+                Some(ViewCommand::Up) => view.scroll_to(view.top.saturating_sub(per_row)),
+                Some(ViewCommand::Home) => view.scroll_to(0),
+                Some(ViewCommand::End) => view.scroll_to_end(),
+                Some(ViewCommand::Close) | None => continue,
+            },
+        }
+        view.draw();
+    }
+    // A comparison still running is abandoned
+    cancel.store(true, Ordering::Relaxed);
+}
@@ -0,0 +1,68 @@
+// Copying text to the clipboard of the terminal, wherever it runs.
+// The OSC 52 escape sequence asks the terminal emulator to set its clipboard,
+// so it also works over ssh. Terminals that don't support it ignore it.
+
+use std::io::{self, Write};
+
+// Larger texts are refused: many terminals drop long escape sequences
+pub const MAX_CLIPBOARD_BYTES: usize = 1024 * 1024;
+
+const BASE64_ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64_ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+// Send `text` to the terminal's clipboard
+pub fn copy(text: &str) -> io::Result<()> {
+    if text.len() > MAX_CLIPBOARD_BYTES {
+        return Err(io::Error::other(format!("Can't copy more than {} KiB", MAX_CLIPBOARD_BYTES / 1024)));
+    }
+    let mut out = io::stdout().lock();
+    write!(out, "\x1b]52;c;{}\x07", base64(text.as_bytes()))?;
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_vectors() {
+        // RFC 4648, section 10
+        let cases = [
+            ("", ""),
+            ("f", "Zg=="),
+            ("fo", "Zm8="),
+            ("foo", "Zm9v"),
+            ("foob", "Zm9vYg=="),
+            ("fooba", "Zm9vYmE="),
+            ("foobar", "Zm9vYmFy"),
+        ];
+        for (text, encoded) in cases {
+            assert_eq!(base64(text.as_bytes()), encoded, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn all_bits() {
+        assert_eq!(base64(&[0x00, 0x00, 0x00]), "AAAA");
+        assert_eq!(base64(&[0xff, 0xff, 0xff]), "////");
+        assert_eq!(base64(&[0xfb, 0xef, 0xbe]), "++++");
+        assert_eq!(base64(&[0xff]), "/w==");
+        assert_eq!(base64(&[0xff, 0xfe]), "//4=");
+        assert_eq!(base64("é漢".as_bytes()), "w6nmvKI=");
+    }
+}
@@ -0,0 +1,125 @@
+// Transparent decompression of gzip, bzip2, xz and zstd files.
+// The decompressed stream can't seek, so it is copied to an anonymous
+// temporary file in the background, like a command's output, and the viewer
+// reads what has arrived so far.
+
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+// Decompressed up front, to tell whether the file can be decompressed at all
+const FIRST_CHUNK: usize = 64 * 1024;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Compression {
+    Gzip,
+    Bzip2,
+    Xz,
+    Zstd,
+}
+
+impl Compression {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Compression::Gzip => "gzip",
+            Compression::Bzip2 => "bzip2",
+            Compression::Xz => "xz",
+            Compression::Zstd => "zstd",
+        }
+    }
+
+    // Recognise a compressed file by its magic bytes, or else by its extension
+    pub fn detect(path: &Path, magic: &[u8]) -> Option<Self> {
+        if magic.starts_with(&[0x1f, 0x8b]) {
+            Some(Compression::Gzip)
+        } else if is_bzip2(magic) {
+            Some(Compression::Bzip2)
+        } else if magic.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Some(Compression::Xz)
+        } else if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Compression::Zstd)
+        } else {
+            match path.extension()?.to_str()? {
+                "gz" | "tgz" => Some(Compression::Gzip),
+                "bz2" | "tbz2" => Some(Compression::Bzip2),
+                "xz" | "txz" => Some(Compression::Xz),
+                "zst" | "tzst" => Some(Compression::Zstd),
+                _ => None,
+            }
+        }
+    }
+
+    // A reader producing the decompressed contents of `file`
+    pub fn decoder(&self, file: File) -> io::Result<Box<dyn Read + Send>> {
+        Ok(match self {
+            // Multi-member readers, since logs are often concatenated archives
+            Compression::Gzip => Box::new(flate2::read::MultiGzDecoder::new(file)),
+            Compression::Bzip2 => Box::new(bzip2::read::MultiBzDecoder::new(file)),
+            Compression::Xz => Box::new(xz2::read::XzDecoder::new_multi_decoder(file)),
+            Compression::Zstd => Box::new(zstd::stream::read::Decoder::new(file)?),
+        })
+    }
+}
+
+// "BZh", the block size '1'..='9', then the magic of the first block, or of
+// the end of the stream when nothing was compressed
+fn is_bzip2(magic: &[u8]) -> bool {
+    const BLOCK: [u8; 6] = [0x31, 0x41, 0x59, 0x26, 0x53, 0x59];
+    const END: [u8; 6] = [0x17, 0x72, 0x45, 0x38, 0x50, 0x90];
+    match magic {
+        [b'B', b'Z', b'h', b'1'..=b'9', rest @ ..] => rest.starts_with(&BLOCK) || rest.starts_with(&END),
+        _ => false,
+    }
+}
+
+// Read the first bytes of a file, then rewind
+pub fn read_magic(file: &mut File) -> io::Result<Vec<u8>> {
+    let mut magic = Vec::new();
+    (&mut *file).take(10).read_to_end(&mut magic)?;
+    file.seek(SeekFrom::Start(0))?;
+    Ok(magic)
+}
+
+// A reader of the decompressed contents of `file`. Its first bytes are
+// decompressed now, so a file that isn't compressed after all fails here,
+// and the rest as it is read.
+pub fn decompressed(compression: Compression, file: File) -> io::Result<Box<dyn Read + Send>> {
+    let mut decoder = compression.decoder(file)?;
+    let mut first = vec![0u8; FIRST_CHUNK];
+    let n = decoder.read(&mut first)?;
+    first.truncate(n);
+    Ok(Box::new(io::Cursor::new(first).chain(decoder)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn bzip2(data: &[u8]) -> Vec<u8> {
+        let mut encoder = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::best());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn detect_bzip2() {
+        let none = Path::new("file");
+        assert!(Compression::detect(none, &bzip2(b"hello")) == Some(Compression::Bzip2));
+        assert!(Compression::detect(none, &bzip2(b"")) == Some(Compression::Bzip2));
+        // Text that merely starts like bzip2
+        let cases: [&[u8]; 5] = [
+            b"BZh",
+            b"BZhello world",
+            b"BZh91AY&SX",
+            b"BZh01AY&SY",
+            b"BZh:1AY&SY",
+        ];
+        for magic in cases {
+            assert!(Compression::detect(none, magic).is_none(), "{:?}", magic);
+        }
+        assert!(Compression::detect(none, b"BZh91AY&SY") == Some(Compression::Bzip2));
+        // The extension still counts when the contents don't
+        assert!(Compression::detect(Path::new("file.bz2"), b"BZhello") == Some(Compression::Bzip2));
+    }
+}
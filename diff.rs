@@ -0,0 +1,382 @@
+// Line by line comparison of two texts, for showing them side by side.
+// Uses Myers' algorithm in linear space: common lines at the ends are
+// skipped, and the rest is split at a "middle snake" of matching lines,
+// recursively. A comparison taking too long falls back to showing the
+// remaining part as replaced, so huge, very different files still finish.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read};
+use std::ops::Range;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+// Larger files are refused, as both are held in memory
+pub const MAX_DIFF_BYTES: u64 = 256 * 1024 * 1024;
+
+// Time for finding the smallest difference before settling for a larger one
+const TIME_LIMIT: Duration = Duration::from_secs(5);
+
+// A file split into lines
+pub struct Text {
+    bytes: Vec<u8>,
+    lines: Vec<Range<usize>>, // Each line without its line feed (or CR LF)
+}
+
+impl Text {
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let file = File::open(path)?;
+        if file.metadata()?.len() > MAX_DIFF_BYTES {
+            return Err(io::Error::other(format!("{} is larger than {} MiB",
+                path.display(), MAX_DIFF_BYTES / 1024 / 1024)));
+        }
+        let mut bytes = Vec::new();
+        file.take(MAX_DIFF_BYTES).read_to_end(&mut bytes)?;
+        Ok(Text::from_bytes(bytes))
+    }
+
+    fn from_bytes(bytes: Vec<u8>) -> Self {
+        let mut lines = Vec::new();
+        let mut start = 0;
+        for (i, _) in bytes.iter().enumerate().filter(|&(_, &b)| b == b'\n') {
+            let end = if i > start && bytes[i - 1] == b'\r' { i - 1 } else { i };
+            lines.push(start..end);
+            start = i + 1;
+        }
+        if start < bytes.len() {
+            lines.push(start..bytes.len()); // Last line without a line feed
+        }
+        Text { bytes, lines }
+    }
+
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    // Line `i` (0-based), as text
+    pub fn line(&self, i: usize) -> String {
+        String::from_utf8_lossy(&self.bytes[self.lines[i].clone()]).into_owned()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum RowKind {
+    Same,
+    Changed, // Different lines on both sides
+    Deleted, // Only on the left
+    Inserted, // Only on the right
+}
+
+// One row of the side by side view: a line of each file, or of one of them
+#[derive(Clone, Copy)]
+pub struct Row {
+    pub left: Option<usize>,
+    pub right: Option<usize>,
+    pub kind: RowKind,
+}
+
+pub struct Diff {
+    pub left: Text,
+    pub right: Text,
+    pub rows: Vec<Row>,
+    pub hunks: Vec<usize>, // First row of each run of differing rows
+    pub minimal: bool, // False if the time limit was reached and some changes are larger than needed
+}
+
+// What the comparison found, in order
+enum Op {
+    Equal(usize, usize), // Left and right line
+    Delete(usize),
+    Insert(usize),
+}
+
+struct Myers<'a> {
+    old: &'a [u32],
+    new: &'a [u32],
+    vf: Vec<usize>, // Furthest x on each diagonal, forwards
+    vb: Vec<usize>, // and backwards
+    ops: Vec<Op>,
+    deadline: Instant,
+    cancel: &'a AtomicBool,
+    minimal: bool,
+}
+
+impl Myers<'_> {
+    // Compare old[old_range] with new[new_range]
+    fn conquer(&mut self, mut old_range: Range<usize>, mut new_range: Range<usize>) {
+        // Lines in common at the start
+        while !old_range.is_empty() && !new_range.is_empty() && self.old[old_range.start] == self.new[new_range.start] {
+            self.ops.push(Op::Equal(old_range.start, new_range.start));
+            old_range.start += 1;
+            new_range.start += 1;
+        }
+        // and at the end, added after the rest
+        let mut suffix = 0;
+        while old_range.len() > suffix && new_range.len() > suffix
+            && self.old[old_range.end - suffix - 1] == self.new[new_range.end - suffix - 1]
+        {
+            suffix += 1;
+        }
+        old_range.end -= suffix;
+        new_range.end -= suffix;
+
+        if old_range.is_empty() {
+            self.ops.extend(new_range.clone().map(Op::Insert));
+        } else if new_range.is_empty() {
+            self.ops.extend(old_range.clone().map(Op::Delete));
+        } else if let Some((x, y)) = self.middle_snake(old_range.clone(), new_range.clone()) {
+            self.conquer(old_range.start..x, new_range.start..y);
+            self.conquer(x..old_range.end, y..new_range.end);
+        } else {
+            // Out of time: the whole part is replaced
+            self.minimal = false;
+            self.ops.extend(old_range.clone().map(Op::Delete));
+            self.ops.extend(new_range.clone().map(Op::Insert));
+        }
+
+        for i in 0..suffix {
+            self.ops.push(Op::Equal(old_range.end + i, new_range.end + i));
+        }
+    }
+
+    // A point where the shortest edit path crosses the middle, or None when out of time.
+    // Both ranges are non-empty and differ in their first and last lines.
+    fn middle_snake(&mut self, old_range: Range<usize>, new_range: Range<usize>) -> Option<(usize, usize)> {
+        let n = old_range.len() as isize;
+        let m = new_range.len() as isize;
+        let delta = n - m;
+        let odd = delta & 1 == 1;
+        let max_d = (n + m + 1) / 2 + 1;
+        // Diagonal k is stored at k + offset
+        let offset = max_d + 1;
+        let size = (2 * offset + 1) as usize;
+        self.vf.clear();
+        self.vf.resize(size, 0);
+        self.vb.clear();
+        self.vb.resize(size, 0);
+        let at = |k: isize| (k + offset) as usize;
+        let old = &self.old[old_range.clone()];
+        let new = &self.new[new_range.clone()];
+
+        for d in 0..max_d {
+            if Instant::now() > self.deadline || self.cancel.load(Ordering::Relaxed) {
+                return None;
+            }
+            // Forwards from the start
+            for k in (-d..=d).rev().step_by(2) {
+                let mut x = if k == -d || (k != d && self.vf[at(k - 1)] < self.vf[at(k + 1)]) {
+                    self.vf[at(k + 1)]
+                } else {
+                    self.vf[at(k - 1)] + 1
+                };
+                let y = (x as isize - k) as usize;
+                let (x0, y0) = (x, y);
+                let mut y = y;
+                while (x as isize) < n && (y as isize) < m && old[x] == new[y] {
+                    x += 1;
+                    y += 1;
+                }
+                self.vf[at(k)] = x;
+                if odd && (k - delta).abs() < d && self.vf[at(k)] + self.vb[at(delta - k)] >= n as usize {
+                    return Some((old_range.start + x0, new_range.start + y0));
+                }
+            }
+            // Backwards from the end
+            for k in (-d..=d).rev().step_by(2) {
+                let mut x = if k == -d || (k != d && self.vb[at(k - 1)] < self.vb[at(k + 1)]) {
+                    self.vb[at(k + 1)]
+                } else {
+                    self.vb[at(k - 1)] + 1
+                };
+                let mut y = (x as isize - k) as usize;
+                while (x as isize) < n && (y as isize) < m && old[n as usize - x - 1] == new[m as usize - y - 1] {
+                    x += 1;
+                    y += 1;
+                }
+                self.vb[at(k)] = x;
+                if !odd && (k - delta).abs() <= d && self.vb[at(k)] + self.vf[at(delta - k)] >= n as usize {
+                    return Some((old_range.start + n as usize - x, new_range.start + m as usize - y));
+                }
+            }
+        }
+        None
+    }
+}
+
+// Number each distinct line, so lines are compared as numbers
+fn line_ids(left: &Text, right: &Text) -> (Vec<u32>, Vec<u32>) {
+    let mut ids: HashMap<&[u8], u32> = HashMap::new();
+    let [old, new] = [left, right].map(|text| {
+        text.lines.iter().map(|r| {
+            let next = ids.len() as u32;
+            *ids.entry(&text.bytes[r.clone()]).or_insert(next)
+        }).collect()
+    });
+    (old, new)
+}
+
+// Compare two texts. Returns None if `cancel` was set.
+pub fn compare(left: Text, right: Text, cancel: &AtomicBool) -> Option<Diff> {
+    let (old, new) = line_ids(&left, &right);
+    let mut myers = Myers {
+        old: &old,
+        new: &new,
+        vf: Vec::new(),
+        vb: Vec::new(),
+        ops: Vec::new(),
+        deadline: Instant::now() + TIME_LIMIT,
+        cancel,
+        minimal: true,
+    };
+    myers.conquer(0..old.len(), 0..new.len());
+    if cancel.load(Ordering::Relaxed) {
+        return None;
+    }
+    let minimal = myers.minimal;
+    let rows = align(&myers.ops);
+    let hunks = (0..rows.len())
+        .filter(|&i| rows[i].kind != RowKind::Same && (i == 0 || rows[i - 1].kind == RowKind::Same))
+        .collect();
+    Some(Diff { left, right, rows, hunks, minimal })
+}
+
+// Put deleted and inserted lines next to each other as changed rows
+fn align(ops: &[Op]) -> Vec<Row> {
+    let mut rows = Vec::with_capacity(ops.len());
+    let mut i = 0;
+    while i < ops.len() {
+        if let Op::Equal(left, right) = ops[i] {
+            rows.push(Row { left: Some(left), right: Some(right), kind: RowKind::Same });
+            i += 1;
+            continue;
+        }
+        // A run of deletions and insertions
+        let mut deleted = Vec::new();
+        let mut inserted = Vec::new();
+        while i < ops.len() {
+            match ops[i] {
+                Op::Delete(left) => deleted.push(left),
+                Op::Insert(right) => inserted.push(right),
+                Op::Equal(..) => break,
+            }
+            i += 1;
+        }
+        for j in 0..deleted.len().max(inserted.len()) {
+            let (left, right) = (deleted.get(j).copied(), inserted.get(j).copied());
+            let kind = match (left, right) {
+                (Some(_), Some(_)) => RowKind::Changed,
+                (Some(_), None) => RowKind::Deleted,
+                _ => RowKind::Inserted,
+            };
+            rows.push(Row { left, right, kind });
+        }
+    }
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn diff(left: &str, right: &str) -> Diff {
+        let text = |s: &str| Text::from_bytes(s.as_bytes().to_vec());
+        compare(text(left), text(right), &AtomicBool::new(false)).unwrap()
+    }
+
+    // Lines only on one side, counting a changed row as two
+    fn edits(diff: &Diff) -> usize {
+        diff.rows.iter().map(|row| match row.kind {
+            RowKind::Same => 0,
+            RowKind::Changed => 2,
+            RowKind::Deleted | RowKind::Inserted => 1,
+        }).sum()
+    }
+
+    // The fewest lines to delete and insert, the slow way
+    fn fewest_edits(old: &[u8], new: &[u8]) -> usize {
+        let mut lcs = vec![vec![0; new.len() + 1]; old.len() + 1];
+        for i in (0..old.len()).rev() {
+            for j in (0..new.len()).rev() {
+                lcs[i][j] = if old[i] == new[j] { lcs[i + 1][j + 1] + 1 } else { lcs[i + 1][j].max(lcs[i][j + 1]) };
+            }
+        }
+        old.len() + new.len() - 2 * lcs[0][0]
+    }
+
+    // Every line of both texts once, in order, and same rows really the same
+    fn check_rows(diff: &Diff) {
+        let left: Vec<usize> = diff.rows.iter().filter_map(|r| r.left).collect();
+        let right: Vec<usize> = diff.rows.iter().filter_map(|r| r.right).collect();
+        assert_eq!(left, (0..diff.left.len()).collect::<Vec<_>>());
+        assert_eq!(right, (0..diff.right.len()).collect::<Vec<_>>());
+        for row in diff.rows.iter().filter(|r| r.kind == RowKind::Same) {
+            assert_eq!(diff.left.line(row.left.unwrap()), diff.right.line(row.right.unwrap()));
+        }
+    }
+
+    #[test]
+    fn splits_lines() {
+        let text = Text::from_bytes(b"a\r\nb\n\nc".to_vec());
+        assert_eq!(text.len(), 4);
+        assert_eq!(text.line(0), "a");
+        assert_eq!(text.line(2), "");
+        assert_eq!(text.line(3), "c");
+        assert_eq!(Text::from_bytes(Vec::new()).len(), 0);
+    }
+
+    #[test]
+    fn empty_texts() {
+        let same = diff("", "");
+        assert!(same.rows.is_empty() && same.hunks.is_empty());
+        let added = diff("", "a\nb\n");
+        assert!(added.rows.iter().all(|r| r.kind == RowKind::Inserted));
+        assert_eq!(added.hunks, vec![0]);
+        let removed = diff("a\n", "");
+        assert_eq!(removed.rows.len(), 1);
+        assert!(removed.rows[0].kind == RowKind::Deleted);
+    }
+
+    #[test]
+    fn identical_texts() {
+        let d = diff("a\nb\nc\n", "a\nb\nc\n");
+        assert!(d.rows.iter().all(|r| r.kind == RowKind::Same));
+        assert!(d.hunks.is_empty() && d.minimal);
+    }
+
+    #[test]
+    fn changed_lines_side_by_side() {
+        let d = diff("a\nb\nc\nd\n", "a\nB\nc\nd\ne\n");
+        check_rows(&d);
+        let kinds: Vec<_> = d.rows.iter().map(|r| r.kind).collect();
+        assert!(kinds == [RowKind::Same, RowKind::Changed, RowKind::Same, RowKind::Same, RowKind::Inserted]);
+        assert_eq!(d.hunks, vec![1, 4]);
+    }
+
+    #[test]
+    fn smallest_difference() {
+        // Texts of a few distinct lines, so they have much in common
+        let mut seed = 12345u32;
+        let mut random = |below: u32| {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed % below
+        };
+        for _ in 0..300 {
+            let mut text = || -> Vec<u8> { (0..random(20)).map(|_| b'a' + random(4) as u8).collect() };
+            let (old, new) = (text(), text());
+            let lines = |t: &[u8]| t.iter().map(|&c| format!("{}\n", c as char)).collect::<String>();
+            let d = diff(&lines(&old), &lines(&new));
+            check_rows(&d);
+            assert!(d.minimal);
+            assert_eq!(edits(&d), fewest_edits(&old, &new), "{:?} {:?}", old, new);
+        }
+    }
+
+    #[test]
+    fn cancelled() {
+        let text = || Text::from_bytes(b"a\nb\n".to_vec());
+        assert!(compare(text(), text(), &AtomicBool::new(true)).is_none());
+    }
+}
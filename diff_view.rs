@@ -0,0 +1,230 @@
+// Two files side by side, with the lines that differ highlighted.
+// The files are read and compared in a background thread while the view
+// waits; both sides scroll together, and 'n' and 'p' go from hunk to hunk.
+
+use crate::ansi::Style;
+use crate::diff::{self, Diff, RowKind, Text};
+use crate::display_line::display_row;
+use crate::modal_dialog;
+use crate::settings::{self, ViewCommand};
+use crate::viewer::{close_hint, draw_bottom_line, new_frame, resize_frame, view_key};
+use ncurses::*;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use unicode_width::UnicodeWidthStr;
+
+// How often to check whether the comparison has finished, in milliseconds
+const POLL_MS: i32 = 100;
+
+// Columns moved by Left and Right
+const SHIFT_COLS: usize = 8;
+
+// Rows shown above a hunk after jumping to it
+const CONTEXT_ROWS: usize = 2;
+
+struct DiffView {
+    superwindow: WINDOW, // Border, title and status line
+    window: WINDOW, // Both files, side by side
+    title: String,
+    diff: Option<Diff>, // None until the comparison has finished
+    top: usize, // First visible row
+    shift: usize, // Characters hidden at the start of each line
+    tab_width: usize,
+}
+
+impl DiffView {
+    fn page_rows(&self) -> usize {
+        getmaxy(self.window).max(1) as usize
+    }
+
+    fn n_rows(&self) -> usize {
+        self.diff.as_ref().map_or(0, |d| d.rows.len())
+    }
+
+    // Show `top` as the first row, without scrolling past the end
+    fn scroll_to(&mut self, top: usize) {
+        self.top = top.min(self.n_rows().saturating_sub(self.page_rows()));
+    }
+
+    // Go to the next or previous run of differing rows
+    fn jump_to_hunk(&mut self, forward: bool) {
+        let Some(diff) = &self.diff else {
+            return;
+        };
+        let reference = self.top + CONTEXT_ROWS;
+        let hunk = match forward {
+            true => diff.hunks.iter().find(|&&h| h > reference),
+            false => diff.hunks.iter().rev().find(|&&h| h < reference),
+        };
+        match hunk {
+            Some(&h) => self.scroll_to(h.saturating_sub(CONTEXT_ROWS)),
+            None => {
+                beep();
+            }
+        }
+    }
+
+    fn status(&self) -> String {
+        let Some(diff) = &self.diff else {
+            return " Comparing... ".to_string();
+        };
+        if diff.hunks.is_empty() {
+            return " The files have the same lines ".to_string();
+        }
+        // Hunks that begin above the middle of the screen
+        let hunk = diff.hunks.iter().take_while(|&&h| h < self.top + self.page_rows() / 2).count().max(1);
+        let approximate = if diff.minimal { "" } else { " (approximate)" };
+        format!(" Row {}/{}  Hunk {}/{}{} ", self.top + 1, diff.rows.len(), hunk, diff.hunks.len(), approximate)
+    }
+
+    // Draw the border, title and status line
+    fn draw_frame(&self) {
+        box_(self.superwindow, 0, 0);
+        let _ = mvwaddnstr(self.superwindow, 0, 2, &self.title, getmaxx(self.superwindow) - 4);
+        draw_bottom_line(self.superwindow, &self.status(), &format!(" n/p hunk {}", close_hint("close")));
+    }
+
+    fn draw(&self) {
+        self.draw_frame();
+        werase(self.window);
+        if let Some(diff) = &self.diff {
+            let width = getmaxx(self.window) as usize;
+            let side_width = width.saturating_sub(1) / 2;
+            for (screen_row, row) in diff.rows.iter().skip(self.top).take(self.page_rows()).enumerate() {
+                let style = match row.kind {
+                    RowKind::Same => Style::default(),
+                    RowKind::Changed => Style::colored(COLOR_BLACK, false).on(COLOR_CYAN),
+                    RowKind::Deleted => Style::colored(COLOR_WHITE, true).on(COLOR_RED),
+                    RowKind::Inserted => Style::colored(COLOR_BLACK, false).on(COLOR_GREEN),
+                };
+                let y = screen_row as i32;
+                self.draw_side(y, 0, side_width, &diff.left, row.left, style);
+                let _ = mvwaddstr(self.window, y, side_width as i32, "│");
+                self.draw_side(y, side_width + 1, width - side_width - 1, &diff.right, row.right, style);
+            }
+        }
+        wrefresh(self.window);
+    }
+
+    // Width of the line numbers, with a space after them
+    fn gutter_width(&self) -> usize {
+        self.diff.as_ref().map_or(0, |d| d.left.len().max(d.right.len())).to_string().len() + 1
+    }
+
+    // Draw line `line` of `text` with its number, in `width` columns from `col`
+    fn draw_side(&self, y: i32, col: usize, width: usize, text: &Text, line: Option<usize>, style: Style) {
+        let gutter_width = self.gutter_width();
+        let Some(line) = line else {
+            return; // Nothing on this side
+        };
+        wattron(self.window, COLOR_PAIR(2));
+        let _ = mvwaddnstr(self.window, y, col as i32, &format!("{:>1$} ", line + 1, gutter_width - 1), width as i32);
+        let text_width = width.saturating_sub(gutter_width);
+        if text_width == 0 {
+            wattron(self.window, COLOR_PAIR(1));
+            return;
+        }
+        let shown: String = text.line(line).chars().skip(self.shift).collect();
+        let shown = display_row(&shown, self.tab_width, text_width);
+        let padding = " ".repeat(text_width - shown.width().min(text_width));
+        wattrset(self.window, style.attrs());
+        let _ = waddstr(self.window, &format!("{}{}", shown, padding));
+        wattrset(self.window, COLOR_PAIR(1));
+    }
+}
+
+impl Drop for DiffView {
+    fn drop(&mut self) {
+        delwin(self.window);
+        delwin(self.superwindow);
+    }
+}
+
+// Show two files side by side until the user closes the view
+pub fn diff_files_modal(w_debug: WINDOW, left: &Path, right: &Path) {
+    let (superwindow, window) = new_frame();
+    let mut view = DiffView {
+        superwindow,
+        window,
+        title: format!(" {} │ {} ", left.display(), right.display()),
+        diff: None,
+        top: 0,
+        shift: 0,
+        tab_width: settings::current().tab_width,
+    };
+    view.draw();
+
+    // Read and compare the files in the background
+    let cancel = Arc::new(AtomicBool::new(false));
+    let (sender, receiver) = mpsc::channel();
+    {
+        let (left, right, cancel) = (left.to_path_buf(), right.to_path_buf(), Arc::clone(&cancel));
+        thread::spawn(move || {
+            let result = Text::read(&left)
+                .and_then(|left| Ok((left, Text::read(&right)?)))
+                .map(|(left, right)| diff::compare(left, right, &cancel));
+            sender.send(result).ok();
+        });
+    }
+    let _ = waddstr(w_debug, &format!("DIFF {} {}\n", left.display(), right.display()));
+    wrefresh(w_debug);
+
+    wtimeout(view.window, POLL_MS);
+    loop {
+        if view.diff.is_none() {
+            match receiver.try_recv() {
+                Ok(Ok(Some(diff))) => {
+                    view.diff = Some(diff);
+                    wtimeout(view.window, -1);
+                    // Start at the first difference
+                    if let Some(&first) = view.diff.as_ref().and_then(|d| d.hunks.first()) {
+                        view.scroll_to(first.saturating_sub(CONTEXT_ROWS));
+                    }
+                    view.draw();
+                }
+                Ok(Ok(None)) => break, // Cancelled
+                Ok(Err(e)) => {
+                    modal_dialog::message(view.window, "Compare", &e.to_string());
+                    break;
+                }
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    modal_dialog::message(view.window, "Compare", "The comparison stopped unexpectedly");
+                    break;
+                }
+                Err(mpsc::TryRecvError::Empty) => {}
+            }
+        }
+
+        let ch = wgetch(view.window);
+        let page_rows = view.page_rows();
+        match ch {
+            // Timeout while comparing
+            ERR => continue,
+            KEY_NPAGE => view.scroll_to(view.top + page_rows),
+            KEY_PPAGE => view.scroll_to(view.top.saturating_sub(page_rows)),
+            KEY_RIGHT => view.shift += SHIFT_COLS,
+            KEY_LEFT => view.shift = view.shift.saturating_sub(SHIFT_COLS),
+            // 'n' or Tab for the next hunk, 'p' or Shift-Tab for the previous one
+            110 | 9 => view.jump_to_hunk(true),
+            112 | KEY_BTAB => view.jump_to_hunk(false),
+            KEY_RESIZE => {
+                resize_frame(w_debug, view.superwindow, view.window);
+                view.scroll_to(view.top);
+            }
+            // The viewer's keys to scroll and close
+            _ => match view_key(ch) {
+                Some(ViewCommand::Down) => view.scroll_to(view.top + 1),
+                Some(ViewCommand::Up) => view.scroll_to(view.top.saturating_sub(1)),
+                Some(ViewCommand::Home) => view.scroll_to(0),
+                Some(ViewCommand::End) => view.scroll_to(usize::MAX),
+                Some(ViewCommand::Close) => break,
+                None => continue,
+            },
+        }
+        view.draw();
+    }
+    // A comparison still running is abandoned
+    cancel.store(true, Ordering::Relaxed);
+}
@@ -0,0 +1,188 @@
+// Turn decoded text into what is drawn on one screen row.
+// Tabs are expanded, control characters are shown in caret notation (^A)
+// and wide characters (CJK, emoji) take two columns.
+
+use crate::ansi::Style;
+use ncurses::*;
+use unicode_width::UnicodeWidthChar;
+
+// Tab stops every this many columns unless the user picks another width
+pub const DEFAULT_TAB_WIDTH: usize = 8;
+
+// Builds the text of one row, a piece at a time, without going past the right edge.
+pub struct RowBuilder {
+    text: String, // Text produced since the last take()
+    col: usize, // Screen column of the next character
+    max_cols: usize, // Width of the row
+    tab_width: usize, // Distance between tab stops
+}
+
+impl RowBuilder {
+    pub fn new(max_cols: usize, tab_width: usize) -> Self {
+        RowBuilder {
+            text: String::new(),
+            col: 0,
+            max_cols,
+            tab_width: tab_width.max(1),
+        }
+    }
+
+    // Append text. Returns false once the row is full.
+    pub fn push_str(&mut self, s: &str) -> bool {
+        for c in s.chars() {
+            if !self.push_char(c) {
+                return false;
+            }
+        }
+        true
+    }
+
+    fn push_char(&mut self, c: char) -> bool {
+        let (shown, width) = shown_char(c, self.col, self.tab_width);
+        if c == '\t' {
+            // A tab is cut at the right edge
+            let stop = (self.col + width).min(self.max_cols);
+            while self.col < stop {
+                self.text.push(' ');
+                self.col += 1;
+            }
+            self.col < self.max_cols
+        } else {
+            self.push_visible(&shown, width)
+        }
+    }
+
+    // Append text that takes `width` columns, if it fits entirely
+    fn push_visible(&mut self, s: &str, width: usize) -> bool {
+        if self.col + width > self.max_cols {
+            // Don't split a wide character or an escape at the right edge
+            self.col = self.max_cols;
+            return false;
+        }
+        self.text.push_str(s);
+        self.col += width;
+        self.col < self.max_cols
+    }
+
+    // Screen columns used so far
+    pub fn col(&self) -> usize {
+        self.col
+    }
+
+    // Remove and return the text built so far
+    pub fn take(&mut self) -> String {
+        std::mem::take(&mut self.text)
+    }
+}
+
+// How a character is shown at screen column `col`, and the columns it takes
+pub fn shown_char(c: char, col: usize, tab_width: usize) -> (String, usize) {
+    match c {
+        '\t' => {
+            let width = tab_width.max(1) - col % tab_width.max(1);
+            (" ".repeat(width), width)
+        }
+        // C0 controls and DEL in caret notation
+        '\0'..='\x1f' | '\x7f' => (format!("^{}", ((c as u8) ^ 0x40) as char), 2),
+        // C1 controls as their code
+        '\u{80}'..='\u{9f}' => (format!("<{:02X}>", c as u32), 4),
+        _ => match c.width() {
+            Some(width) => (c.to_string(), width),
+            // Other non-printable characters
+            None => ("?".to_string(), 1),
+        },
+    }
+}
+
+// The screen text of a whole line, cut at `max_cols` columns
+pub fn display_row(line: &str, tab_width: usize, max_cols: usize) -> String {
+    let mut row = RowBuilder::new(max_cols, tab_width);
+    row.push_str(line);
+    row.take()
+}
+
+// Draw styled pieces of text at `row`, `col` of a window, each in its own
+// colours plus `extra` attributes, stopping at `max_cols` columns.
+// Returns the number of columns drawn.
+pub fn draw_styled(window: WINDOW, row: i32, col: i32, max_cols: usize, tab_width: usize,
+    pieces: Vec<(Style, String)>, extra: attr_t) -> usize {
+    let mut builder = RowBuilder::new(max_cols, tab_width);
+    wmove(window, row, col);
+    for (style, text) in pieces {
+        let more = builder.push_str(&text);
+        wattrset(window, style.attrs() | extra);
+        let _ = waddstr(window, &builder.take());
+        if !more {
+            break;
+        }
+    }
+    wattrset(window, COLOR_PAIR(1));
+    builder.col()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shown_chars() {
+        let cases: &[(char, usize, &str, usize)] = &[
+            ('a', 0, "a", 1),
+            ('\t', 0, "        ", 8),
+            ('\t', 5, "   ", 3),
+            ('\t', 8, "        ", 8),
+            ('\x01', 0, "^A", 2),
+            ('\0', 0, "^@", 2),
+            ('\x1b', 0, "^[", 2),
+            ('\x7f', 0, "^?", 2),
+            ('\u{85}', 0, "<85>", 4),
+            ('é', 0, "é", 1),
+            ('漢', 0, "漢", 2),
+            ('😀', 0, "😀", 2),
+            ('\u{200b}', 0, "\u{200b}", 0),
+        ];
+        for &(c, col, shown, width) in cases {
+            assert_eq!(shown_char(c, col, DEFAULT_TAB_WIDTH), (shown.to_string(), width), "{:?} at {}", c, col);
+        }
+        assert_eq!(shown_char('\t', 3, 4), (" ".to_string(), 1));
+        // A tab width of 0 is taken as 1
+        assert_eq!(shown_char('\t', 3, 0), (" ".to_string(), 1));
+    }
+
+    #[test]
+    fn rows_cut_at_the_width() {
+        let cases: &[(&str, usize, &str)] = &[
+            ("hello", 10, "hello"),
+            ("hello", 3, "hel"),
+            ("a\tb", 10, "a       b"),
+            // A tab is cut at the right edge
+            ("a\tb", 4, "a   "),
+            ("a\x01b", 10, "a^Ab"),
+            // Caret notation and wide characters aren't split
+            ("ab\x01", 3, "ab"),
+            ("a漢字", 4, "a漢"),
+            ("a漢字", 5, "a漢字"),
+            ("漢", 1, ""),
+            ("", 5, ""),
+        ];
+        for &(line, max_cols, row) in cases {
+            assert_eq!(display_row(line, DEFAULT_TAB_WIDTH, max_cols), row, "{:?} in {}", line, max_cols);
+        }
+    }
+
+    #[test]
+    fn built_a_piece_at_a_time() {
+        let mut row = RowBuilder::new(6, 4);
+        assert!(row.push_str("ab"));
+        assert_eq!(row.take(), "ab");
+        // Tab stops count from the start of the row, not of the piece
+        assert!(row.push_str("\t"));
+        assert_eq!(row.take(), "  ");
+        assert_eq!(row.col(), 4);
+        assert!(!row.push_str("漢字"));
+        assert_eq!(row.take(), "漢");
+        assert_eq!(row.col(), 6);
+        assert!(!row.push_str("x"));
+        assert_eq!(row.take(), "");
+    }
+}
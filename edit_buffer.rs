@@ -0,0 +1,437 @@
+// Text being edited: lines of characters, with undo and redo.
+// Every change replaces a range of text with other text. Each user action
+// (typing a run of characters, pasting, replacing all matches) is one undo
+// step, made of one or more changes.
+
+// A position in the text: line and character index, both from 0
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Pos {
+    pub line: usize,
+    pub col: usize,
+}
+
+impl Pos {
+    pub fn new(line: usize, col: usize) -> Self {
+        Pos { line, col }
+    }
+
+    // Where `text` ends when inserted here
+    fn after(self, text: &str) -> Pos {
+        match text.rfind('\n') {
+            Some(i) => Pos::new(self.line + text.matches('\n').count(), text[i + 1..].chars().count()),
+            None => Pos::new(self.line, self.col + text.chars().count()),
+        }
+    }
+}
+
+// `removed` was replaced with `inserted` at `start`
+struct Change {
+    start: Pos,
+    removed: String,
+    inserted: String,
+}
+
+struct Step {
+    id: u64, // Tells apart the state after each step, to know whether the text was saved
+    changes: Vec<Change>,
+    cursor_before: Pos,
+    cursor_after: Pos,
+    typing: bool, // Made of typed characters, so more may join it
+}
+
+pub struct Buffer {
+    lines: Vec<Vec<char>>, // Never empty
+    undo: Vec<Step>,
+    redo: Vec<Step>,
+    next_id: u64,
+    saved_id: u64, // Id of the last step when the text was loaded or saved, 0 for none
+    group_depth: usize, // Inside begin_group: changes join the last step
+    crlf: bool, // Lines end with CR LF
+    final_newline: bool, // The last line ends with a line break
+}
+
+impl Buffer {
+    // Lines are taken to end with CR LF only if they all do. With mixed endings
+    // the CRs stay in the text, so the file is saved as it was.
+    pub fn from_text(text: &str) -> Self {
+        let crlf = text.contains("\r\n") && text.matches('\n').count() == text.matches("\r\n").count();
+        let final_newline = text.ends_with('\n');
+        let body = text.strip_suffix('\n').unwrap_or(text);
+        let lines = body.split('\n')
+            .map(|line| {
+                let line = if crlf { line.strip_suffix('\r').unwrap_or(line) } else { line };
+                line.chars().collect()
+            })
+            .collect();
+        Buffer {
+            lines,
+            undo: Vec::new(),
+            redo: Vec::new(),
+            next_id: 1,
+            saved_id: 0,
+            group_depth: 0,
+            crlf,
+            final_newline,
+        }
+    }
+
+    // The whole text, with the line endings it was loaded with
+    pub fn to_text(&self) -> String {
+        let newline = if self.crlf { "\r\n" } else { "\n" };
+        let mut text = self.lines.iter().map(|l| l.iter().collect::<String>()).collect::<Vec<_>>().join(newline);
+        if self.final_newline && !self.is_emptied() {
+            text.push_str(newline);
+        }
+        text
+    }
+
+    // All the text was deleted, so nothing is saved, not even a line break.
+    // A file holding only a line break keeps it until it is changed.
+    fn is_emptied(&self) -> bool {
+        self.lines.len() == 1 && self.lines[0].is_empty() && self.top_id() != 0
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn line(&self, i: usize) -> &[char] {
+        &self.lines[i]
+    }
+
+    pub fn end(&self) -> Pos {
+        let last = self.lines.len() - 1;
+        Pos::new(last, self.lines[last].len())
+    }
+
+    // The nearest position that exists
+    pub fn clamp(&self, pos: Pos) -> Pos {
+        let line = pos.line.min(self.lines.len() - 1);
+        Pos::new(line, pos.col.min(self.lines[line].len()))
+    }
+
+    // The text between two positions
+    pub fn text(&self, start: Pos, end: Pos) -> String {
+        let mut text = String::new();
+        for line in start.line..=end.line {
+            let chars = &self.lines[line];
+            let from = if line == start.line { start.col } else { 0 };
+            let to = if line == end.line { end.col } else { chars.len() };
+            text.extend(&chars[from..to]);
+            if line < end.line {
+                text.push('\n');
+            }
+        }
+        text
+    }
+
+    // Replace the text between two positions, without recording it
+    fn splice(&mut self, start: Pos, end: Pos, text: &str) -> String {
+        let removed = self.text(start, end);
+        let mut tail = self.lines[end.line].split_off(end.col);
+        self.lines[start.line].truncate(start.col);
+        self.lines.drain(start.line + 1..=end.line);
+        let mut pieces = text.split('\n');
+        self.lines[start.line].extend(pieces.next().unwrap_or("").chars());
+        let mut line = start.line;
+        for piece in pieces {
+            line += 1;
+            self.lines.insert(line, piece.chars().collect());
+        }
+        self.lines[line].append(&mut tail);
+        removed
+    }
+
+    fn top_id(&self) -> u64 {
+        self.undo.last().map_or(0, |s| s.id)
+    }
+
+    pub fn is_modified(&self) -> bool {
+        self.top_id() != self.saved_id
+    }
+
+    pub fn mark_saved(&mut self) {
+        self.saved_id = self.top_id();
+        if let Some(step) = self.undo.last_mut() {
+            step.typing = false;
+        }
+    }
+
+    // Make the following changes one undo step, until end_group
+    pub fn begin_group(&mut self, cursor: Pos) {
+        if self.group_depth == 0 {
+            self.push_step(cursor, false);
+        }
+        self.group_depth += 1;
+    }
+
+    pub fn end_group(&mut self, cursor: Pos) {
+        self.group_depth -= 1;
+        if let Some(step) = self.undo.last_mut() {
+            step.cursor_after = cursor;
+        }
+        if self.group_depth == 0 && self.undo.last().is_some_and(|s| s.changes.is_empty()) {
+            self.undo.pop(); // Nothing was changed
+        }
+    }
+
+    fn push_step(&mut self, cursor: Pos, typing: bool) {
+        self.redo.clear();
+        self.undo.push(Step { id: self.next_id, changes: Vec::new(), cursor_before: cursor, cursor_after: cursor, typing });
+        self.next_id += 1;
+    }
+
+    // Replace the text between `start` and `end` with `text`, as a step of its own
+    // or as part of the current group. Typed text joins the previous step if it
+    // was typed just before, also when it types over text on the same line.
+    // Returns the end of the new text.
+    pub fn replace(&mut self, start: Pos, end: Pos, text: &str, cursor: Pos, typing: bool) -> Pos {
+        let typing = typing && !text.contains('\n') && start.line == end.line;
+        let joins = self.group_depth == 0 && typing
+            && self.undo.last().is_some_and(|s| s.typing && s.cursor_after == start);
+        let removed = self.splice(start, end, text);
+        if joins {
+            self.redo.clear();
+            let step = self.undo.last_mut().unwrap();
+            let change = step.changes.last_mut().unwrap();
+            change.removed.push_str(&removed);
+            change.inserted.push_str(text);
+        } else {
+            if self.group_depth == 0 {
+                self.push_step(cursor, typing);
+            }
+            let step = self.undo.last_mut().unwrap();
+            step.changes.push(Change { start, removed, inserted: text.to_string() });
+        }
+        let new_end = start.after(text);
+        self.undo.last_mut().unwrap().cursor_after = new_end;
+        new_end
+    }
+
+    // Take back the last step. Returns where the cursor was before it.
+    pub fn undo(&mut self) -> Option<Pos> {
+        let step = self.undo.pop()?;
+        for change in step.changes.iter().rev() {
+            let end = change.start.after(&change.inserted);
+            self.splice(change.start, end, &change.removed);
+        }
+        let cursor = step.cursor_before;
+        self.redo.push(Step { typing: false, ..step });
+        Some(cursor)
+    }
+
+    // Make the last undone step again. Returns where the cursor was after it.
+    pub fn redo(&mut self) -> Option<Pos> {
+        let step = self.redo.pop()?;
+        for change in &step.changes {
+            let end = change.start.after(&change.removed);
+            self.splice(change.start, end, &change.inserted);
+        }
+        let cursor = step.cursor_after;
+        self.undo.push(step);
+        Some(cursor)
+    }
+
+    // The first occurrence of `needle` at or after `from`, within a line
+    pub fn find(&self, from: Pos, needle: &[char]) -> Option<(Pos, Pos)> {
+        if needle.is_empty() {
+            return None;
+        }
+        for line in from.line..self.lines.len() {
+            let chars = &self.lines[line];
+            let first = if line == from.line { from.col } else { 0 };
+            if chars.len() < needle.len() || first > chars.len() - needle.len() {
+                continue;
+            }
+            if let Some(i) = chars[first..].windows(needle.len()).position(|w| w == needle) {
+                let col = first + i;
+                return Some((Pos::new(line, col), Pos::new(line, col + needle.len())));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Type a string one character at a time, as the editor does
+    fn type_text(buffer: &mut Buffer, at: Pos, text: &str) -> Pos {
+        text.chars().fold(at, |pos, c| buffer.replace(pos, pos, &c.to_string(), pos, true))
+    }
+
+    // Type over the characters from `at`, as the editor does in overwrite mode
+    fn overwrite(buffer: &mut Buffer, at: Pos, text: &str) -> Pos {
+        text.chars().fold(at, |pos, c| {
+            let next = Pos::new(pos.line, pos.col + 1);
+            buffer.replace(pos, next, &c.to_string(), pos, true)
+        })
+    }
+
+    #[test]
+    fn empty_buffer() {
+        let mut buffer = Buffer::from_text("");
+        assert_eq!(buffer.line_count(), 1);
+        assert_eq!(buffer.end(), Pos::new(0, 0));
+        assert_eq!(buffer.to_text(), "");
+        assert!(!buffer.is_modified());
+        assert_eq!(buffer.undo(), None);
+        assert_eq!(buffer.redo(), None);
+        assert_eq!(buffer.find(Pos::default(), &['a']), None);
+        assert_eq!(buffer.clamp(Pos::new(3, 5)), Pos::new(0, 0));
+    }
+
+    #[test]
+    fn keeps_line_endings() {
+        assert_eq!(Buffer::from_text("a\r\nb\r\n").to_text(), "a\r\nb\r\n");
+        assert_eq!(Buffer::from_text("a\nb").to_text(), "a\nb");
+        assert_eq!(Buffer::from_text("a\n\n").line_count(), 2);
+    }
+
+    #[test]
+    fn keeps_mixed_line_endings() {
+        let mut buffer = Buffer::from_text("a\r\nb\nc\r\n");
+        assert_eq!(buffer.line(0), ['a', '\r']);
+        assert_eq!(buffer.to_text(), "a\r\nb\nc\r\n");
+        type_text(&mut buffer, Pos::new(1, 1), "x");
+        assert_eq!(buffer.to_text(), "a\r\nbx\nc\r\n");
+    }
+
+    #[test]
+    fn only_a_line_break() {
+        let mut buffer = Buffer::from_text("\n");
+        assert_eq!(buffer.line_count(), 1);
+        assert_eq!(buffer.to_text(), "\n");
+        type_text(&mut buffer, Pos::default(), "a");
+        assert_eq!(buffer.to_text(), "a\n");
+        buffer.undo();
+        assert_eq!(buffer.to_text(), "\n");
+        buffer.redo();
+        buffer.replace(Pos::default(), buffer.end(), "", Pos::default(), false);
+        assert_eq!(buffer.to_text(), "");
+    }
+
+    #[test]
+    fn cleared_text_is_empty() {
+        let mut buffer = Buffer::from_text("a\nb\n");
+        buffer.replace(Pos::default(), buffer.end(), "", Pos::default(), false);
+        assert_eq!(buffer.line_count(), 1);
+        assert_eq!(buffer.to_text(), "");
+    }
+
+    #[test]
+    fn typing_is_one_step() {
+        let mut buffer = Buffer::from_text("ab\n");
+        let end = type_text(&mut buffer, Pos::new(0, 1), "xyz");
+        assert_eq!(end, Pos::new(0, 4));
+        assert_eq!(buffer.to_text(), "axyzb\n");
+        assert!(buffer.is_modified());
+        assert_eq!(buffer.undo(), Some(Pos::new(0, 1)));
+        assert_eq!(buffer.to_text(), "ab\n");
+        assert!(!buffer.is_modified());
+        assert_eq!(buffer.redo(), Some(Pos::new(0, 4)));
+        assert_eq!(buffer.to_text(), "axyzb\n");
+    }
+
+    #[test]
+    fn typing_elsewhere_is_another_step() {
+        let mut buffer = Buffer::from_text("abc");
+        type_text(&mut buffer, Pos::new(0, 0), "1");
+        type_text(&mut buffer, Pos::new(0, 4), "2");
+        assert_eq!(buffer.to_text(), "1abc2");
+        buffer.undo();
+        assert_eq!(buffer.to_text(), "1abc");
+        buffer.undo();
+        assert_eq!(buffer.to_text(), "abc");
+    }
+
+    #[test]
+    fn undo_after_overwrite() {
+        let mut buffer = Buffer::from_text("hello world");
+        let end = overwrite(&mut buffer, Pos::new(0, 6), "there");
+        assert_eq!(end, Pos::new(0, 11));
+        assert_eq!(buffer.to_text(), "hello there");
+        assert_eq!(buffer.undo(), Some(Pos::new(0, 6)));
+        assert_eq!(buffer.to_text(), "hello world");
+        assert_eq!(buffer.redo(), Some(Pos::new(0, 11)));
+        assert_eq!(buffer.to_text(), "hello there");
+    }
+
+    #[test]
+    fn overwrite_after_insert() {
+        let mut buffer = Buffer::from_text("abcd");
+        let pos = type_text(&mut buffer, Pos::new(0, 1), "XY");
+        overwrite(&mut buffer, pos, "Z");
+        assert_eq!(buffer.to_text(), "aXYZcd");
+        buffer.undo();
+        assert_eq!(buffer.to_text(), "abcd");
+    }
+
+    #[test]
+    fn splice_across_lines() {
+        let mut buffer = Buffer::from_text("one\ntwo\nthree\n");
+        let end = buffer.replace(Pos::new(0, 1), Pos::new(2, 2), "X\nY", Pos::new(0, 1), false);
+        assert_eq!(end, Pos::new(1, 1));
+        assert_eq!(buffer.to_text(), "oX\nYree\n");
+        assert_eq!(buffer.text(Pos::new(0, 0), Pos::new(1, 4)), "oX\nYree");
+        buffer.undo();
+        assert_eq!(buffer.to_text(), "one\ntwo\nthree\n");
+    }
+
+    #[test]
+    fn group_undoes_together() {
+        let mut buffer = Buffer::from_text("a a a");
+        buffer.begin_group(Pos::new(0, 0));
+        for col in [4, 2, 0] {
+            buffer.replace(Pos::new(0, col), Pos::new(0, col + 1), "bb", Pos::new(0, 0), false);
+        }
+        buffer.end_group(Pos::new(0, 2));
+        assert_eq!(buffer.to_text(), "bb bb bb");
+        assert_eq!(buffer.undo(), Some(Pos::new(0, 0)));
+        assert_eq!(buffer.to_text(), "a a a");
+        assert_eq!(buffer.redo(), Some(Pos::new(0, 2)));
+        assert_eq!(buffer.to_text(), "bb bb bb");
+    }
+
+    #[test]
+    fn empty_group_is_not_a_step() {
+        let mut buffer = Buffer::from_text("a");
+        buffer.begin_group(Pos::default());
+        buffer.end_group(Pos::default());
+        assert!(!buffer.is_modified());
+        assert_eq!(buffer.undo(), None);
+    }
+
+    #[test]
+    fn new_change_clears_redo() {
+        let mut buffer = Buffer::from_text("");
+        type_text(&mut buffer, Pos::default(), "a");
+        buffer.undo();
+        type_text(&mut buffer, Pos::default(), "b");
+        assert_eq!(buffer.redo(), None);
+        assert_eq!(buffer.to_text(), "b");
+    }
+
+    #[test]
+    fn saved_after_undo() {
+        let mut buffer = Buffer::from_text("");
+        type_text(&mut buffer, Pos::default(), "a");
+        buffer.mark_saved();
+        type_text(&mut buffer, Pos::new(0, 1), "b");
+        assert!(buffer.is_modified());
+        buffer.undo();
+        assert!(!buffer.is_modified());
+        assert_eq!(buffer.to_text(), "a");
+    }
+
+    #[test]
+    fn find_within_lines() {
+        let buffer = Buffer::from_text("abab\nab");
+        let needle: Vec<char> = "ab".chars().collect();
+        assert_eq!(buffer.find(Pos::new(0, 1), &needle), Some((Pos::new(0, 2), Pos::new(0, 4))));
+        assert_eq!(buffer.find(Pos::new(0, 3), &needle), Some((Pos::new(1, 0), Pos::new(1, 2))));
+        assert_eq!(buffer.find(Pos::new(1, 1), &needle), None);
+        assert_eq!(buffer.find(Pos::default(), &[]), None);
+    }
+}
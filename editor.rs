@@ -0,0 +1,567 @@
+// Built-in text editor (F4), in the same frame and colours as the viewer.
+// The whole file is loaded into memory, so files larger than MAX_EDIT_BYTES
+// are refused; files that aren't UTF-8 go to the hex editor. Shift with the
+// arrow keys selects, Ctrl-C/X/V copy, cut and paste, Ctrl-Z/Y undo and redo,
+// Insert switches to overwriting.
+
+use crate::clipboard;
+use crate::display_line::shown_char;
+use crate::edit_buffer::{Buffer, Pos};
+use crate::hex_editor;
+use crate::modal_dialog;
+use crate::settings;
+use crate::viewer::{draw_bottom_line, new_frame, resize_frame};
+use ncurses::*;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+// Larger files are refused
+pub const MAX_EDIT_BYTES: u64 = 16 * 1024 * 1024;
+
+struct Editor {
+    superwindow: WINDOW, // Border, title and status line
+    window: WINDOW, // Text area inside the border
+    path: PathBuf,
+    buffer: Buffer,
+    cursor: Pos,
+    anchor: Option<Pos>, // Other end of the selection
+    goal_col: Option<usize>, // Screen column kept while moving up and down
+    top: usize, // First visible line
+    left: usize, // First visible screen column
+    overwrite: bool, // Typing replaces the character at the cursor
+    tab_width: usize,
+    clipboard: String, // Text copied or cut
+    last_find: String, // Offered again the next time
+    last_replacement: String,
+}
+
+// The file as text, None if it isn't UTF-8 text, or why it can't be edited
+fn read_text(path: &Path) -> Result<Option<String>, String> {
+    let metadata = fs::metadata(path).map_err(|e| e.to_string())?;
+    if !metadata.is_file() {
+        return Err(format!("{} is not a regular file", path.display()));
+    }
+    if metadata.len() > MAX_EDIT_BYTES {
+        return Err(format!("{} is too large to edit: {} bytes, the limit is {} MiB\nShift-F4 edits it in hex",
+            path.display(), metadata.len(), MAX_EDIT_BYTES / 1024 / 1024));
+    }
+    let bytes = fs::read(path).map_err(|e| e.to_string())?;
+    Ok(String::from_utf8(bytes).ok())
+}
+
+// Replace a file with new contents: write a new file next to it and rename it over
+// the old one, so the old contents survive a failed write. Symbolic links are followed.
+fn write_file(path: &Path, text: &str) -> std::io::Result<()> {
+    let target = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let dir = target.parent().filter(|d| !d.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let mut temp = tempfile::NamedTempFile::new_in(dir)?;
+    temp.write_all(text.as_bytes())?;
+    if let Ok(metadata) = fs::metadata(&target) {
+        temp.as_file().set_permissions(metadata.permissions())?;
+    }
+    temp.as_file().sync_all()?;
+    temp.persist(&target).map_err(|e| e.error)?;
+    Ok(())
+}
+
+impl Editor {
+    fn height(&self) -> usize {
+        getmaxy(self.window).max(1) as usize
+    }
+
+    fn width(&self) -> usize {
+        getmaxx(self.window).max(1) as usize
+    }
+
+    // Screen column of a position, from the start of its line
+    fn screen_col(&self, pos: Pos) -> usize {
+        self.buffer.line(pos.line)[..pos.col].iter()
+            .fold(0, |col, &c| col + shown_char(c, col, self.tab_width).1)
+    }
+
+    // The character at or before `screen_col` on a line
+    fn col_at_screen(&self, line: usize, screen_col: usize) -> usize {
+        let mut col = 0;
+        for (i, &c) in self.buffer.line(line).iter().enumerate() {
+            col += shown_char(c, col, self.tab_width).1;
+            if col > screen_col {
+                return i;
+            }
+        }
+        self.buffer.line(line).len()
+    }
+
+    // The selected text's start and end
+    fn selection(&self) -> Option<(Pos, Pos)> {
+        let anchor = self.anchor.filter(|&a| a != self.cursor)?;
+        Some((anchor.min(self.cursor), anchor.max(self.cursor)))
+    }
+
+    // Put the cursor at `pos`, selecting from where it was if `selecting`
+    fn move_to(&mut self, pos: Pos, selecting: bool) {
+        if selecting {
+            self.anchor.get_or_insert(self.cursor);
+        } else {
+            self.anchor = None;
+        }
+        self.cursor = self.buffer.clamp(pos);
+    }
+
+    // Move up or down by `lines`, keeping the screen column
+    fn move_lines(&mut self, lines: isize, selecting: bool) {
+        let goal = self.goal_col.unwrap_or_else(|| self.screen_col(self.cursor));
+        let line = self.cursor.line.saturating_add_signed(lines).min(self.buffer.line_count() - 1);
+        let col = self.col_at_screen(line, goal);
+        self.move_to(Pos::new(line, col), selecting);
+        self.goal_col = Some(goal);
+    }
+
+    // The position one character to the left, or the end of the line above
+    fn before(&self, pos: Pos) -> Pos {
+        match pos {
+            Pos { line: 0, col: 0 } => pos,
+            Pos { line, col: 0 } => Pos::new(line - 1, self.buffer.line(line - 1).len()),
+            Pos { line, col } => Pos::new(line, col - 1),
+        }
+    }
+
+    // The position one character to the right, or the start of the line below
+    fn after(&self, pos: Pos) -> Pos {
+        if pos.col < self.buffer.line(pos.line).len() {
+            Pos::new(pos.line, pos.col + 1)
+        } else if pos.line + 1 < self.buffer.line_count() {
+            Pos::new(pos.line + 1, 0)
+        } else {
+            pos
+        }
+    }
+
+    // The start of the previous or next word
+    fn word_start(&self, forward: bool) -> Pos {
+        let is_word = |p: Pos| self.buffer.line(p.line).get(p.col).is_some_and(|c| c.is_alphanumeric() || *c == '_');
+        let mut pos = self.cursor;
+        if forward {
+            while is_word(pos) && pos != self.buffer.end() {
+                pos = self.after(pos);
+            }
+            while !is_word(pos) && pos != self.buffer.end() {
+                pos = self.after(pos);
+            }
+        } else {
+            pos = self.before(pos);
+            while !is_word(pos) && pos != Pos::default() {
+                pos = self.before(pos);
+            }
+            while pos.col > 0 && is_word(Pos::new(pos.line, pos.col - 1)) {
+                pos.col -= 1;
+            }
+        }
+        pos
+    }
+
+    // Replace the selection (or nothing, at the cursor) with `text`
+    fn replace_selection(&mut self, text: &str, typing: bool) {
+        let (start, end) = self.selection().unwrap_or((self.cursor, self.cursor));
+        self.cursor = self.buffer.replace(start, end, text, self.cursor, typing);
+        self.anchor = None;
+    }
+
+    fn type_char(&mut self, c: char) {
+        let at_end = self.cursor.col >= self.buffer.line(self.cursor.line).len();
+        if self.overwrite && self.selection().is_none() && !at_end {
+            let next = Pos::new(self.cursor.line, self.cursor.col + 1);
+            self.cursor = self.buffer.replace(self.cursor, next, &c.to_string(), self.cursor, true);
+        } else {
+            self.replace_selection(&c.to_string(), true);
+        }
+    }
+
+    // Backspace or Delete: remove the selection, or the character before or after the cursor
+    fn delete(&mut self, forward: bool) {
+        if self.selection().is_none() {
+            let other = if forward { self.after(self.cursor) } else { self.before(self.cursor) };
+            if other == self.cursor {
+                beep();
+                return;
+            }
+            self.anchor = Some(other);
+        }
+        self.replace_selection("", false);
+    }
+
+    fn copy(&mut self) -> bool {
+        let Some((start, end)) = self.selection() else {
+            beep();
+            return false;
+        };
+        self.clipboard = self.buffer.text(start, end);
+        // Also for other programs, where the terminal allows it
+        clipboard::copy(&self.clipboard).ok();
+        true
+    }
+
+    fn undo(&mut self, redo: bool) {
+        let cursor = if redo { self.buffer.redo() } else { self.buffer.undo() };
+        match cursor {
+            Some(cursor) => self.move_to(cursor, false),
+            None => {
+                beep();
+            }
+        }
+    }
+
+    // Popups hide the text cursor; draw() shows it again
+    fn prompt(&self, title: &str, initial: &str) -> Option<String> {
+        curs_set(CURSOR_VISIBILITY::CURSOR_INVISIBLE);
+        modal_dialog::prompt(self.window, title, initial)
+    }
+
+    fn choose(&self, title: &str, items: &[&str]) -> Option<usize> {
+        curs_set(CURSOR_VISIBILITY::CURSOR_INVISIBLE);
+        modal_dialog::choose(self.window, title, items, 0)
+    }
+
+    fn message(&self, title: &str, text: &str) {
+        curs_set(CURSOR_VISIBILITY::CURSOR_INVISIBLE);
+        modal_dialog::message(self.window, title, text);
+    }
+
+    // Select the next occurrence of `needle` after the cursor, starting over from the top if needed
+    fn find_next(&mut self, needle: &str) {
+        let needle: Vec<char> = needle.chars().collect();
+        let found = self.buffer.find(self.cursor, &needle).or_else(|| self.buffer.find(Pos::default(), &needle));
+        match found {
+            Some((start, end)) => {
+                self.anchor = Some(start);
+                self.cursor = end;
+            }
+            None => self.message("Find", &format!("Not found: {}", needle.iter().collect::<String>())),
+        }
+    }
+
+    fn find(&mut self) {
+        let Some(needle) = self.prompt("Find", &self.last_find).filter(|s| !s.is_empty()) else {
+            return;
+        };
+        self.last_find = needle.clone();
+        self.find_next(&needle);
+    }
+
+    // Replace occurrences from the cursor to the end, asking about each one unless the user picks All
+    fn replace(&mut self) {
+        let Some(needle) = self.prompt("Replace", &self.last_find).filter(|s| !s.is_empty()) else {
+            return;
+        };
+        self.last_find = needle.clone();
+        let Some(replacement) = self.prompt("Replace with", &self.last_replacement) else {
+            return;
+        };
+        self.last_replacement = replacement.clone();
+
+        let needle: Vec<char> = needle.chars().collect();
+        let mut from = self.selection().map_or(self.cursor, |(start, _)| start);
+        let mut all = false;
+        let mut count = 0;
+        // All replacements are undone together
+        self.buffer.begin_group(self.cursor);
+        while let Some((start, end)) = self.buffer.find(from, &needle) {
+            if !all {
+                self.anchor = Some(start);
+                self.cursor = end;
+                self.draw();
+                match self.choose("Replace?", &["Yes", "No", "All", "Cancel"]) {
+                    Some(0) => {}
+                    Some(1) => {
+                        from = end;
+                        continue;
+                    }
+                    Some(2) => all = true,
+                    _ => break,
+                }
+            }
+            self.cursor = self.buffer.replace(start, end, &replacement, self.cursor, false);
+            self.anchor = None;
+            from = self.cursor;
+            count += 1;
+        }
+        self.buffer.end_group(self.cursor);
+        self.anchor = None;
+        self.draw();
+        self.message("Replace", &format!("Replaced {} match{}", count, if count == 1 { "" } else { "es" }));
+    }
+
+    // Write the text to its file. Returns false if that failed.
+    fn save(&mut self) -> bool {
+        match write_file(&self.path, &self.buffer.to_text()) {
+            Ok(()) => {
+                self.buffer.mark_saved();
+                true
+            }
+            Err(e) => {
+                self.message("Save", &format!("{}: {}", self.path.display(), e));
+                false
+            }
+        }
+    }
+
+    // Write the text to another file, which is edited from then on
+    fn save_as(&mut self) {
+        let Some(input) = self.prompt("Save as", &self.path.to_string_lossy()).filter(|s| !s.is_empty()) else {
+            return;
+        };
+        // Relative to the file's directory
+        let path = self.path.parent().unwrap_or(Path::new("")).join(input);
+        if path != self.path && path.exists() && settings::current().confirm_overwrite {
+            let title = format!("{} exists", path.display());
+            if self.choose(&title, &["Overwrite", "Cancel"]) != Some(0) {
+                return;
+            }
+        }
+        let old_path = std::mem::replace(&mut self.path, path);
+        if !self.save() {
+            self.path = old_path;
+        }
+    }
+
+    // Whether to close, after asking what to do with unsaved changes
+    fn confirm_close(&mut self) -> bool {
+        if !self.buffer.is_modified() {
+            return true;
+        }
+        match self.choose("Save changes?", &["Save", "Discard changes", "Cancel"]) {
+            Some(0) => self.save(),
+            Some(1) => true,
+            _ => false,
+        }
+    }
+
+    // Scroll so the cursor is on screen
+    fn scroll_into_view(&mut self) {
+        let (height, width) = (self.height(), self.width());
+        if self.cursor.line < self.top {
+            self.top = self.cursor.line;
+        } else if self.cursor.line >= self.top + height {
+            self.top = self.cursor.line + 1 - height;
+        }
+        let col = self.screen_col(self.cursor);
+        if col < self.left {
+            self.left = col;
+        } else if col >= self.left + width {
+            self.left = col + 1 - width;
+        }
+    }
+
+    fn draw_frame(&self) {
+        box_(self.superwindow, 0, 0);
+        let _ = mvwaddnstr(self.superwindow, 0, 2, &format!(" {} ", self.path.display()), getmaxx(self.superwindow) - 4);
+        let mode = if self.overwrite { "Overwrite" } else { "Insert" };
+        let modified = if self.buffer.is_modified() { "  Modified" } else { "" };
+        let status = format!(" Line {}/{}  Col {}  {}{} ", self.cursor.line + 1, self.buffer.line_count(),
+            self.screen_col(self.cursor) + 1, mode, modified);
+        draw_bottom_line(self.superwindow, &status, " ^S save  ^F find  ^R replace  Esc close ");
+    }
+
+    // Draw the visible part of a line on row `y`
+    fn draw_line(&self, y: i32, line: usize, selection: Option<(Pos, Pos)>) {
+        let right = self.left + self.width();
+        let is_selected = |col: usize| selection.is_some_and(|(start, end)| {
+            let pos = Pos::new(line, col);
+            start <= pos && pos < end
+        });
+        let mut screen_col = 0;
+        let chars = self.buffer.line(line);
+        for (i, &c) in chars.iter().enumerate() {
+            let (shown, width) = shown_char(c, screen_col, self.tab_width);
+            let (start, end) = (screen_col, screen_col + width);
+            screen_col = end;
+            if end <= self.left {
+                continue;
+            }
+            if start >= right {
+                break;
+            }
+            // Cut by an edge of the window: show the visible part as spaces
+            let shown = if start < self.left || end > right {
+                " ".repeat(end.min(right) - start.max(self.left))
+            } else {
+                shown
+            };
+            let attrs = if is_selected(i) { COLOR_PAIR(1) | A_REVERSE } else { COLOR_PAIR(1) };
+            wattrset(self.window, attrs);
+            let _ = mvwaddstr(self.window, y, (start.max(self.left) - self.left) as i32, &shown);
+        }
+        // A selected line break
+        if is_selected(chars.len()) && screen_col >= self.left && screen_col < right {
+            wattrset(self.window, COLOR_PAIR(1) | A_REVERSE);
+            let _ = mvwaddstr(self.window, y, (screen_col - self.left) as i32, " ");
+        }
+        wattrset(self.window, COLOR_PAIR(1));
+    }
+
+    fn draw(&mut self) {
+        self.scroll_into_view();
+        self.draw_frame();
+        werase(self.window);
+        let selection = self.selection();
+        for (y, line) in (self.top..self.buffer.line_count()).take(self.height()).enumerate() {
+            self.draw_line(y as i32, line, selection);
+        }
+        let col = self.screen_col(self.cursor) - self.left;
+        wmove(self.window, (self.cursor.line - self.top) as i32, col as i32);
+        curs_set(CURSOR_VISIBILITY::CURSOR_VISIBLE);
+        wrefresh(self.window);
+    }
+
+    // Act on a key. Returns true when the user wants to close the editor.
+    fn handle_key(&mut self, w_debug: WINDOW, key: WchResult) -> bool {
+        let page = self.height().saturating_sub(1).max(1) as isize;
+        let goal_col = self.goal_col.take();
+        match key {
+            WchResult::KeyCode(code) => match code {
+                KEY_LEFT | KEY_SLEFT => self.move_to(self.before(self.cursor), code == KEY_SLEFT),
+                KEY_RIGHT | KEY_SRIGHT => self.move_to(self.after(self.cursor), code == KEY_SRIGHT),
+                KEY_UP | KEY_DOWN | KEY_SR | KEY_SF | KEY_PPAGE | KEY_NPAGE | KEY_SPREVIOUS | KEY_SNEXT => {
+                    self.goal_col = goal_col;
+                    let lines = match code {
+                        KEY_UP | KEY_SR => -1,
+                        KEY_DOWN | KEY_SF => 1,
+                        KEY_PPAGE | KEY_SPREVIOUS => -page,
+                        _ => page,
+                    };
+                    let selecting = matches!(code, KEY_SR | KEY_SF | KEY_SPREVIOUS | KEY_SNEXT);
+                    self.move_lines(lines, selecting);
+                }
+                KEY_HOME | KEY_SHOME => self.move_to(Pos::new(self.cursor.line, 0), code == KEY_SHOME),
+                KEY_END | KEY_SEND => self.move_to(Pos::new(self.cursor.line, usize::MAX), code == KEY_SEND),
+                // Insert switches between inserting and overwriting
+                KEY_IC => self.overwrite = !self.overwrite,
+                KEY_BACKSPACE => self.delete(false),
+                KEY_DC => self.delete(true),
+                KEY_ENTER => self.replace_selection("\n", false),
+                // F2 to save, F12 or Shift-F2 to save under another name
+                _ if code == KEY_F(2) => {
+                    self.save();
+                }
+                _ if code == KEY_F(12) || code == KEY_F(14) => self.save_as(),
+                // F7 to find, F10 to close
+                _ if code == KEY_F(7) => self.find(),
+                _ if code == KEY_F(10) => return true,
+                KEY_RESIZE => resize_frame(w_debug, self.superwindow, self.window),
+                // Ctrl with the arrows and Home/End moves by word and to either end of the text.
+                // These keys have no fixed codes, so they are told apart by name.
+                _ => match keyname(code).as_deref() {
+                    Some("kLFT5") => self.move_to(self.word_start(false), false),
+                    Some("kRIT5") => self.move_to(self.word_start(true), false),
+                    Some("kHOM5") => self.move_to(Pos::default(), false),
+                    Some("kEND5") => self.move_to(self.buffer.end(), false),
+                    _ => {}
+                },
+            },
+            WchResult::Char(c) => match c {
+                // Ctrl-A selects everything
+                1 => {
+                    self.anchor = Some(Pos::default());
+                    self.cursor = self.buffer.end();
+                }
+                // Ctrl-C copies, Ctrl-X cuts, Ctrl-V pastes
+                3 => {
+                    self.copy();
+                }
+                24 => {
+                    if self.copy() {
+                        self.replace_selection("", false);
+                    }
+                }
+                22 => {
+                    let text = self.clipboard.clone();
+                    self.replace_selection(&text, false);
+                }
+                // Ctrl-F finds, Ctrl-G finds again, Ctrl-R replaces
+                6 => self.find(),
+                7 => {
+                    let needle = self.last_find.clone();
+                    if needle.is_empty() {
+                        self.find();
+                    } else {
+                        self.find_next(&needle);
+                    }
+                }
+                18 => self.replace(),
+                // Ctrl-S saves
+                19 => {
+                    self.save();
+                }
+                // Ctrl-Z undoes, Ctrl-Y redoes
+                26 => self.undo(false),
+                25 => self.undo(true),
+                // Backspace, depending on the terminal
+                8 | 127 => self.delete(false),
+                10 | 13 => self.replace_selection("\n", false),
+                // Escape closes
+                27 => return true,
+                _ => match char::from_u32(c) {
+                    Some(c) if c == '\t' || !c.is_control() => self.type_char(c),
+                    _ => {}
+                },
+            },
+        }
+        false
+    }
+}
+
+impl Drop for Editor {
+    fn drop(&mut self) {
+        delwin(self.window);
+        delwin(self.superwindow);
+    }
+}
+
+// Edit a text file until the user closes the editor
+pub fn edit_file_modal(w_debug: WINDOW, path: &Path) {
+    let text = match read_text(path) {
+        Ok(Some(text)) => text,
+        // Binary files are edited in hex, saying why
+        Ok(None) => {
+            modal_dialog::message(stdscr(), "Edit", &format!("{} is not UTF-8 text, editing in hex", path.display()));
+            return hex_editor::edit_file_modal(w_debug, path);
+        }
+        Err(e) => {
+            modal_dialog::message(stdscr(), "Edit", &e);
+            return;
+        }
+    };
+    let _ = waddstr(w_debug, &format!("EDIT {}\n", path.display()));
+    wrefresh(w_debug);
+    let (superwindow, window) = new_frame();
+    let mut editor = Editor {
+        superwindow,
+        window,
+        path: path.to_path_buf(),
+        buffer: Buffer::from_text(&text),
+        cursor: Pos::default(),
+        anchor: None,
+        goal_col: None,
+        top: 0,
+        left: 0,
+        overwrite: false,
+        tab_width: settings::current().tab_width,
+        clipboard: String::new(),
+        last_find: String::new(),
+        last_replacement: String::new(),
+    };
+    // Raw mode, so Ctrl-C, Ctrl-S and friends arrive as keys
+    raw();
+    loop {
+        editor.draw();
+        let Some(key) = wget_wch(editor.window) else {
+            continue;
+        };
+        if editor.handle_key(w_debug, key) && editor.confirm_close() {
+            break;
+        }
+    }
+    noraw();
+    cbreak();
+    curs_set(CURSOR_VISIBILITY::CURSOR_INVISIBLE);
+}
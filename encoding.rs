@@ -0,0 +1,199 @@
+// Character encodings the viewer can decode.
+// Single-byte code pages keep ASCII as is (including tabs and newlines)
+// and map the upper half through a table.
+
+// Code page 437 (original IBM PC), bytes 0x80..=0xFF
+const CP437_HIGH: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»',
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+// Windows-1252, bytes 0x80..=0x9F. The rest matches ISO-8859-1.
+// Unassigned bytes decode to the C1 control of the same value.
+const CP1252_C1: [char; 32] = [
+    '€', '\u{81}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\u{8d}', 'Ž', '\u{8f}',
+    '\u{90}', '‘', '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\u{9d}', 'ž', 'Ÿ',
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Encoding {
+    Utf8,
+    Latin1,      // ISO-8859-1
+    Windows1252,
+    Cp437,       // DOS
+    Utf16Le,
+    Utf16Be,
+}
+
+impl Encoding {
+    pub const ALL: [Encoding; 6] = [
+        Encoding::Utf8,
+        Encoding::Latin1,
+        Encoding::Windows1252,
+        Encoding::Cp437,
+        Encoding::Utf16Le,
+        Encoding::Utf16Be,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Encoding::Utf8 => "UTF-8",
+            Encoding::Latin1 => "ISO-8859-1",
+            Encoding::Windows1252 => "Windows-1252",
+            Encoding::Cp437 => "CP437",
+            Encoding::Utf16Le => "UTF-16LE",
+            Encoding::Utf16Be => "UTF-16BE",
+        }
+    }
+
+    // Size of one code unit in bytes. Line starts are aligned to it.
+    pub fn unit_size(&self) -> usize {
+        match self {
+            Encoding::Utf16Le | Encoding::Utf16Be => 2,
+            _ => 1,
+        }
+    }
+
+    // The bytes of a line feed
+    pub fn newline(&self) -> &'static [u8] {
+        match self {
+            Encoding::Utf16Le => b"\n\0",
+            Encoding::Utf16Be => b"\0\n",
+            _ => b"\n",
+        }
+    }
+
+    // Guess the encoding from a byte order mark or from the first bytes of the file
+    pub fn detect(sample: &[u8]) -> Self {
+        if sample.starts_with(&[0xef, 0xbb, 0xbf]) {
+            return Encoding::Utf8;
+        }
+        if sample.starts_with(&[0xff, 0xfe]) {
+            return Encoding::Utf16Le;
+        }
+        if sample.starts_with(&[0xfe, 0xff]) {
+            return Encoding::Utf16Be;
+        }
+
+        // UTF-16 text without a BOM has mostly zero high bytes
+        let pairs = sample.len() / 2;
+        if pairs > 0 {
+            let even_zeros = sample.chunks_exact(2).filter(|p| p[0] == 0).count();
+            let odd_zeros = sample.chunks_exact(2).filter(|p| p[1] == 0).count();
+            if odd_zeros * 10 > pairs * 4 && even_zeros * 10 < pairs {
+                return Encoding::Utf16Le;
+            }
+            if even_zeros * 10 > pairs * 4 && odd_zeros * 10 < pairs {
+                return Encoding::Utf16Be;
+            }
+        }
+
+        match std::str::from_utf8(sample) {
+            Ok(_) => Encoding::Utf8,
+            // The sample may end in the middle of a multi-byte character
+            Err(e) if e.error_len().is_none() => Encoding::Utf8,
+            Err(_) => Encoding::Windows1252,
+        }
+    }
+
+    // Decode bytes to text. Invalid sequences become U+FFFD.
+    pub fn decode(&self, bytes: &[u8]) -> String {
+        match self {
+            Encoding::Utf8 => String::from_utf8_lossy(bytes).into_owned(),
+            Encoding::Latin1 => bytes.iter().map(|&b| b as char).collect(),
+            Encoding::Windows1252 => bytes.iter().map(|&b| match b {
+                0x80..=0x9f => CP1252_C1[(b - 0x80) as usize],
+                _ => b as char,
+            }).collect(),
+            Encoding::Cp437 => bytes.iter().map(|&b| match b {
+                0x80..=0xff => CP437_HIGH[(b - 0x80) as usize],
+                _ => b as char,
+            }).collect(),
+            Encoding::Utf16Le | Encoding::Utf16Be => {
+                let units = bytes.chunks_exact(2).map(|p| match self {
+                    Encoding::Utf16Le => u16::from_le_bytes([p[0], p[1]]),
+                    _ => u16::from_be_bytes([p[0], p[1]]),
+                });
+                let mut text: String = char::decode_utf16(units)
+                    .map(|r| r.unwrap_or(char::REPLACEMENT_CHARACTER))
+                    .collect();
+                if !bytes.len().is_multiple_of(2) {
+                    // Odd byte at the end of the file
+                    text.push(char::REPLACEMENT_CHARACTER);
+                }
+                text
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utf16(text: &str, encoding: Encoding) -> Vec<u8> {
+        text.encode_utf16().flat_map(|u| match encoding {
+            Encoding::Utf16Le => u.to_le_bytes(),
+            _ => u.to_be_bytes(),
+        }).collect()
+    }
+
+    #[test]
+    fn detect() {
+        let cases: &[(&[u8], Encoding)] = &[
+            (b"\xef\xbb\xbfplain", Encoding::Utf8),
+            (b"\xff\xfea\0", Encoding::Utf16Le),
+            (b"\xfe\xff\0a", Encoding::Utf16Be),
+            (b"h\0e\0l\0l\0o\0", Encoding::Utf16Le),
+            (b"\0h\0e\0l\0l\0o", Encoding::Utf16Be),
+            ("caf\u{e9}".as_bytes(), Encoding::Utf8),
+            // Cut in the middle of "é"
+            (&"caf\u{e9}".as_bytes()[..4], Encoding::Utf8),
+            (b"caf\xe9 au lait", Encoding::Windows1252),
+            (b"", Encoding::Utf8),
+        ];
+        for &(sample, expected) in cases {
+            assert_eq!(Encoding::detect(sample), expected, "{:?}", sample);
+        }
+    }
+
+    #[test]
+    fn utf16_round_trip() {
+        // U+1F600 is a surrogate pair
+        let text = "a\u{e9}\u{1f600}\n";
+        for encoding in [Encoding::Utf16Le, Encoding::Utf16Be] {
+            let bytes = utf16(text, encoding);
+            assert_eq!(bytes.len(), 10);
+            assert_eq!(encoding.decode(&bytes), text);
+            // An odd byte left at the end
+            let mut odd = bytes.clone();
+            odd.push(b'x');
+            assert_eq!(encoding.decode(&odd), format!("{}\u{fffd}", text));
+            // Half of the surrogate pair
+            assert_eq!(encoding.decode(&bytes[..6]), "a\u{e9}\u{fffd}");
+        }
+    }
+
+    #[test]
+    fn single_byte_round_trip() {
+        let bytes: Vec<u8> = (0..=255).collect();
+        for encoding in [Encoding::Latin1, Encoding::Windows1252, Encoding::Cp437] {
+            let text = encoding.decode(&bytes);
+            let chars: Vec<char> = text.chars().collect();
+            assert_eq!(chars.len(), 256);
+            // ASCII as it is, and every byte to a different character, so it can be encoded back
+            assert_eq!(&text[..128], std::str::from_utf8(&bytes[..128]).unwrap());
+            let back: Vec<u8> = chars.iter().map(|c| chars.iter().position(|d| d == c).unwrap() as u8).collect();
+            assert_eq!(back, bytes, "{}", encoding.name());
+        }
+        assert_eq!(Encoding::Latin1.decode(b"\xe9\x80"), "\u{e9}\u{80}");
+        assert_eq!(Encoding::Windows1252.decode(b"\x80\x81\xe9"), "\u{20ac}\u{81}\u{e9}");
+        assert_eq!(Encoding::Cp437.decode(b"\x80\xb0\xc9\xcd\xbb\xe1\xfe\xff"), "\u{c7}\u{2591}\u{2554}\u{2550}\u{2557}\u{df}\u{25a0}\u{a0}");
+    }
+}
@@ -0,0 +1,67 @@
+// Running other programs on the terminal, e.g. an associated player, $PAGER or
+// $EDITOR. Curses is put aside while they run and the screen is repainted
+// afterwards, at the terminal's new size if it was resized meanwhile.
+
+use crate::modal_dialog;
+use ncurses::*;
+use std::path::Path;
+use std::process::Command;
+
+// The terminal's size in rows and columns, as it is now
+fn terminal_size() -> Option<(i32, i32)> {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    if unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } < 0 || size.ws_row == 0 {
+        return None;
+    }
+    Some((size.ws_row as i32, size.ws_col as i32))
+}
+
+// Run a shell command in `dir` and wait for it. Failures are shown in a message.
+pub fn run(w_debug: WINDOW, command: &str, dir: &Path) {
+    execute(w_debug, command, dir, false);
+}
+
+// Like run, but wait for Enter afterwards so the command's output can be read
+pub fn run_and_wait(w_debug: WINDOW, command: &str, dir: &Path) {
+    execute(w_debug, command, dir, true);
+}
+
+fn execute(w_debug: WINDOW, command: &str, dir: &Path, wait: bool) {
+    let command = command.trim_end();
+    let _ = waddstr(w_debug, &format!("EXTERNAL: {}\n", command));
+    wrefresh(w_debug);
+    let script = match wait {
+        true => format!("{}\nstatus=$?\nprintf '\\nPress Enter to return '\nread _\nexit $status", command),
+        false => command.to_string(),
+    };
+    def_prog_mode();
+    endwin();
+    let status = Command::new("sh").arg("-c").arg(script).current_dir(dir).status();
+    reset_prog_mode();
+    // A resize while the program ran isn't seen by curses
+    let resized = terminal_size().filter(|&size| size != (LINES(), COLS()));
+    if let Some((rows, cols)) = resized {
+        resize_term(rows, cols);
+    }
+    // Repaint everything, the debug window too, as the panels are redrawn by the caller
+    clearok(curscr(), true);
+    refresh();
+    touchwin(w_debug);
+    wrefresh(w_debug);
+    let failure = match status {
+        Ok(status) if status.success() => None,
+        Ok(status) => match status.code() {
+            Some(code) => Some(format!("{}\nexited with status {}", command, code)),
+            None => Some(format!("{}\nwas stopped by a signal", command)),
+        },
+        Err(e) => Some(format!("{}\n{}", command, e)),
+    };
+    if let Some(failure) = failure {
+        modal_dialog::message(stdscr(), "Run", &failure);
+    }
+    // The caller lays out its windows again
+    if resized.is_some() {
+        ungetch(KEY_RESIZE);
+    }
+}
+
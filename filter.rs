@@ -0,0 +1,229 @@
+// Lines of a file matching a text or a regular expression, like grep.
+// The file is scanned from the start only as far as needed to fill the
+// screen, a budget of bytes at a time, so the first matches show up at once
+// even in a large log.
+
+use crate::encoding::Encoding;
+use crate::source::SourceReader;
+use regex::Regex;
+use std::io::{self, BufRead, Read, Seek, SeekFrom};
+
+// Longest line tested for a match; the rest of a longer line is skipped
+const MAX_LINE_BYTES: usize = 1024 * 1024;
+
+enum Pattern {
+    Text(String),
+    Regex(Regex),
+}
+
+pub struct Filter {
+    pattern: Pattern,
+    invert: bool, // Keep the lines that don't match
+    description: String, // What the user typed
+    matches: Vec<(u64, u64)>, // Offset and line number (1-based) of each kept line
+    scanned_to: u64, // Start of the next line to test
+    lines_scanned: u64, // Lines tested so far
+    done: bool, // Reached the end of the file
+}
+
+impl Filter {
+    // Parse a filter: text, or /regex/, with a leading '!' to keep the lines that don't match
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let (invert, rest) = match input.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, input),
+        };
+        let pattern = match rest.strip_prefix('/').and_then(|r| r.strip_suffix('/')) {
+            Some(expr) => Pattern::Regex(Regex::new(expr).map_err(|e| e.to_string())?),
+            None => Pattern::Text(rest.to_string()),
+        };
+        if matches!(&pattern, Pattern::Text(t) if t.is_empty()) {
+            return Err("Empty filter".to_string());
+        }
+        Ok(Filter {
+            pattern,
+            invert,
+            description: input.to_string(),
+            matches: Vec::new(),
+            scanned_to: 0,
+            lines_scanned: 0,
+            done: false,
+        })
+    }
+
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    pub fn matches(&self) -> &[(u64, u64)] {
+        &self.matches
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    pub fn scanned_to(&self) -> u64 {
+        self.scanned_to
+    }
+
+    fn is_match(&self, line: &str) -> bool {
+        let found = match &self.pattern {
+            Pattern::Text(text) => line.contains(text.as_str()),
+            Pattern::Regex(regex) => regex.is_match(line),
+        };
+        found != self.invert
+    }
+
+    // Test lines until there are `wanted` matches, the end of the file, or
+    // `budget` bytes have been read. Returns the number of bytes read.
+    pub fn scan(&mut self, reader: &mut SourceReader, encoding: Encoding, wanted: usize, budget: u64) -> io::Result<u64> {
+        let start = self.scanned_to;
+        reader.seek(SeekFrom::Start(start))?;
+        let mut bytes = Vec::new();
+        while !self.done && self.matches.len() < wanted && self.scanned_to - start < budget {
+            let n = read_line_bytes(reader, encoding, &mut bytes)?;
+            if n == 0 {
+                self.done = true;
+                break;
+            }
+            let mut line = encoding.decode(&bytes);
+            if self.scanned_to == 0 && line.starts_with('\u{feff}') {
+                line.remove(0);
+            }
+            let line = line.trim_end_matches(['\n', '\r']);
+            self.lines_scanned += 1;
+            if self.is_match(line) {
+                self.matches.push((self.scanned_to, self.lines_scanned));
+            }
+            self.scanned_to += n as u64;
+        }
+        Ok(self.scanned_to - start)
+    }
+}
+
+// Read one line including its line feed, keeping at most MAX_LINE_BYTES of it.
+// Returns the length of the whole line in the file.
+fn read_line_bytes(reader: &mut SourceReader, encoding: Encoding, bytes: &mut Vec<u8>) -> io::Result<usize> {
+    bytes.clear();
+    if encoding.unit_size() == 1 {
+        let mut n = 0;
+        loop {
+            let buf = reader.fill_buf()?;
+            if buf.is_empty() {
+                return Ok(n);
+            }
+            let (len, found) = match buf.iter().position(|&b| b == b'\n') {
+                Some(i) => (i + 1, true),
+                None => (buf.len(), false),
+            };
+            let keep = len.min(MAX_LINE_BYTES.saturating_sub(bytes.len()));
+            bytes.extend_from_slice(&buf[..keep]);
+            reader.consume(len);
+            n += len;
+            if found {
+                return Ok(n);
+            }
+        }
+    } else {
+        let newline = encoding.newline();
+        let mut n = 0;
+        loop {
+            let mut unit = [0u8; 2];
+            let got = reader.read(&mut unit[..1])? + reader.read(&mut unit[1..])?;
+            if got < 2 {
+                // Odd byte at the end of the file
+                bytes.extend_from_slice(&unit[..got]);
+                return Ok(n + got);
+            }
+            n += 2;
+            if bytes.len() < MAX_LINE_BYTES {
+                bytes.extend_from_slice(&unit);
+            }
+            if unit == newline {
+                return Ok(n);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::FileSource;
+    use std::io::{BufReader, Write};
+    use tempfile::NamedTempFile;
+
+    fn open(contents: &[u8]) -> (NamedTempFile, SourceReader) {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(contents).unwrap();
+        let source = FileSource::open(std::ptr::null_mut(), file.path()).unwrap();
+        (file, BufReader::new(Box::new(source)))
+    }
+
+    // Line numbers of the lines the filter keeps, scanning the whole file
+    fn kept(input: &str, contents: &[u8], encoding: Encoding) -> Vec<u64> {
+        let (_file, mut reader) = open(contents);
+        let mut filter = Filter::parse(input).unwrap();
+        filter.scan(&mut reader, encoding, usize::MAX, u64::MAX).unwrap();
+        assert!(filter.is_done());
+        filter.matches().iter().map(|&(_, line)| line).collect()
+    }
+
+    #[test]
+    fn parse() {
+        assert!(matches!(Filter::parse("abc").unwrap().pattern, Pattern::Text(ref t) if t == "abc"));
+        assert!(matches!(Filter::parse("/a.c/").unwrap().pattern, Pattern::Regex(_)));
+        // Only a closing slash makes a regex
+        assert!(matches!(Filter::parse("/abc").unwrap().pattern, Pattern::Text(ref t) if t == "/abc"));
+        let filter = Filter::parse("!abc").unwrap();
+        assert!(filter.invert);
+        assert_eq!(filter.description(), "!abc");
+        assert!(Filter::parse("").is_err());
+        assert!(Filter::parse("!").is_err());
+        assert!(Filter::parse("/(/").is_err());
+    }
+
+    #[test]
+    fn matches_and_line_numbers() {
+        let contents = b"apple\nbanana\ncherry\napricot";
+        assert_eq!(kept("ap", contents, Encoding::Utf8), [1, 4]);
+        assert_eq!(kept("/^.a/", contents, Encoding::Utf8), [2]);
+        assert_eq!(kept("!an", contents, Encoding::Utf8), [1, 3, 4]);
+        let (_file, mut reader) = open(contents);
+        let mut filter = Filter::parse("a").unwrap();
+        filter.scan(&mut reader, Encoding::Utf8, 10, u64::MAX).unwrap();
+        assert_eq!(filter.matches(), [(0, 1), (6, 2), (20, 4)]);
+        assert_eq!(filter.scanned_to(), contents.len() as u64);
+    }
+
+    #[test]
+    fn bom_and_line_endings() {
+        // The BOM isn't part of the first line, nor the CR of a CRLF
+        let contents = b"\xef\xbb\xbfone\r\ntwo\r\n";
+        assert_eq!(kept("/^one$/", contents, Encoding::Utf8), [1]);
+        assert_eq!(kept("/o$/", contents, Encoding::Utf8), [2]);
+        let utf16: Vec<u8> = "\u{feff}one\r\ntwo\r\n".encode_utf16().flat_map(|u| u.to_le_bytes()).collect();
+        assert_eq!(kept("/^one$/", &utf16, Encoding::Utf16Le), [1]);
+        assert_eq!(kept("/^two$/", &utf16, Encoding::Utf16Le), [2]);
+    }
+
+    #[test]
+    fn wanted_and_budget() {
+        let contents = b"a1\nb\na2\nb\na3\n";
+        let (_file, mut reader) = open(contents);
+        let mut filter = Filter::parse("a").unwrap();
+        // Stops as soon as there are enough matches
+        assert_eq!(filter.scan(&mut reader, Encoding::Utf8, 2, u64::MAX).unwrap(), 8);
+        assert_eq!(filter.matches().len(), 2);
+        assert!(!filter.is_done());
+        // A budget of one byte still tests a whole line
+        assert_eq!(filter.scan(&mut reader, Encoding::Utf8, 10, 1).unwrap(), 2);
+        assert_eq!(filter.matches().len(), 2);
+        filter.scan(&mut reader, Encoding::Utf8, 10, u64::MAX).unwrap();
+        assert_eq!(filter.matches(), [(0, 1), (5, 3), (10, 5)]);
+        assert!(filter.is_done());
+        // Nothing more once done
+        assert_eq!(filter.scan(&mut reader, Encoding::Utf8, 10, u64::MAX).unwrap(), 0);
+    }
+}
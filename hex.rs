@@ -0,0 +1,94 @@
+// Bytes shown in hex and as characters, one row at a time:
+//   00001230  48 65 6c 6c 6f 0a 00 ff  Hello...
+// Shared by the binary comparison and the hex editor.
+
+use ncurses::*;
+use std::fs::File;
+use std::io;
+use std::os::unix::fs::FileExt;
+
+// Where the parts of a row go: the offset, the bytes in hex, the bytes as characters
+#[derive(Clone, Copy)]
+pub struct Layout {
+    offset_digits: usize, // Hex digits of the offset, 8 or more to fit the file's last offset
+    pub per_row: usize, // Bytes in a row: a multiple of 4, from 4 to 16
+}
+
+impl Layout {
+    // The layout for a file of `size` bytes in a window `width` columns wide
+    pub fn new(size: u64, width: usize) -> Self {
+        let last = size.saturating_sub(1);
+        let offset_digits = ((64 - last.leading_zeros() as usize).div_ceil(4)).max(8);
+        // The offset, with two spaces after it
+        let n = width.saturating_sub(offset_digits + 3) / 4;
+        Layout { offset_digits, per_row: (n / 4 * 4).clamp(4, 16) }
+    }
+
+    // Column of byte `i` of a row in the hex part
+    pub fn hex_col(&self, i: usize) -> usize {
+        self.offset_digits + 2 + i * 3
+    }
+
+    // Column of byte `i` of a row in the character part
+    pub fn char_col(&self, i: usize) -> usize {
+        self.hex_col(self.per_row) + 1 + i
+    }
+}
+
+// Printable ASCII as it is, anything else as '.'
+fn shown_char(b: u8) -> char {
+    if (0x20..0x7f).contains(&b) { b as char } else { '.' }
+}
+
+// Draw a row of bytes from `offset`. `bytes` is shorter than a row at the end of the file.
+// `attrs` gives the attributes of each byte, to highlight some of them.
+pub fn draw_row(window: WINDOW, y: i32, offset: u64, bytes: &[u8], layout: Layout, attrs: impl Fn(usize) -> attr_t) {
+    wattrset(window, COLOR_PAIR(2));
+    let _ = mvwaddstr(window, y, 0, &format!("{:0width$x}", offset, width = layout.offset_digits));
+    for (i, &b) in bytes.iter().enumerate().take(layout.per_row) {
+        wattrset(window, attrs(i));
+        let _ = mvwaddstr(window, y, layout.hex_col(i) as i32, &format!("{:02x}", b));
+        let _ = mvwaddstr(window, y, layout.char_col(i) as i32, &shown_char(b).to_string());
+    }
+    wattrset(window, COLOR_PAIR(1));
+}
+
+// Read up to `len` bytes at `offset`, fewer at the end of the file
+pub fn read_range(file: &File, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+    let mut bytes = vec![0u8; len];
+    let mut n = 0;
+    while n < len {
+        match file.read_at(&mut bytes[n..], offset + n as u64)? {
+            0 => break,
+            k => n += k,
+        }
+    }
+    bytes.truncate(n);
+    Ok(bytes)
+}
+
+// Parse an offset in decimal or 0x hex
+pub fn parse_offset(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+    .map_err(|_| format!("Not an offset: '{}'", s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offsets_fit_the_file() {
+        let small = Layout::new(1000, 80);
+        assert_eq!((small.per_row, small.hex_col(0), small.char_col(0)), (16, 10, 59));
+        assert_eq!(Layout::new(1 << 32, 80).hex_col(0), 10);
+        let large = Layout::new((1 << 32) + 1, 80);
+        assert_eq!((large.per_row, large.hex_col(0)), (16, 11));
+        assert_eq!(Layout::new(u64::MAX, 80).hex_col(0), 18);
+        assert_eq!(Layout::new(1000, 20).per_row, 4);
+    }
+}
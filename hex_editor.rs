@@ -0,0 +1,344 @@
+// Hex editor (Shift-F4, or F4 on a binary file): bytes are overwritten in
+// hex or as characters. Changes are kept aside and highlighted until they are
+// saved, and saving writes only the changed bytes, in place, so files of any
+// size can be edited. The file's size never changes.
+
+use crate::ansi::Style;
+use crate::hex;
+use crate::modal_dialog;
+use crate::viewer::{draw_bottom_line, new_frame, resize_frame};
+use ncurses::*;
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+
+struct HexEditor {
+    superwindow: WINDOW, // Border, title and status line
+    window: WINDOW, // Rows of bytes
+    file: File,
+    read_only: bool, // The file couldn't be opened for writing
+    title: String,
+    size: u64,
+    changes: BTreeMap<u64, u8>, // New bytes by offset, not saved yet
+    undo: Vec<(u64, Option<u8>)>, // Offset and its change before each edit
+    cursor: u64, // Offset of the byte being edited
+    low_nibble: bool, // The high hex digit of the byte at the cursor was just typed
+    in_chars: bool, // Typing characters rather than hex digits
+    top: u64, // Offset of the first visible row
+}
+
+impl HexEditor {
+    fn layout(&self) -> hex::Layout {
+        hex::Layout::new(self.size, getmaxx(self.window) as usize)
+    }
+
+    fn per_row(&self) -> u64 {
+        self.layout().per_row as u64
+    }
+
+    fn rows(&self) -> u64 {
+        getmaxy(self.window).max(1) as u64
+    }
+
+    // Put the cursor at `offset`, at most the last byte
+    fn move_to(&mut self, offset: u64) {
+        self.cursor = offset.min(self.size.saturating_sub(1));
+        self.low_nibble = false;
+    }
+
+    fn move_by(&mut self, delta: i64) {
+        self.move_to(self.cursor.saturating_add_signed(delta));
+    }
+
+    // Scroll so the cursor is on screen
+    fn scroll_into_view(&mut self) {
+        let per_row = self.per_row();
+        let row = self.cursor / per_row * per_row;
+        if row < self.top {
+            self.top = row;
+        } else if row >= self.top + self.rows() * per_row {
+            self.top = row + per_row - self.rows() * per_row;
+        }
+        self.top = self.top / per_row * per_row;
+    }
+
+    // Change the byte at the cursor. `joins` makes it part of the previous edit.
+    fn set_byte(&mut self, value: u8, joins: bool) {
+        if !joins {
+            self.undo.push((self.cursor, self.changes.get(&self.cursor).copied()));
+        }
+        self.changes.insert(self.cursor, value);
+    }
+
+    // The byte at the cursor as it is now
+    fn byte_at_cursor(&self) -> io::Result<u8> {
+        if let Some(&b) = self.changes.get(&self.cursor) {
+            return Ok(b);
+        }
+        let bytes = hex::read_range(&self.file, self.cursor, 1)?;
+        bytes.first().copied().ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))
+    }
+
+    fn switch_column(&mut self) {
+        self.in_chars = !self.in_chars;
+        self.low_nibble = false;
+    }
+
+    // Act on a typed character: a hex digit, or in the character column the
+    // character's UTF-8 bytes, one after another
+    fn type_char(&mut self, c: char) {
+        if self.read_only || self.size == 0 {
+            beep();
+            return;
+        }
+        if self.in_chars {
+            let mut buf = [0u8; 4];
+            let bytes = c.encode_utf8(&mut buf).as_bytes();
+            // The file doesn't grow
+            if self.cursor + bytes.len() as u64 > self.size {
+                beep();
+                return;
+            }
+            for &b in bytes {
+                self.set_byte(b, false);
+                self.move_by(1);
+            }
+            return;
+        }
+        let (Some(digit), Ok(old)) = (c.to_digit(16), self.byte_at_cursor()) else {
+            beep();
+            return;
+        };
+        if self.low_nibble {
+            self.set_byte(old & 0xf0 | digit as u8, true);
+            self.move_by(1);
+        } else {
+            self.set_byte((digit as u8) << 4 | old & 0x0f, false);
+            self.low_nibble = true;
+        }
+    }
+
+    // Take back the last byte edit
+    fn undo(&mut self) {
+        let Some((offset, previous)) = self.undo.pop() else {
+            beep();
+            return;
+        };
+        match previous {
+            Some(b) => self.changes.insert(offset, b),
+            None => self.changes.remove(&offset),
+        };
+        self.move_to(offset);
+    }
+
+    // Write the changed bytes, each run of adjacent ones at once.
+    // Returns false if that failed.
+    fn save(&mut self) -> bool {
+        let mut runs: Vec<(u64, Vec<u8>)> = Vec::new();
+        for (&offset, &b) in &self.changes {
+            match runs.last_mut() {
+                Some((start, bytes)) if *start + bytes.len() as u64 == offset => bytes.push(b),
+                _ => runs.push((offset, vec![b])),
+            }
+        }
+        let written = runs.iter().try_for_each(|(offset, bytes)| self.file.write_all_at(bytes, *offset))
+            .and_then(|_| self.file.sync_data());
+        match written {
+            Ok(()) => {
+                self.changes.clear();
+                // The saved bytes are the file's now
+                self.undo.clear();
+                true
+            }
+            Err(e) => {
+                modal_dialog::message(self.window, "Save", &e.to_string());
+                false
+            }
+        }
+    }
+
+    // Ask for an offset to go to
+    fn prompt_offset(&mut self) {
+        curs_set(CURSOR_VISIBILITY::CURSOR_INVISIBLE);
+        let input = modal_dialog::prompt(self.window, "Go to offset (decimal or 0x hex)", "");
+        let Some(input) = input else {
+            return;
+        };
+        match hex::parse_offset(&input) {
+            Ok(offset) if offset < self.size => self.move_to(offset),
+            Ok(_) => modal_dialog::message(self.window, "Go to", &format!("The file is {} bytes", self.size)),
+            Err(e) => modal_dialog::message(self.window, "Go to", &e),
+        }
+    }
+
+    // Whether to close, after asking what to do with unsaved changes
+    fn confirm_close(&mut self) -> bool {
+        if self.changes.is_empty() {
+            return true;
+        }
+        curs_set(CURSOR_VISIBILITY::CURSOR_INVISIBLE);
+        match modal_dialog::choose(self.window, "Save changes?", &["Save", "Discard changes", "Cancel"], 0) {
+            Some(0) => self.save(),
+            Some(1) => true,
+            _ => false,
+        }
+    }
+
+    fn draw_frame(&self) {
+        box_(self.superwindow, 0, 0);
+        let _ = mvwaddnstr(self.superwindow, 0, 2, &format!(" {} ", self.title), getmaxx(self.superwindow) - 4);
+        let mut status = format!(" Offset {:08x} ({}) of {}  {} ", self.cursor, self.cursor, self.size,
+            if self.in_chars { "Text" } else { "Hex" });
+        if !self.changes.is_empty() {
+            status.push_str(&format!(" {} bytes changed ", self.changes.len()));
+        }
+        if self.read_only {
+            status.push_str(" Read-only ");
+        }
+        draw_bottom_line(self.superwindow, &status, " Tab hex/text  ^G go to  ^Z undo  ^S save  Esc close ");
+    }
+
+    fn draw(&mut self) {
+        self.scroll_into_view();
+        self.draw_frame();
+        werase(self.window);
+        let layout = self.layout();
+        let per_row = layout.per_row;
+        let mut bytes = hex::read_range(&self.file, self.top, per_row * self.rows() as usize).unwrap_or_default();
+        for (&offset, &b) in self.changes.range(self.top..self.top + bytes.len() as u64) {
+            bytes[(offset - self.top) as usize] = b;
+        }
+        let changed = Style::colored(COLOR_YELLOW, true).on(COLOR_RED).attrs();
+        for (row, row_bytes) in bytes.chunks(per_row).enumerate() {
+            let offset = self.top + (row * per_row) as u64;
+            hex::draw_row(self.window, row as i32, offset, row_bytes, layout, |i| {
+                let offset = offset + i as u64;
+                let attrs = if self.changes.contains_key(&offset) { changed } else { COLOR_PAIR(1) };
+                if offset == self.cursor { attrs | A_REVERSE } else { attrs }
+            });
+        }
+        // The terminal's cursor on the digit or character being typed
+        let i = ((self.cursor - self.top) % per_row as u64) as usize;
+        let y = ((self.cursor - self.top) / per_row as u64) as i32;
+        let x = match self.in_chars {
+            true => layout.char_col(i),
+            false => layout.hex_col(i) + self.low_nibble as usize,
+        };
+        wmove(self.window, y, x as i32);
+        curs_set(CURSOR_VISIBILITY::CURSOR_VISIBLE);
+        wrefresh(self.window);
+    }
+
+    // Act on a key. Returns true when the user wants to close the editor.
+    fn handle_key(&mut self, w_debug: WINDOW, key: WchResult) -> bool {
+        let per_row = self.per_row();
+        let row_start = self.cursor / per_row * per_row;
+        let page = (per_row * (self.rows() - 1).max(1)) as i64;
+        match key {
+            WchResult::KeyCode(code) => match code {
+                KEY_LEFT => self.move_by(-1),
+                KEY_RIGHT => self.move_by(1),
+                KEY_UP => self.move_by(-(per_row as i64)),
+                KEY_DOWN => self.move_by(per_row as i64),
+                KEY_PPAGE => self.move_by(-page),
+                KEY_NPAGE => self.move_by(page),
+                // Home and End go to either end of the row
+                KEY_HOME => self.move_to(row_start),
+                KEY_END => self.move_to(row_start + per_row - 1),
+                KEY_BTAB => self.switch_column(),
+                KEY_RESIZE => resize_frame(w_debug, self.superwindow, self.window),
+                // F2 saves, F10 closes
+                _ if code == KEY_F(2) => {
+                    self.save();
+                }
+                _ if code == KEY_F(10) => return true,
+                // Ctrl-Home and Ctrl-End go to either end of the file
+                _ => match keyname(code).as_deref() {
+                    Some("kHOM5") => self.move_to(0),
+                    Some("kEND5") => self.move_to(u64::MAX),
+                    _ => {}
+                },
+            },
+            WchResult::Char(c) => match c {
+                // Tab switches between the hex digits and the characters
+                9 => self.switch_column(),
+                // Ctrl-G goes to an offset
+                7 => self.prompt_offset(),
+                // Ctrl-S saves
+                19 => {
+                    self.save();
+                }
+                // Ctrl-Z undoes the last byte edit
+                26 => self.undo(),
+                // Backspace goes back a byte
+                8 | 127 => self.move_by(-1),
+                // Escape closes
+                27 => return true,
+                _ => match char::from_u32(c) {
+                    Some(c) if !c.is_control() => self.type_char(c),
+                    _ => {}
+                },
+            },
+        }
+        false
+    }
+}
+
+impl Drop for HexEditor {
+    fn drop(&mut self) {
+        delwin(self.window);
+        delwin(self.superwindow);
+    }
+}
+
+// Edit a file's bytes until the user closes the editor
+pub fn edit_file_modal(w_debug: WINDOW, path: &Path) {
+    // Read-only files can still be looked at
+    let opened = OpenOptions::new().read(true).write(true).open(path).map(|f| (f, false))
+        .or_else(|_| File::open(path).map(|f| (f, true)))
+        .and_then(|(file, read_only)| Ok((file.metadata()?, file, read_only)));
+    let (metadata, file, read_only) = match opened {
+        Ok(opened) => opened,
+        Err(e) => {
+            modal_dialog::message(stdscr(), "Hex edit", &format!("{}: {}", path.display(), e));
+            return;
+        }
+    };
+    if !metadata.is_file() {
+        modal_dialog::message(stdscr(), "Hex edit", &format!("{} is not a regular file", path.display()));
+        return;
+    }
+    let _ = waddstr(w_debug, &format!("HEXEDIT {}\n", path.display()));
+    wrefresh(w_debug);
+    let (superwindow, window) = new_frame();
+    let mut editor = HexEditor {
+        superwindow,
+        window,
+        file,
+        read_only,
+        title: path.display().to_string(),
+        size: metadata.len(),
+        changes: BTreeMap::new(),
+        undo: Vec::new(),
+        cursor: 0,
+        low_nibble: false,
+        in_chars: false,
+        top: 0,
+    };
+    // Raw mode, so Ctrl-S and Ctrl-Z arrive as keys
+    raw();
+    loop {
+        editor.draw();
+        let Some(key) = wget_wch(editor.window) else {
+            continue;
+        };
+        if editor.handle_key(w_debug, key) && editor.confirm_close() {
+            break;
+        }
+    }
+    noraw();
+    cbreak();
+    curs_set(CURSOR_VISIBILITY::CURSOR_INVISIBLE);
+}
@@ -0,0 +1,273 @@
+// JSON shown pretty-printed, with objects and arrays that fold away.
+// The document is flattened into nodes in reading order; a container's
+// children follow it, up to its `end`.
+
+use crate::ansi::Style;
+use crate::display_line::draw_styled;
+use crate::structured::{ListCursor, StructuredView};
+use crate::syntax::Kind;
+use ncurses::*;
+use serde_json::Value;
+
+// Indent per nesting level, in columns
+const INDENT: usize = 2;
+
+enum NodeValue {
+    Scalar(Kind, String), // Already formatted as JSON
+    Array(usize), // Number of elements
+    Object(usize), // Number of members
+}
+
+struct Node {
+    depth: usize,
+    key: Option<String>, // Member name inside an object
+    value: NodeValue,
+    end: usize, // Index after the last node inside this one
+    last: bool, // Last element or member of its container: no comma
+    collapsed: bool,
+}
+
+// A visible row: the node, or the closing bracket of an expanded container
+#[derive(Clone, Copy, PartialEq)]
+struct Row {
+    node: usize,
+    closing: bool,
+}
+
+pub struct JsonTree {
+    nodes: Vec<Node>,
+    rows: Vec<Row>,
+    cursor: ListCursor,
+}
+
+impl JsonTree {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let value: Value = serde_json::from_str(text).map_err(|e| e.to_string())?;
+        let mut tree = JsonTree {
+            nodes: Vec::new(),
+            rows: Vec::new(),
+            cursor: ListCursor::default(),
+        };
+        tree.add(&value, None, 0, true);
+        tree.update_rows();
+        Ok(tree)
+    }
+
+    // Append a value and everything inside it
+    fn add(&mut self, value: &Value, key: Option<String>, depth: usize, last: bool) {
+        let index = self.nodes.len();
+        let node_value = match value {
+            Value::Null => NodeValue::Scalar(Kind::Keyword, "null".to_string()),
+            Value::Bool(b) => NodeValue::Scalar(Kind::Keyword, b.to_string()),
+            Value::Number(n) => NodeValue::Scalar(Kind::Number, n.to_string()),
+            Value::String(_) => NodeValue::Scalar(Kind::Str, value.to_string()),
+            Value::Array(a) => NodeValue::Array(a.len()),
+            Value::Object(o) => NodeValue::Object(o.len()),
+        };
+        self.nodes.push(Node { depth, key, value: node_value, end: 0, last, collapsed: false });
+        match value {
+            Value::Array(a) => {
+                for (i, v) in a.iter().enumerate() {
+                    self.add(v, None, depth + 1, i + 1 == a.len());
+                }
+            }
+            Value::Object(o) => {
+                for (i, (k, v)) in o.iter().enumerate() {
+                    self.add(v, Some(k.clone()), depth + 1, i + 1 == o.len());
+                }
+            }
+            _ => {}
+        }
+        self.nodes[index].end = self.nodes.len();
+    }
+
+    fn is_container(&self, i: usize) -> bool {
+        !matches!(self.nodes[i].value, NodeValue::Scalar(..))
+    }
+
+    // List the rows that are not folded away, keeping the same row selected
+    fn update_rows(&mut self) {
+        let selected = self.rows.get(self.cursor.selected).copied();
+        self.rows.clear();
+        // Containers still waiting for their closing bracket
+        let mut open: Vec<usize> = Vec::new();
+        let mut i = 0;
+        while i < self.nodes.len() {
+            while let Some(&c) = open.last() && self.nodes[c].end <= i {
+                self.rows.push(Row { node: c, closing: true });
+                open.pop();
+            }
+            self.rows.push(Row { node: i, closing: false });
+            let has_children = self.nodes[i].end > i + 1;
+            if has_children && self.nodes[i].collapsed {
+                i = self.nodes[i].end;
+            } else {
+                // Empty containers open and close on one row
+                if has_children {
+                    open.push(i);
+                }
+                i += 1;
+            }
+        }
+        while let Some(c) = open.pop() {
+            self.rows.push(Row { node: c, closing: true });
+        }
+        if let Some(row) = selected {
+            // A closing row that disappeared is replaced by its opening row
+            let row = Row { closing: row.closing && !self.nodes[row.node].collapsed, ..row };
+            self.cursor.selected = self.rows.iter().position(|r| *r == row).unwrap_or(0);
+        }
+    }
+
+    // Fold or unfold the container at the selected row
+    fn set_collapsed(&mut self, collapsed: bool) {
+        let Some(row) = self.rows.get(self.cursor.selected).copied() else {
+            return;
+        };
+        if self.is_container(row.node) && self.nodes[row.node].collapsed != collapsed {
+            self.nodes[row.node].collapsed = collapsed;
+            if collapsed {
+                self.cursor.selected = self.rows.iter().position(|r| r.node == row.node).unwrap_or(0);
+            }
+            self.update_rows();
+        } else if collapsed && row.node > 0 {
+            // Already folded or not a container: go to the enclosing container
+            let depth = self.nodes[row.node].depth;
+            let parent = (0..row.node).rev().find(|&i| self.nodes[i].depth < depth).unwrap_or(0);
+            self.cursor.selected = self.rows.iter().position(|r| r.node == parent).unwrap_or(0);
+        }
+    }
+
+    // Fold or unfold every container below the top level
+    fn set_all_collapsed(&mut self, collapsed: bool) {
+        for node in self.nodes.iter_mut().skip(1) {
+            node.collapsed = collapsed;
+        }
+        self.cursor.selected = 0;
+        self.update_rows();
+    }
+
+    // The text of a row, in pieces to colour
+    fn row_pieces(&self, row: Row) -> Vec<(Style, String)> {
+        let node = &self.nodes[row.node];
+        let mut pieces = vec![(Style::default(), " ".repeat(node.depth * INDENT))];
+        let comma = if node.last { "" } else { "," };
+        if row.closing {
+            let bracket = if matches!(node.value, NodeValue::Array(_)) { "]" } else { "}" };
+            pieces.push((Style::default(), format!("{}{}", bracket, comma)));
+            return pieces;
+        }
+        if let Some(key) = &node.key {
+            pieces.push((Kind::Key.style(), Value::String(key.clone()).to_string()));
+            pieces.push((Style::default(), ": ".to_string()));
+        }
+        match &node.value {
+            NodeValue::Scalar(kind, text) => {
+                pieces.push((kind.style(), text.clone()));
+                pieces.push((Style::default(), comma.to_string()));
+            }
+            NodeValue::Array(n) | NodeValue::Object(n) => {
+                let (open, close, unit) = match node.value {
+                    NodeValue::Array(_) => ("[", "]", if *n == 1 { "item" } else { "items" }),
+                    _ => ("{", "}", if *n == 1 { "key" } else { "keys" }),
+                };
+                if *n == 0 {
+                    pieces.push((Style::default(), format!("{}{}{}", open, close, comma)));
+                } else if node.collapsed {
+                    pieces.push((Style::default(), format!("{} … {}{}", open, close, comma)));
+                    pieces.push((Kind::Comment.style(), format!("  {} {}", n, unit)));
+                } else {
+                    pieces.push((Style::default(), open.to_string()));
+                }
+            }
+        }
+        pieces
+    }
+}
+
+impl StructuredView for JsonTree {
+    fn draw(&mut self, window: WINDOW, tab_width: usize) {
+        let height = getmaxy(window) as usize;
+        let width = getmaxx(window) as usize;
+        self.cursor.scroll_into_view(self.rows.len(), height);
+        for (screen_row, i) in (self.cursor.top..self.rows.len()).take(height).enumerate() {
+            let selected = i == self.cursor.selected;
+            let extra = if selected { A_REVERSE } else { 0 };
+            let used = draw_styled(window, screen_row as i32, 0, width, tab_width, self.row_pieces(self.rows[i]), extra);
+            if selected {
+                // Highlight the whole width
+                wattron(window, A_REVERSE);
+                let _ = waddstr(window, &" ".repeat(width - used));
+                wattroff(window, A_REVERSE);
+            }
+        }
+    }
+
+    fn status(&self) -> String {
+        let depth = self.rows.get(self.cursor.selected).map_or(0, |r| self.nodes[r.node].depth);
+        format!(" Row {}/{}  Depth {}  JSON tree ", self.cursor.selected + 1, self.rows.len(), depth)
+    }
+
+    fn handle_key(&mut self, ch: i32, page_rows: usize) {
+        if self.cursor.handle_key(ch, self.rows.len(), page_rows) {
+            return;
+        }
+        match ch {
+            KEY_LEFT => self.set_collapsed(true),
+            KEY_RIGHT => self.set_collapsed(false),
+            // Enter or space to fold or unfold
+            KEY_ENTER | 10 | 13 | 32 => {
+                if let Some(row) = self.rows.get(self.cursor.selected).copied() {
+                    self.set_collapsed(!self.nodes[row.node].collapsed);
+                }
+            }
+            // '-' folds everything, '+' unfolds everything
+            45 => self.set_all_collapsed(true),
+            43 => self.set_all_collapsed(false),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The visible rows as text
+    fn lines(tree: &JsonTree) -> Vec<String> {
+        tree.rows.iter().map(|&row| tree.row_pieces(row).into_iter().map(|(_, s)| s).collect()).collect()
+    }
+
+    #[test]
+    fn flattened() {
+        let cases: &[(&str, &[&str])] = &[
+            ("1", &["1"]),
+            ("[]", &["[]"]),
+            ("[1, \"a\", null]", &["[", "  1,", "  \"a\",", "  null", "]"]),
+            ("{\"a\": {}, \"b\": [true]}", &["{", "  \"a\": {},", "  \"b\": [", "    true", "  ]", "}"]),
+            ("[[[]]]", &["[", "  [", "    []", "  ]", "]"]),
+        ];
+        for &(text, expected) in cases {
+            assert_eq!(lines(&JsonTree::parse(text).unwrap()), expected, "{}", text);
+        }
+        assert!(JsonTree::parse("{").is_err());
+    }
+
+    #[test]
+    fn folding() {
+        let mut tree = JsonTree::parse("{\"a\": [1, 2], \"b\": {\"c\": 3}}").unwrap();
+        assert_eq!(tree.nodes.len(), 6);
+        assert_eq!(tree.nodes[1].end, 4);
+        tree.set_all_collapsed(true);
+        assert_eq!(lines(&tree), ["{", "  \"a\": [ … ],  2 items", "  \"b\": { … }  1 key", "}"]);
+        // Unfold "b", then fold it again from inside
+        tree.cursor.selected = 2;
+        tree.set_collapsed(false);
+        assert_eq!(lines(&tree)[3], "    \"c\": 3");
+        tree.cursor.selected = 3;
+        tree.set_collapsed(true);
+        assert_eq!(tree.cursor.selected, 2);
+        tree.set_all_collapsed(false);
+        assert_eq!(tree.rows.len(), 9);
+    }
+}
@@ -0,0 +1,220 @@
+// Line numbers for file offsets.
+// The file is scanned for line feeds only as far as needed, remembering where
+// every CHECKPOINT_LINES-th line starts, so later lookups only rescan from the
+// nearest checkpoint.
+
+use crate::encoding::Encoding;
+use crate::source::SourceReader;
+use std::io::{self, Read, Seek, SeekFrom};
+
+// Distance between remembered line starts, in lines
+const CHECKPOINT_LINES: u64 = 1024;
+
+// Bytes read at a time while scanning
+const SCAN_CHUNK: usize = 64 * 1024;
+
+pub struct LineIndex {
+    newline: &'static [u8], // Line feed in the file's encoding
+    checkpoints: Vec<u64>, // Start of line 1 + i * CHECKPOINT_LINES
+    scanned_to: u64, // Checkpoints are complete up to this offset
+    lines_before: u64, // Line feeds before scanned_to
+    cursor: (u64, u64), // Last lookup: offset, line feeds before it
+}
+
+impl LineIndex {
+    pub fn new(encoding: Encoding) -> Self {
+        LineIndex {
+            newline: encoding.newline(),
+            checkpoints: vec![0],
+            scanned_to: 0,
+            lines_before: 0,
+            cursor: (0, 0),
+        }
+    }
+
+    // Number of lines found so far
+    pub fn lines_known(&self) -> u64 {
+        self.lines_before + 1
+    }
+
+    // Whether line_at is quick for `offset`: the file has been scanned that far
+    pub fn knows(&self, offset: u64) -> bool {
+        offset <= self.scanned_to
+    }
+
+    // Scan at most `budget` more bytes towards `to`, so a far offset can be
+    // reached a step at a time. Returns true once it is reached, or the end of the file.
+    pub fn scan_towards(&mut self, reader: &mut SourceReader, to: u64, budget: u64) -> io::Result<bool> {
+        let from = self.scanned_to;
+        if to > from {
+            self.extend(reader, to.min(from + budget))?;
+        }
+        Ok(self.scanned_to >= to || self.scanned_to < (from + budget).min(to))
+    }
+
+    // Line number (1-based) of the line containing `offset`
+    pub fn line_at(&mut self, reader: &mut SourceReader, offset: u64) -> io::Result<u64> {
+        if offset > self.scanned_to {
+            self.extend(reader, offset)?;
+            return Ok(self.lines_before + 1);
+        }
+
+        // Rescan from the nearest known position before the offset
+        let i = self.checkpoints.partition_point(|&cp| cp <= offset) - 1;
+        let (mut from, mut lines) = (self.checkpoints[i], i as u64 * CHECKPOINT_LINES);
+        if self.cursor.0 <= offset && self.cursor.0 > from {
+            (from, lines) = self.cursor;
+        }
+        let newline = self.newline;
+        scan(reader, newline, from, offset, |_| lines += 1)?;
+        self.cursor = (offset, lines);
+        Ok(lines + 1)
+    }
+
+    // Scan forward from scanned_to, adding checkpoints
+    fn extend(&mut self, reader: &mut SourceReader, to: u64) -> io::Result<()> {
+        let (checkpoints, lines_before) = (&mut self.checkpoints, &mut self.lines_before);
+        let end = scan(reader, self.newline, self.scanned_to, to, |line_start| {
+            *lines_before += 1;
+            if *lines_before % CHECKPOINT_LINES == 0 {
+                checkpoints.push(line_start);
+            }
+        })?;
+        self.scanned_to = end;
+        self.cursor = (end, self.lines_before);
+        Ok(())
+    }
+}
+
+// Call `on_line` with the offset after each line feed between `from` and `to`.
+// Returns where the scan stopped: `to`, or the end of the file.
+fn scan(reader: &mut SourceReader, newline: &[u8], from: u64, to: u64, mut on_line: impl FnMut(u64)) -> io::Result<u64> {
+    let unit = newline.len();
+    let mut pos = from;
+    let mut buf = vec![0u8; SCAN_CHUNK];
+    reader.seek(SeekFrom::Start(from))?;
+    while pos < to {
+        let want = ((to - pos) as usize).min(SCAN_CHUNK);
+        let n = reader.read(&mut buf[..want])?;
+        if n == 0 {
+            break; // EOF
+        }
+        // Keep whole code units, the rest is read again next time
+        let whole = n - n % unit;
+        if whole == 0 {
+            break;
+        }
+        if whole != n {
+            reader.seek(SeekFrom::Start(pos + whole as u64))?;
+        }
+        let n = whole;
+        if unit == 1 {
+            for (i, _) in buf[..n].iter().enumerate().filter(|&(_, &b)| b == newline[0]) {
+                on_line(pos + i as u64 + 1);
+            }
+        } else {
+            for (i, _) in buf[..n].chunks_exact(unit).enumerate().filter(|&(_, c)| c == newline) {
+                on_line(pos + ((i + 1) * unit) as u64);
+            }
+        }
+        pos += n as u64;
+    }
+    Ok(pos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::FileSource;
+    use std::io::{BufReader, Write};
+    use tempfile::NamedTempFile;
+
+    fn open(file: &NamedTempFile) -> SourceReader {
+        let source = FileSource::open(std::ptr::null_mut(), file.path()).unwrap();
+        BufReader::new(Box::new(source))
+    }
+
+    // Lines "0\n", "1\n", ... "n-1\n": line i starts at the sum of the lengths before it
+    fn numbered_lines(n: u64) -> (NamedTempFile, Vec<u64>) {
+        let mut file = NamedTempFile::new().unwrap();
+        let mut starts = Vec::new();
+        let mut offset = 0;
+        for i in 0..n {
+            starts.push(offset);
+            let line = format!("{}\n", i);
+            file.write_all(line.as_bytes()).unwrap();
+            offset += line.len() as u64;
+        }
+        (file, starts)
+    }
+
+    #[test]
+    fn around_a_checkpoint() {
+        let (file, starts) = numbered_lines(3 * CHECKPOINT_LINES);
+        let mut reader = open(&file);
+        let mut index = LineIndex::new(Encoding::Utf8);
+        let far = starts[2 * CHECKPOINT_LINES as usize + 5];
+        assert!(!index.knows(far));
+        assert_eq!(index.line_at(&mut reader, far).unwrap(), 2 * CHECKPOINT_LINES + 6);
+        assert!(index.knows(far));
+        assert_eq!(index.checkpoints.len(), 3);
+        // Both sides of a checkpoint, and the middle of a line, looked up backwards
+        for line in [CHECKPOINT_LINES + 1, CHECKPOINT_LINES, CHECKPOINT_LINES - 1, 1] {
+            assert_eq!(index.line_at(&mut reader, starts[line as usize]).unwrap(), line + 1);
+        }
+        assert_eq!(index.line_at(&mut reader, starts[CHECKPOINT_LINES as usize] + 1).unwrap(), CHECKPOINT_LINES + 1);
+        assert_eq!(index.line_at(&mut reader, 0).unwrap(), 1);
+    }
+
+    #[test]
+    fn counted_a_step_at_a_time() {
+        let (file, starts) = numbered_lines(2000);
+        let mut reader = open(&file);
+        let mut index = LineIndex::new(Encoding::Utf8);
+        let target = starts[1500];
+        assert!(!index.scan_towards(&mut reader, target, 1000).unwrap());
+        assert!(!index.knows(target));
+        while !index.scan_towards(&mut reader, target, 1000).unwrap() {}
+        assert!(index.knows(target));
+        assert_eq!(index.line_at(&mut reader, target).unwrap(), 1501);
+        assert_eq!(index.lines_known(), 1501);
+    }
+
+    #[test]
+    fn file_grows_after_indexing() {
+        let (mut file, starts) = numbered_lines(10);
+        let mut reader = open(&file);
+        let mut index = LineIndex::new(Encoding::Utf8);
+        let end = starts[9] + 2;
+        assert_eq!(index.line_at(&mut reader, end).unwrap(), 11);
+        file.write_all(b"more\nand more\n").unwrap();
+        assert_eq!(index.line_at(&mut reader, end + 5).unwrap(), 12);
+        assert_eq!(index.line_at(&mut reader, starts[3]).unwrap(), 4);
+        // Past the end of the file
+        assert!(index.scan_towards(&mut reader, end + 1000, 1 << 20).unwrap());
+        assert_eq!(index.lines_known(), 13);
+    }
+
+    #[test]
+    fn last_line_without_a_line_break() {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(b"one\ntwo").unwrap();
+        let mut reader = open(&file);
+        let mut index = LineIndex::new(Encoding::Utf8);
+        assert_eq!(index.line_at(&mut reader, 7).unwrap(), 2);
+        assert_eq!(index.line_at(&mut reader, 5).unwrap(), 2);
+        assert_eq!(index.line_at(&mut reader, 3).unwrap(), 1);
+        assert_eq!(index.lines_known(), 2);
+    }
+
+    #[test]
+    fn utf16_line_feeds() {
+        let mut file = NamedTempFile::new().unwrap();
+        // "a\n\u{a00}\nb" in UTF-16LE: the 0x0a byte of U+0A00 is not a line feed
+        file.write_all(b"a\0\n\0\0\x0a\n\0b\0").unwrap();
+        let mut reader = open(&file);
+        let mut index = LineIndex::new(Encoding::Utf16Le);
+        assert_eq!(index.line_at(&mut reader, 10).unwrap(), 3);
+        assert_eq!(index.line_at(&mut reader, 4).unwrap(), 2);
+    }
+}
@@ -6,7 +6,35 @@ extern crate ncurses;
 use ncurses::*;
 use std::fs;
 use std::io;
-//mod modal_dialog;
+use std::os::fd::{AsFd, AsRawFd};
+use associations::{Action, Associations};
+use settings::{Command, Settings, Sort};
+mod ansi;
+mod associations;
+mod bindiff;
+mod bindiff_view;
+mod clipboard;
+mod decompress;
+mod diff;
+mod diff_view;
+mod display_line;
+mod edit_buffer;
+mod editor;
+mod encoding;
+mod external;
+mod filter;
+mod hex;
+mod hex_editor;
+mod json_tree;
+mod line_index;
+mod modal_dialog;
+mod positions;
+mod settings;
+mod source;
+mod structured;
+mod syntax;
+mod table_view;
+mod user_menu;
 mod viewer;
 
 struct DirView {
@@ -15,6 +43,9 @@ struct DirView {
     scroll_offset: usize, // First visible entry index
     dirents: io::Result<Vec<DirListItem>>, // Directory entries
     path: std::path::PathBuf, // Path of the directory being viewed
+    marked: std::collections::BTreeSet<usize>, // Indexes of the marked entries
+    sort: Sort, // Order of the entries
+    show_hidden: bool, // Show entries whose name starts with '.'
     dirty: bool, // Needs redraw
 }
 
@@ -38,8 +69,8 @@ impl DirView {
             elts.push(DirListItem::ParentDir(parent.to_path_buf()));
         }
 
-        let foo = read_directory_contents(&self.path);
-        match foo {
+        let contents = read_directory_contents(&self.path, self.sort, self.show_hidden);
+        match contents {
             Ok(entries) => {
                 // Add real directory entries
                 // for entry in entries.drain(..) {
@@ -56,9 +87,32 @@ impl DirView {
 
         self.selected = 0;
         self.scroll_offset = 0;
+        self.marked.clear();
         self.dirty = true;
     }
 
+    // Reload after something else may have changed the directory, staying on the same entry
+    fn reload_keeping_selection(&mut self) {
+        let name = match self.dirents {
+            Ok(ref elements) => match elements.get(self.selected) {
+                Some(DirListItem::Entry(entry)) => Some(entry.file_name()),
+                _ => None,
+            },
+            Err(_) => None,
+        };
+        self.reload();
+        let Ok(ref elements) = self.dirents else {
+            return;
+        };
+        let index = elements.iter().position(|item| match item {
+            DirListItem::Entry(entry) => Some(entry.file_name()) == name,
+            DirListItem::ParentDir(_) => false,
+        });
+        if let Some(index) = index {
+            self.select(index);
+        }
+    }
+
     // Create a new DirView instance
     fn new(win_height: i32, win_width: i32, win_starty: i32, win_startx: i32, path: &std::path::Path) -> io::Result<Self> {
         // Throw if win_height or win_width is less than 3
@@ -76,6 +130,9 @@ impl DirView {
             scroll_offset: 0,
             dirents: Ok(Vec::new()), // Placeholder, will be loaded
             path: path.to_path_buf(),
+            marked: std::collections::BTreeSet::new(),
+            sort: settings::current().sort,
+            show_hidden: settings::current().show_hidden,
             dirty: true,
         };
         dirview.load(path); // Load directory contents before returning
@@ -90,24 +147,104 @@ impl DirView {
         self.dirty = true;
     }
 
+    // Move the cursor to an entry, scrolling to show it
+    fn select(&mut self, index: usize) {
+        let view_height = ((getmaxy(self.window) - 2) as usize).max(1); // Adjust for borders
+        self.selected = index;
+        if index < self.scroll_offset {
+            self.scroll_offset = index;
+        } else if index >= self.scroll_offset + view_height {
+            self.scroll_offset = index + 1 - view_height;
+        }
+        self.dirty = true;
+    }
+
+    // Mark or unmark the selected entry, and move to the next one
+    fn toggle_mark(&mut self, w_debug: WINDOW) {
+        let Ok(ref elements) = self.dirents else {
+            beep();
+            return;
+        };
+        if !matches!(elements.get(self.selected), Some(DirListItem::Entry(_))) {
+            beep(); // [..] can't be marked
+            return;
+        }
+        if !self.marked.remove(&self.selected) {
+            self.marked.insert(self.selected);
+        }
+        self.dirty = true;
+        if self.selected + 1 < elements.len() {
+            scroll_down(w_debug, self);
+        }
+    }
+
+    // The two files to compare: the two marked files, or the marked file and the one at the cursor
+    fn compare_pair(&self) -> Option<(std::path::PathBuf, std::path::PathBuf)> {
+        let Ok(ref elements) = self.dirents else {
+            return None;
+        };
+        let files = regular_files(elements);
+        let marked: Vec<_> = files.iter().filter(|(i, _)| self.marked.contains(i)).collect();
+        let at_cursor = files.iter().find(|(i, _)| *i == self.selected && !self.marked.contains(i));
+        match (marked.as_slice(), at_cursor) {
+            ([(_, left), (_, right)], _) => Some((left.clone(), right.clone())),
+            ([(_, left)], Some((_, right))) => Some((left.clone(), right.clone())),
+            _ => None,
+        }
+    }
+
+    // The entry at the cursor, unless it is [..]
+    fn selected_path(&self) -> Option<std::path::PathBuf> {
+        match self.dirents {
+            Ok(ref elements) => match elements.get(self.selected) {
+                Some(DirListItem::Entry(entry)) => Some(entry.path()),
+                _ => None,
+            },
+            Err(_) => None,
+        }
+    }
+
+    // The marked entries
+    fn marked_paths(&self) -> Vec<std::path::PathBuf> {
+        let Ok(ref elements) = self.dirents else {
+            return Vec::new();
+        };
+        self.marked.iter().filter_map(|&i| match elements.get(i) {
+            Some(DirListItem::Entry(entry)) => Some(entry.path()),
+            _ => None,
+        }).collect()
+    }
+
+    // The regular file at the cursor
+    fn selected_file(&self) -> Option<std::path::PathBuf> {
+        let Ok(ref elements) = self.dirents else {
+            return None;
+        };
+        regular_files(elements).into_iter().find(|(i, _)| *i == self.selected).map(|(_, path)| path)
+    }
+
     // Draw the DirView contents if dirty
     fn draw(&mut self, w_debug: WINDOW) {
         // Drawing logic
-        if self.dirty == false {
+        if !self.dirty {
             return;
         }
 
         werase(self.window);
         box_(self.window, 0, 0);
-        // Display path at the top
-        let rc = mvwaddstr(self.window, 0, 2, self.path.to_str().unwrap());
+        // Display path at the top, with the order unless it is by name
+        let title = match self.sort {
+            Sort::Name => self.path.to_str().unwrap().to_string(),
+            sort => format!("{} (by {})", self.path.display(), sort.name()),
+        };
+        let rc = mvwaddstr(self.window, 0, 2, &title);
         if let Err(rc) = rc {
             panic!("mvwaddstr path: {} error: {}", self.path.display(), rc);
         }
-        if let Ok(rc) = rc {
-            if rc == ERR {
-                panic!("mvwaddstr returned ERR for path: {}", self.path.display());
-            }
+        if let Ok(rc) = rc
+            && rc == ERR
+        {
+            panic!("mvwaddstr returned ERR for path: {}", self.path.display());
         }
 
         let win_height = getmaxy(self.window);
@@ -129,7 +266,7 @@ impl DirView {
                             }
                             // Highlight directories in a different color
                             wattron(self.window, COLOR_PAIR(2));
-                            mvwaddstr(self.window, (i + 1 - self.scroll_offset) as i32, 1, &file_name_str);
+                            let _ = mvwaddstr(self.window, (i + 1 - self.scroll_offset) as i32, 1, &file_name_str);
                             wattron(self.window, COLOR_PAIR(1)); // Reset to default color
                             if i == self.selected {
                                 wattroff(self.window, A_REVERSE);
@@ -144,7 +281,15 @@ impl DirView {
                             if is_openable_dir(entry) {
                                 wattron(self.window, COLOR_PAIR(2));
                             }
-                            mvwaddstr(self.window, (i + 1 - self.scroll_offset) as i32, 1, &file_name_str);
+                            // Marked entries in bright yellow
+                            let marked = self.marked.contains(&i);
+                            if marked {
+                                wattron(self.window, COLOR_PAIR(2) | A_BOLD);
+                            }
+                            let _ = mvwaddstr(self.window, (i + 1 - self.scroll_offset) as i32, 1, &file_name_str);
+                            if marked {
+                                wattroff(self.window, A_BOLD);
+                            }
                             if i == self.selected {
                                 wattroff(self.window, A_REVERSE);
                             }
@@ -152,20 +297,24 @@ impl DirView {
                         }
                     }
                 }
-                waddstr(w_debug, &format!("Draw {}:{}\n", self.scroll_offset, self.scroll_offset + view_height));
+                let _ = waddstr(w_debug, &format!("Draw {}:{}\n", self.scroll_offset, self.scroll_offset + view_height));
             }
             Err(e) => {
                 // Highlight directories in a different color
                 let file_name_str = "[..]".to_string();
                 wattron(self.window, A_REVERSE);
                 wattron(self.window, COLOR_PAIR(2));
-                mvwaddstr(self.window, 1, 1, &file_name_str);
+                let _ = mvwaddstr(self.window, 1, 1, &file_name_str);
                 wattron(self.window, COLOR_PAIR(1)); // Reset to default color
                 wattroff(self.window, A_REVERSE);
-                mvwaddstr(self.window, 2, 1, &format!("Read error: {}", e));
+                let _ = mvwaddstr(self.window, 2, 1, &format!("Read error: {}", e));
             }
         }
-        mvwaddstr(self.window, win_height - 1, 2, "Use arrow keys to move, 'q' to quit.");
+        let help = match settings::current().key_name(Command::Quit) {
+            Some(quit) => format!("Use arrow keys to move, '{}' to quit.", quit),
+            None => "Use arrow keys to move.".to_string(),
+        };
+        let _ = mvwaddstr(self.window, win_height - 1, 2, &help);
         wrefresh(self.window);
         self.dirty = false;
     }
@@ -181,7 +330,7 @@ fn scroll_down(w_debug: WINDOW, dirview: &mut DirView) {
                 // Scroll down
                 dirview.scroll_offset += 1;
             }
-            waddstr(w_debug, &format!("KDOWN Beg:{} Sel:{} End:{}\n", dirview.scroll_offset, dirview.selected, dirview.scroll_offset + view_height));
+            let _ = waddstr(w_debug, &format!("KDOWN Beg:{} Sel:{} End:{}\n", dirview.scroll_offset, dirview.selected, dirview.scroll_offset + view_height));
             dirview.dirty = true;
         }
         else {
@@ -203,7 +352,7 @@ fn scroll_up(w_debug: WINDOW, dirview: &mut DirView) {
                 dirview.scroll_offset -= 1;
             }
             let view_height = (getmaxy(dirview.window) - 2) as usize; // Adjust for borders
-            waddstr(w_debug, &format!("KUP Beg:{} Sel:{} End:{}\n", dirview.scroll_offset, dirview.selected, dirview.scroll_offset + view_height));
+            let _ = waddstr(w_debug, &format!("KUP Beg:{} Sel:{} End:{}\n", dirview.scroll_offset, dirview.selected, dirview.scroll_offset + view_height));
             dirview.dirty = true;
         } else {
             beep();  // Cannot move above first entry
@@ -215,14 +364,41 @@ fn scroll_up(w_debug: WINDOW, dirview: &mut DirView) {
 }
 
 fn main() {
+    // `--view FILE` views one file and exits, `--view -` views standard input
+    let args: Vec<String> = std::env::args().collect();
+    let view_target = match args.get(1).map(String::as_str) {
+        None => None,
+        Some("--view") if args.len() == 3 => Some(args[2].clone()),
+        Some(_) => {
+            eprintln!("Usage: {} [--view FILE | --view -]", args[0]);
+            std::process::exit(2);
+        }
+    };
+    // Piped input must be moved aside before ncurses reads keys from standard input
+    let stdin_data = if view_target.as_deref() == Some("-") {
+        match take_stdin() {
+            Ok(data) => Some(data),
+            Err(e) => {
+                eprintln!("Cannot read keys from the terminal: {}", e);
+                std::process::exit(1);
+            }
+        }
+    } else {
+        None
+    };
+
+    // Use the terminal's character set (UTF-8) for file names and file contents
+    let _ = setlocale(LcCategory::all, "");
     initscr();
     noecho();
     curs_set(CURSOR_VISIBILITY::CURSOR_INVISIBLE);
     start_color();
-    init_pair(1, COLOR_WHITE, COLOR_BLUE);      // Regular files
-    init_pair(2, COLOR_YELLOW, COLOR_BLUE);     // Directories
+    // Colours, keys and defaults from the configuration file
+    let settings_errors = settings::load();
+    init_colors(&settings::current());
 
-    let w_debug = newwin(getmaxy(stdscr()), getmaxx(stdscr())/2, 0, 0);
+    let (_, _, _, debug_width) = viewer::calc_extents();
+    let w_debug = newwin(getmaxy(stdscr()), debug_width.max(1), 0, 0);
     if w_debug.is_null() {
         endwin();
         eprintln!("Create debug window failed");
@@ -230,25 +406,39 @@ fn main() {
     }
     keypad(w_debug, true);
     scrollok(w_debug, true);
-    waddstr(w_debug, "Debug Window\n");
+    let _ = waddstr(w_debug, "Debug Window\n");
 
     // modal_dialog::hello_modal(w_debug);
 
+    if let Some(target) = view_target {
+        let path = std::path::Path::new(&target);
+        let (source, remember): (io::Result<Box<dyn source::Source>>, _) = match stdin_data {
+            Some(data) => (source::StreamSource::spawn("(stdin)".to_string(), None, move |_| {
+                Ok(Box::new(data) as Box<dyn io::Read + Send>)
+            }).map(|s| Box::new(s) as Box<dyn source::Source>), None),
+            None => (source::open_path(w_debug, path), Some(path)),
+        };
+        let result = source.map(|source| viewer::view_source_modal(w_debug, source, remember));
+        delwin(w_debug);
+        endwin();
+        if let Err(e) = result {
+            eprintln!("{}: {}", target, e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let cwd = std::env::current_dir().expect("Failed to get current directory");
 
-    let (init_win_height, init_win_width, init_win_starty, init_win_startx);
-    {
-        // Get terminal size
-        let max_y = getmaxy(stdscr());
-        let max_x = getmaxx(stdscr());
-        init_win_starty = 0;
-        init_win_startx = max_x / 2;
-        init_win_height = max_y;
-        init_win_width = max_x - init_win_startx;
-    }
+    let (init_win_height, init_win_width, init_win_starty, init_win_startx) = viewer::calc_extents();
     let mut dirview = DirView::new(init_win_height, init_win_width, init_win_starty, init_win_startx, &cwd)
         .expect("Failed to initialize DirView");
 
+    report_settings_errors(w_debug, &dirview, &settings_errors);
+    // Programs that open, view or edit some types of files
+    let (mut associations, errors) = Associations::load();
+    report_settings_errors(w_debug, &dirview, &errors);
+
     loop {
         // Draw if dirty
         dirview.draw(w_debug);
@@ -272,7 +462,7 @@ fn main() {
                                 let parent_clone = parent.clone();  // Clone the parent path
                                 // Navigate to parent directory
                                 dirview.load(&parent_clone);
-                                waddstr(w_debug, &format!("KENTER: Chdir {}\n", parent_clone.display()));
+                                let _ = waddstr(w_debug, &format!("KENTER: Chdir {}\n", parent_clone.display()));
                                 continue;
                             }
                             DirListItem::Entry(entry) => {
@@ -280,12 +470,18 @@ fn main() {
                                 if path.is_dir() {
                                     // Navigate to sub-directory
                                     dirview.load(&path);
-                                    waddstr(w_debug, &format!("KENTER: Chdir {}\n", path.to_path_buf().display()));
+                                    let _ = waddstr(w_debug, &format!("KENTER: Chdir {}\n", path.to_path_buf().display()));
                                 } else {
                                     // Handle file (open, view, edit, ...)
-                                    waddstr(w_debug, &format!("KENTER: Open {}\n", path.to_path_buf().display()));
-                                    viewer::view_file_modal(w_debug, &path);
-                                    waddstr(w_debug, &format!("KENTER: Close {}\n", path.to_path_buf().display()));
+                                    let _ = waddstr(w_debug, &format!("KENTER: Open {}\n", path.to_path_buf().display()));
+                                    match associations.command(&path, Action::Open) {
+                                        Some(command) => {
+                                            external::run(w_debug, &command, &dirview.path);
+                                            dirview.reload_keeping_selection();
+                                        }
+                                        None => view_in_viewer(w_debug, &mut dirview, &path),
+                                    }
+                                    let _ = waddstr(w_debug, &format!("KENTER: Close {}\n", path.to_path_buf().display()));
                                     // Redraw now
                                     dirview.dirty = true;
                                 }
@@ -293,7 +489,7 @@ fn main() {
                         }
                     }
                     else {
-                        waddstr(w_debug, &format!("KENTER: No entry at selected index {}!\n", dirview.selected));
+                        let _ = waddstr(w_debug, &format!("KENTER: No entry at selected index {}!\n", dirview.selected));
                     }
                 }
                 else {
@@ -305,25 +501,112 @@ fn main() {
                     }
                 }
             }
-            113 | 27 => {
-                // Escape or 'q' to quit
-                break;
-            }
             KEY_RESIZE => {
-                // Resize dirview
-                // Get terminal size
-                let max_y = getmaxy(stdscr());
-                let max_x = getmaxx(stdscr());
-                let win_starty = 0;
-                let win_startx = max_x / 2;
-                let win_height = max_y;
-                let win_width = max_x - win_startx;
-                dirview.resize(win_height, win_width, win_starty, win_startx);
-                // Resize debug window
-                wresize(w_debug, max_y, max_x/2);
-                mvwin(w_debug, 0, 0);
+                // Resize dirview and the debug window
+                layout(w_debug, &mut dirview);
             }
-            _ => {}
+            // The other keys are in the settings
+            _ => match settings::current().command(ch) {
+                Some(Command::Menu) => {
+                    // The user menu
+                    let (selected, marked) = (dirview.selected_path(), dirview.marked_paths());
+                    user_menu::user_menu_modal(w_debug, dirview.window, &dirview.path, selected.as_deref(), &marked);
+                    dirview.reload_keeping_selection();
+                }
+                Some(Command::View) => {
+                    // View the file at the cursor, with its associated viewer or $PAGER if there is one
+                    match dirview.selected_file() {
+                        Some(path) => match external_command(&associations, &path, Action::View) {
+                            Some(command) => {
+                                external::run(w_debug, &command, &dirview.path);
+                                dirview.reload_keeping_selection();
+                            }
+                            None => view_in_viewer(w_debug, &mut dirview, &path),
+                        },
+                        None => {
+                            beep();
+                        }
+                    }
+                    dirview.dirty = true;
+                }
+                Some(command @ (Command::Edit | Command::HexEdit)) => {
+                    // Edit the file at the cursor, or edit it in hex
+                    match dirview.selected_file() {
+                        Some(path) if command == Command::HexEdit => hex_editor::edit_file_modal(w_debug, &path),
+                        Some(path) => match external_command(&associations, &path, Action::Edit) {
+                            Some(command) => {
+                                external::run(w_debug, &command, &dirview.path);
+                                dirview.reload_keeping_selection();
+                            }
+                            None => editor::edit_file_modal(w_debug, &path),
+                        },
+                        None => {
+                            beep();
+                        }
+                    }
+                    dirview.dirty = true;
+                }
+                Some(Command::Shell) => {
+                    // Run a shell command and view its output
+                    if let Some(command) = modal_dialog::prompt(dirview.window, "Command", "")
+                        && !command.trim().is_empty() {
+                        let _ = waddstr(w_debug, &format!("RUN: {}\n", command));
+                        viewer::view_command_modal(w_debug, &command, &dirview.path);
+                    }
+                    dirview.dirty = true;
+                }
+                Some(Command::Mark) => {
+                    // Mark the file for commands on two or more files
+                    dirview.toggle_mark(w_debug);
+                }
+                Some(command @ (Command::Diff | Command::Compare)) => {
+                    // Compare two files line by line, side by side, or byte by byte
+                    match dirview.compare_pair() {
+                        Some((left, right)) if command == Command::Diff => diff_view::diff_files_modal(w_debug, &left, &right),
+                        Some((left, right)) => bindiff_view::compare_files_modal(w_debug, &left, &right),
+                        None => modal_dialog::message(dirview.window, "Compare",
+                            "Mark two files with Insert,\nor mark one and put the cursor on the other"),
+                    }
+                    dirview.dirty = true;
+                }
+                Some(Command::ToggleHidden) => {
+                    // Show or hide the entries whose name starts with '.'
+                    dirview.show_hidden = !dirview.show_hidden;
+                    dirview.reload_keeping_selection();
+                }
+                Some(Command::Sort) => {
+                    // Sort by the next order: name, extension, size, time
+                    dirview.sort = dirview.sort.next();
+                    let _ = waddstr(w_debug, &format!("SORT: {}\n", dirview.sort.name()));
+                    dirview.reload_keeping_selection();
+                }
+                Some(Command::ReloadConfig) => {
+                    // Read the configuration files again
+                    let mut errors = settings::load();
+                    let loaded;
+                    (associations, loaded) = Associations::load();
+                    errors.extend(loaded);
+                    let settings = settings::current();
+                    init_colors(&settings);
+                    dirview.sort = settings.sort;
+                    dirview.show_hidden = settings.show_hidden;
+                    layout(w_debug, &mut dirview);
+                    dirview.reload_keeping_selection();
+                    if errors.is_empty() {
+                        let _ = waddstr(w_debug, "SETTINGS: Reloaded\n");
+                    }
+                    report_settings_errors(w_debug, &dirview, &errors);
+                }
+                Some(Command::Quit) => {
+                    // Quit, after asking if the settings say so
+                    if !settings::current().confirm_quit
+                        || modal_dialog::choose(dirview.window, "Quit?", &["Quit", "Cancel"], 0) == Some(0) {
+                        break;
+                    }
+                    dirview.dirty = true;
+                }
+                None => {}
+            },
         }
     }
 
@@ -333,16 +616,63 @@ fn main() {
 }
 
 /// Read the contents of a directory and return the entries.
-/// Returns a Vec of DirEntry for the given directory path.
+/// Returns a Vec of DirEntry for the given directory path, in the given order,
+/// without the hidden ones unless `show_hidden`.
 /// Returns an io::Error if the directory can't be read.
-fn read_directory_contents(path: &std::path::Path) -> io::Result<Vec<fs::DirEntry>> {
+fn read_directory_contents(path: &std::path::Path, sort: Sort, show_hidden: bool) -> io::Result<Vec<fs::DirEntry>> {
     let mut entries = fs::read_dir(path)?
         .filter_map(Result::ok)
+        .filter(|e| show_hidden || !e.file_name().to_string_lossy().starts_with('.'))
         .collect::<Vec<_>>();
     entries.sort_by_key(|e| e.file_name());
+    // Stable sorts, so entries that are alike stay by name
+    match sort {
+        Sort::Name => {}
+        Sort::Extension => entries.sort_by_cached_key(|e| {
+            std::path::Path::new(&e.file_name()).extension().map(|x| x.to_string_lossy().to_lowercase())
+        }),
+        Sort::Size => entries.sort_by_cached_key(|e| std::cmp::Reverse(e.metadata().map(|m| m.len()).unwrap_or(0))),
+        Sort::Time => entries.sort_by_cached_key(|e| {
+            std::cmp::Reverse(e.metadata().and_then(|m| m.modified()).ok())
+        }),
+    }
     Ok(entries)
 }
 
+// Colour pairs 1 and 2, used by every window, and the viewer's ANSI and
+// highlighting colours on the same background
+fn init_colors(settings: &Settings) {
+    let (fg, bg) = settings.text_colors;
+    init_pair(1, fg, bg);      // Regular files
+    let (fg, bg) = settings.highlight_colors;
+    init_pair(2, fg, bg);      // Directories
+    ansi::init_colors(settings.text_colors);
+}
+
+// Fit the directory panel and the debug window to the terminal and the settings
+fn layout(w_debug: WINDOW, dirview: &mut DirView) {
+    let (win_height, win_width, win_starty, win_startx) = viewer::calc_extents();
+    dirview.resize(win_height, win_width, win_starty, win_startx);
+    wresize(w_debug, win_height, win_startx.max(1));
+    mvwin(w_debug, 0, 0);
+}
+
+// Show what was wrong with the configuration files, in the debug window and
+// over the whole screen, as the lines are long
+fn report_settings_errors(w_debug: WINDOW, dirview: &DirView, errors: &[String]) {
+    if errors.is_empty() {
+        return;
+    }
+    for error in errors {
+        let _ = waddstr(w_debug, &format!("SETTINGS: {}\n", error));
+    }
+    modal_dialog::message(stdscr(), "Settings", &errors.join("\n"));
+    for window in [w_debug, dirview.window] {
+        touchwin(window);
+        wrefresh(window);
+    }
+}
+
 // Check if the target is a directory and can be opened.
 // Follows symlinks.
 fn is_openable_dir(entry: &fs::DirEntry) -> bool {
@@ -359,6 +689,45 @@ fn is_openable_dir(entry: &fs::DirEntry) -> bool {
     }
 }
 
+// The program that views or edits a file, if not the built-in one: the file's
+// association, or $PAGER or $EDITOR if the settings say so
+fn external_command(associations: &Associations, path: &std::path::Path, action: Action) -> Option<String> {
+    associations.command(path, action).or_else(|| {
+        let settings = settings::current();
+        let program = match action {
+            Action::View if settings.external_viewer => settings::program_from_env("PAGER", "less"),
+            Action::Edit if settings.external_editor => settings::program_from_env("EDITOR", "vi"),
+            _ => return None,
+        };
+        Some(format!("{} {}", program, associations::quote_path(path)))
+    })
+}
+
+// View a file in the viewer, which can step through the other regular files of the directory
+fn view_in_viewer(w_debug: WINDOW, dirview: &mut DirView, path: &std::path::Path) {
+    let files = match dirview.dirents {
+        Ok(ref elements) => regular_files(elements),
+        Err(_) => Vec::new(),
+    };
+    match files.iter().position(|(i, _)| *i == dirview.selected) {
+        Some(start) => {
+            let paths: Vec<_> = files.iter().map(|(_, p)| p.clone()).collect();
+            let last = viewer::view_files_modal(w_debug, &paths, start);
+            // Leave the cursor on the file viewed last
+            dirview.select(files[last].0);
+        }
+        None => viewer::view_file_modal(w_debug, path),
+    }
+}
+
+// Regular files (following symlinks) among the entries, with their index
+fn regular_files(elements: &[DirListItem]) -> Vec<(usize, std::path::PathBuf)> {
+    elements.iter().enumerate().filter_map(|(i, item)| match item {
+        DirListItem::Entry(entry) if fs::metadata(entry.path()).is_ok_and(|m| m.is_file()) => Some((i, entry.path())),
+        _ => None,
+    }).collect()
+}
+
 fn display_name(entry: &fs::DirEntry) -> String {
     let file_name_os = entry.file_name();                     // Own the OsString
     let name = file_name_os.to_string_lossy();                // Borrow from that
@@ -368,3 +737,13 @@ fn display_name(entry: &fs::DirEntry) -> String {
         name.into()
     }
 }
+
+// Keep the data piped to standard input and read keys from the terminal instead
+fn take_stdin() -> io::Result<fs::File> {
+    let data = io::stdin().as_fd().try_clone_to_owned()?;
+    let tty = fs::File::open("/dev/tty")?;
+    if unsafe { libc::dup2(tty.as_raw_fd(), 0) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(fs::File::from(data))
+}
@@ -0,0 +1,154 @@
+// Small modal popups drawn over another window.
+// The caller repaints whatever was underneath when the popup closes.
+
+use ncurses::*;
+use unicode_width::UnicodeWidthChar;
+
+// Create a boxed popup of the given size, centred over `parent`
+fn centered_popup(parent: WINDOW, height: i32, width: i32) -> WINDOW {
+    let height = height.min(getmaxy(parent)).max(3);
+    let width = width.min(getmaxx(parent)).max(4);
+    let starty = getbegy(parent) + (getmaxy(parent) - height) / 2;
+    let startx = getbegx(parent) + (getmaxx(parent) - width) / 2;
+    let popup = newwin(height, width, starty, startx);
+    keypad(popup, true);
+    wbkgd(popup, COLOR_PAIR(1));
+    popup
+}
+
+// Let the user pick one of `items` with the arrow keys and Enter.
+// Returns the index of the chosen item, or None if the user pressed Esc or 'q'.
+pub fn choose(parent: WINDOW, title: &str, items: &[&str], initial: usize) -> Option<usize> {
+    pick(parent, title, items, initial, &[])
+}
+
+// Like choose, but each item also has a key that picks it at once
+pub fn choose_by_key(parent: WINDOW, title: &str, items: &[&str], hotkeys: &[char]) -> Option<usize> {
+    pick(parent, title, items, 0, hotkeys)
+}
+
+fn pick(parent: WINDOW, title: &str, items: &[&str], initial: usize, hotkeys: &[char]) -> Option<usize> {
+    if items.is_empty() {
+        return None;
+    }
+    let widest = items.iter().map(|s| s.chars().count()).max().unwrap_or(0);
+    let width = (widest.max(title.chars().count() + 2) + 4) as i32;
+    let popup = centered_popup(parent, items.len() as i32 + 2, width);
+
+    let mut selected = initial.min(items.len() - 1);
+    let mut scroll_offset = 0;
+    let result = loop {
+        // Keep the selection on screen
+        let view_height = (getmaxy(popup) - 2).max(1) as usize;
+        if selected < scroll_offset {
+            scroll_offset = selected;
+        } else if selected >= scroll_offset + view_height {
+            scroll_offset = selected + 1 - view_height;
+        }
+
+        werase(popup);
+        box_(popup, 0, 0);
+        let _ = mvwaddstr(popup, 0, 2, &format!(" {} ", title));
+        for (i, item) in items.iter().enumerate().skip(scroll_offset).take(view_height) {
+            if i == selected {
+                wattron(popup, A_REVERSE);
+            }
+            let _ = mvwaddnstr(popup, (i - scroll_offset) as i32 + 1, 2, item, getmaxx(popup) - 4);
+            if i == selected {
+                wattroff(popup, A_REVERSE);
+            }
+        }
+        wrefresh(popup);
+
+        let ch = wgetch(popup);
+        if let Some(i) = hotkeys.iter().position(|&key| key as i32 == ch) {
+            break Some(i);
+        }
+        match ch {
+            KEY_UP if selected > 0 => selected -= 1,
+            KEY_DOWN if selected + 1 < items.len() => selected += 1,
+            KEY_HOME => selected = 0,
+            KEY_END => selected = items.len() - 1,
+            KEY_ENTER | 10 | 13 => break Some(selected),
+            // Escape or 'q' to cancel
+            113 | 27 => break None,
+            _ => {}
+        }
+    };
+    delwin(popup);
+    result
+}
+
+// Ask the user for a line of text, starting with `initial`.
+// Returns the text when the user presses Enter, or None on Esc.
+pub fn prompt(parent: WINDOW, title: &str, initial: &str) -> Option<String> {
+    let popup = centered_popup(parent, 3, getmaxx(parent) * 3 / 4);
+    let mut text: Vec<char> = initial.chars().collect();
+    let mut cursor = text.len(); // Index in `text`
+    curs_set(CURSOR_VISIBILITY::CURSOR_VISIBLE);
+
+    let result = loop {
+        // Scroll the field sideways to keep the cursor visible
+        let field_width = (getmaxx(popup) - 4).max(1) as usize;
+        let first = cursor.saturating_sub(field_width - 1);
+        let shown: String = text[first..].iter().take(field_width).collect();
+        let cursor_col: usize = text[first..cursor].iter().map(|c| c.width().unwrap_or(1)).sum();
+
+        werase(popup);
+        box_(popup, 0, 0);
+        let _ = mvwaddstr(popup, 0, 2, &format!(" {} ", title));
+        let _ = mvwaddstr(popup, 1, 2, &shown);
+        wmove(popup, 1, 2 + cursor_col as i32);
+        wrefresh(popup);
+
+        match wget_wch(popup) {
+            Some(WchResult::KeyCode(KEY_LEFT)) if cursor > 0 => cursor -= 1,
+            Some(WchResult::KeyCode(KEY_RIGHT)) if cursor < text.len() => cursor += 1,
+            Some(WchResult::KeyCode(KEY_HOME)) => cursor = 0,
+            Some(WchResult::KeyCode(KEY_END)) => cursor = text.len(),
+            Some(WchResult::KeyCode(KEY_BACKSPACE)) if cursor > 0 => {
+                cursor -= 1;
+                text.remove(cursor);
+            }
+            Some(WchResult::KeyCode(KEY_DC)) if cursor < text.len() => {
+                text.remove(cursor);
+            }
+            Some(WchResult::KeyCode(KEY_ENTER)) => break Some(text.iter().collect()),
+            Some(WchResult::Char(c)) => match char::from_u32(c) {
+                Some('\n') | Some('\r') => break Some(text.iter().collect()),
+                // Escape to cancel
+                Some('\x1b') => break None,
+                // Backspace or Delete, depending on the terminal
+                Some('\x08') | Some('\x7f') if cursor > 0 => {
+                    cursor -= 1;
+                    text.remove(cursor);
+                }
+                Some(c) if !c.is_control() => {
+                    text.insert(cursor, c);
+                    cursor += 1;
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+    };
+    curs_set(CURSOR_VISIBILITY::CURSOR_INVISIBLE);
+    delwin(popup);
+    result
+}
+
+// Show a message until the user presses a key
+pub fn message(parent: WINDOW, title: &str, text: &str) {
+    let lines: Vec<&str> = text.lines().collect();
+    let widest = lines.iter().map(|s| s.chars().count()).max().unwrap_or(0);
+    let width = (widest.max(title.chars().count() + 2) + 4) as i32;
+    let popup = centered_popup(parent, lines.len() as i32 + 2, width);
+    box_(popup, 0, 0);
+    let _ = mvwaddstr(popup, 0, 2, &format!(" {} ", title));
+    for (i, line) in lines.iter().enumerate().take((getmaxy(popup) - 2).max(0) as usize) {
+        let _ = mvwaddnstr(popup, i as i32 + 1, 2, line, getmaxx(popup) - 4);
+    }
+    wrefresh(popup);
+    wgetch(popup);
+    delwin(popup);
+}
@@ -0,0 +1,174 @@
+// Where each file was left in the viewer, so reopening it continues there.
+// Kept in ~/.local/state/norton_commander_rust/positions (or under
+// $XDG_STATE_HOME), one file per line, most recent first:
+//   size <TAB> mtime <TAB> offset <TAB> line <TAB> mode <TAB> absolute path
+// A position is only used while the file's size and mtime are unchanged.
+
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+// Older positions are forgotten
+const MAX_ENTRIES: usize = 500;
+
+// How the viewer showed a file
+pub struct SavedPosition {
+    pub offset: u64, // Top row
+    pub line: Option<u64>, // Line number of the top row, if it was known
+    pub encoding: String, // Encoding name
+    pub tab_width: usize,
+    pub line_numbers: bool,
+    pub ansi: bool,
+    pub highlighting: bool,
+}
+
+impl SavedPosition {
+    // The view mode as one field, e.g. "UTF-8,8,n,h"
+    fn mode(&self) -> String {
+        let mut mode = format!("{},{}", self.encoding, self.tab_width);
+        for (flag, letter) in [(self.line_numbers, ",n"), (self.ansi, ",a"), (self.highlighting, ",h")] {
+            if flag {
+                mode.push_str(letter);
+            }
+        }
+        mode
+    }
+
+    fn parse(offset: &str, line: &str, mode: &str) -> Option<Self> {
+        let mut parts = mode.split(',');
+        let encoding = parts.next()?.to_string();
+        let tab_width = parts.next()?.parse().ok()?;
+        let flags: Vec<&str> = parts.collect();
+        Some(SavedPosition {
+            offset: offset.parse().ok()?,
+            line: line.parse().ok(),
+            encoding,
+            tab_width,
+            line_numbers: flags.contains(&"n"),
+            ansi: flags.contains(&"a"),
+            highlighting: flags.contains(&"h"),
+        })
+    }
+}
+
+fn positions_file() -> Option<PathBuf> {
+    let state_dir = match std::env::var_os("XDG_STATE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".local/state"),
+    };
+    Some(state_dir.join("norton_commander_rust").join("positions"))
+}
+
+// Size and modification time of a file, as written in the positions file
+fn file_key(path: &Path) -> io::Result<(PathBuf, String, String)> {
+    let metadata = fs::metadata(path)?;
+    let mtime = format!("{}.{:09}", metadata.mtime(), metadata.mtime_nsec());
+    Ok((fs::canonicalize(path)?, metadata.len().to_string(), mtime))
+}
+
+// All entries: size, mtime, offset, line, mode, path
+fn read_entries() -> io::Result<Vec<[String; 6]>> {
+    let Some(file) = positions_file() else {
+        return Ok(Vec::new());
+    };
+    let file = match File::open(file) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut entries = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        let fields: Vec<&str> = line.splitn(6, '\t').collect();
+        if let Ok(fields) = <[&str; 6]>::try_from(fields) {
+            entries.push(fields.map(str::to_string));
+        }
+    }
+    Ok(entries)
+}
+
+// The position a file was left at, if it hasn't changed since
+pub fn load(path: &Path) -> io::Result<Option<SavedPosition>> {
+    let (path, size, mtime) = file_key(path)?;
+    let path = path.to_string_lossy();
+    Ok(read_entries()?.iter()
+        .find(|e| e[5] == path)
+        .filter(|e| e[0] == size && e[1] == mtime)
+        .and_then(|e| SavedPosition::parse(&e[2], &e[3], &e[4])))
+}
+
+// Remember where a file was left
+pub fn save(path: &Path, position: &SavedPosition) -> io::Result<()> {
+    let Some(file) = positions_file() else {
+        return Ok(());
+    };
+    let (path, size, mtime) = file_key(path)?;
+    let path = path.to_string_lossy().into_owned();
+    if path.contains('\n') {
+        return Ok(()); // Can't be stored one per line
+    }
+    let line = position.line.map(|n| n.to_string()).unwrap_or_default();
+    let mut entries = read_entries()?;
+    entries.retain(|e| e[5] != path);
+    entries.insert(0, [size, mtime, position.offset.to_string(), line, position.mode(), path]);
+    entries.truncate(MAX_ENTRIES);
+
+    // Write a new file and rename it over the old one
+    if let Some(dir) = file.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let temp = file.with_extension("new");
+    let mut out = io::BufWriter::new(File::create(&temp)?);
+    for entry in &entries {
+        writeln!(out, "{}", entry.join("\t"))?;
+    }
+    out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    fs::rename(temp, file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mode_round_trip() {
+        let modes = ["UTF-8,8", "UTF-8,8,n,a,h", "CP437,4,h", "UTF-16LE,2,n"];
+        for mode in modes {
+            let position = SavedPosition::parse("123", "45", mode).unwrap();
+            assert_eq!(position.mode(), mode);
+        }
+        let position = SavedPosition::parse("123", "45", "Windows-1252,3,a").unwrap();
+        assert_eq!(position.offset, 123);
+        assert_eq!(position.line, Some(45));
+        assert_eq!(position.encoding, "Windows-1252");
+        assert_eq!(position.tab_width, 3);
+        assert!(!position.line_numbers && position.ansi && !position.highlighting);
+        // Unknown flags are ignored
+        assert_eq!(SavedPosition::parse("0", "1", "UTF-8,8,x,n").unwrap().mode(), "UTF-8,8,n");
+    }
+
+    #[test]
+    fn unknown_line() {
+        let position = SavedPosition::parse("4096", "", "UTF-8,8").unwrap();
+        assert_eq!(position.offset, 4096);
+        assert_eq!(position.line, None);
+        assert_eq!(SavedPosition::parse("0", "x", "UTF-8,8").unwrap().line, None);
+    }
+
+    #[test]
+    fn bad_fields() {
+        let cases = [
+            ("", "1", "UTF-8,8"),
+            ("-1", "1", "UTF-8,8"),
+            ("12x", "1", "UTF-8,8"),
+            ("0", "1", "UTF-8"),
+            ("0", "1", "UTF-8,"),
+            ("0", "1", "UTF-8,wide"),
+            ("0", "1", ""),
+        ];
+        for (offset, line, mode) in cases {
+            assert!(SavedPosition::parse(offset, line, mode).is_none(), "{:?}", (offset, line, mode));
+        }
+    }
+}
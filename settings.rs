@@ -0,0 +1,542 @@
+// User settings, from ~/.config/norton_commander_rust/config.toml (or under
+// $XDG_CONFIG_HOME). Every setting is optional; these are the defaults:
+//   [colors]
+//   text = "white on blue"          # Windows and files
+//   highlight = "yellow on blue"    # Directories, marked files, line numbers
+//   [keys]                          # Keys of the directory panel
+//   quit = ["q", "Esc"]
+//   mark = ["Insert", "Space"]
+//   diff = "d"
+//   compare = "c"
+//   shell = "!"
+//   menu = "F2"
+//   view = "F3"
+//   edit = "F4"
+//   hex_edit = "Shift-F4"
+//   toggle_hidden = "."
+//   sort = "s"
+//   reload_config = "Ctrl-R"
+//   [viewer_keys]                   # Keys of the viewer, and of its filter, tree, table, diff and compare views
+//   close = ["q", "Esc"]
+//   up = "Up"
+//   down = "Down"
+//   home = "Home"
+//   end = "End"
+//   [panel]
+//   width = 50                      # Percent of the screen, the debug window has the rest
+//   sort = "name"                   # name, extension, size (largest first) or time (newest first)
+//   show_hidden = true
+//   [viewer]
+//   tab_width = 8
+//   line_numbers = false
+//   highlighting = true
+//   external = false                # F3 runs $PAGER instead of the built-in viewer
+//   [editor]
+//   external = false                # F4 runs $EDITOR instead of the built-in editor
+//   [confirm]
+//   quit = false
+//   overwrite = true                # Saving the editor's text over another file
+// The viewer's other keys, which mostly are letters, and the editors' keys
+// can't be changed.
+// The settings are read at startup and again with the reload_config key.
+// Wrong settings are reported and their defaults used instead.
+
+use crate::display_line::DEFAULT_TAB_WIDTH;
+use ncurses::*;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+// Commands of the directory panel that can be given other keys
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Command {
+    Quit,
+    Mark,
+    Diff,
+    Compare,
+    Shell,
+    Menu,
+    View,
+    Edit,
+    HexEdit,
+    ToggleHidden,
+    Sort,
+    ReloadConfig,
+}
+
+// Each command's name in the [keys] section and its default keys
+const COMMANDS: &[(Command, &str, &[&str])] = &[
+    (Command::Quit, "quit", &["q", "Esc"]),
+    (Command::Mark, "mark", &["Insert", "Space"]),
+    (Command::Diff, "diff", &["d"]),
+    (Command::Compare, "compare", &["c"]),
+    (Command::Shell, "shell", &["!"]),
+    (Command::Menu, "menu", &["F2"]),
+    (Command::View, "view", &["F3"]),
+    (Command::Edit, "edit", &["F4"]),
+    (Command::HexEdit, "hex_edit", &["Shift-F4"]),
+    (Command::ToggleHidden, "toggle_hidden", &["."]),
+    (Command::Sort, "sort", &["s"]),
+    (Command::ReloadConfig, "reload_config", &["Ctrl-R"]),
+];
+
+// Keys with a fixed meaning in the directory panel: Up, Down and the forms of Enter
+const FIXED_KEYS: [i32; 5] = [KEY_UP, KEY_DOWN, KEY_ENTER, 10, 13];
+
+// Commands of the viewer and the views over it that can be given other keys
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ViewCommand {
+    Up,
+    Down,
+    Home,
+    End,
+    Close,
+}
+
+// Each command's name in the [viewer_keys] section and its default keys
+const VIEW_COMMANDS: &[(ViewCommand, &str, &[&str])] = &[
+    (ViewCommand::Close, "close", &["q", "Esc"]),
+    (ViewCommand::Up, "up", &["Up"]),
+    (ViewCommand::Down, "down", &["Down"]),
+    (ViewCommand::Home, "home", &["Home"]),
+    (ViewCommand::End, "end", &["End"]),
+];
+
+// Keys with a fixed meaning in the viewer and its views: the letters of their
+// commands, digits for bookmarks, and Tab, Enter and the other movement keys
+const VIEWER_FIXED_CHARS: &str = "0123456789abefghlmnpstvwFRS +-";
+const VIEWER_FIXED_KEYS: [i32; 9] = [9, 10, 13, KEY_ENTER, KEY_BTAB, KEY_LEFT, KEY_RIGHT, KEY_PPAGE, KEY_NPAGE];
+
+// Order of the entries in the directory panel
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Sort {
+    Name,
+    Extension,
+    Size, // Largest first
+    Time, // Newest first
+}
+
+impl Sort {
+    pub const ALL: [Sort; 4] = [Sort::Name, Sort::Extension, Sort::Size, Sort::Time];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Sort::Name => "name",
+            Sort::Extension => "extension",
+            Sort::Size => "size",
+            Sort::Time => "time",
+        }
+    }
+
+    // The next order, for the sort key
+    pub fn next(&self) -> Sort {
+        let i = Sort::ALL.iter().position(|s| s == self).unwrap_or(0);
+        Sort::ALL[(i + 1) % Sort::ALL.len()]
+    }
+}
+
+pub struct Settings {
+    pub text_colors: (i16, i16), // Foreground and background
+    pub highlight_colors: (i16, i16),
+    keys: Vec<(i32, Command, String)>, // Key code, its command and its name
+    view_keys: Vec<(i32, ViewCommand, String)>,
+    pub panel_width: i32, // Percent of the screen
+    pub sort: Sort,
+    pub show_hidden: bool,
+    pub tab_width: usize,
+    pub line_numbers: bool,
+    pub highlighting: bool,
+    pub external_viewer: bool,
+    pub external_editor: bool,
+    pub confirm_quit: bool,
+    pub confirm_overwrite: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            text_colors: (COLOR_WHITE, COLOR_BLUE),
+            highlight_colors: (COLOR_YELLOW, COLOR_BLUE),
+            keys: default_keys(COMMANDS),
+            view_keys: default_keys(VIEW_COMMANDS),
+            panel_width: 50,
+            sort: Sort::Name,
+            show_hidden: true,
+            tab_width: DEFAULT_TAB_WIDTH,
+            line_numbers: false,
+            highlighting: true,
+            external_viewer: false,
+            external_editor: false,
+            confirm_quit: false,
+            confirm_overwrite: true,
+        }
+    }
+}
+
+// The keys of a table of commands, before the settings change them
+fn default_keys<C: Copy>(commands: &[(C, &str, &[&str])]) -> Vec<(i32, C, String)> {
+    commands.iter()
+        .flat_map(|&(command, _, keys)| keys.iter().filter_map(move |k| Some((parse_key(k)?, command, k.to_string()))))
+        .collect()
+}
+
+// The settings in use, replaced when they are reloaded
+static CURRENT: RwLock<Option<Arc<Settings>>> = RwLock::new(None);
+
+pub fn current() -> Arc<Settings> {
+    CURRENT.read().unwrap().clone().unwrap_or_default()
+}
+
+// A file in the configuration directory
+pub fn config_file(name: &str) -> Option<PathBuf> {
+    let config_dir = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(config_dir.join("norton_commander_rust").join(name))
+}
+
+// The user's pager or editor from the environment, or a common default
+pub fn program_from_env(variable: &str, default: &str) -> String {
+    std::env::var(variable).ok().filter(|s| !s.trim().is_empty()).unwrap_or_else(|| default.to_string())
+}
+
+// The key code for a key name: a character, Esc, Space, Enter, Tab, Insert,
+// Delete, Home, End, PgUp, PgDn, Up, Down, F1-F12, Shift-F1-F12 or Ctrl-A-Z
+fn parse_key(name: &str) -> Option<i32> {
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return Some(c as i32).filter(|_| c.is_ascii_graphic());
+    }
+    let code = match name {
+        "Esc" => 27,
+        "Space" => 32,
+        "Enter" => 10,
+        "Tab" => 9,
+        "Insert" => KEY_IC,
+        "Delete" => KEY_DC,
+        "Home" => KEY_HOME,
+        "End" => KEY_END,
+        "PgUp" => KEY_PPAGE,
+        "PgDn" => KEY_NPAGE,
+        "Up" => KEY_UP,
+        "Down" => KEY_DOWN,
+        _ => {
+            let (shift, f) = match name.strip_prefix("Shift-") {
+                Some(f) => (12, f),
+                None => (0, name),
+            };
+            if let Some(n) = f.strip_prefix('F').and_then(|n| n.parse::<i32>().ok())
+                && (1..=12).contains(&n) {
+                // Shift-F1 is F13 and so on
+                return Some(KEY_F0 + shift + n);
+            }
+            let letter = name.strip_prefix("Ctrl-").filter(|l| l.len() == 1)?.chars().next()?;
+            if !letter.is_ascii_alphabetic() {
+                return None;
+            }
+            letter.to_ascii_uppercase() as i32 - 64
+        }
+    };
+    Some(code)
+}
+
+fn parse_color(name: &str) -> Option<i16> {
+    let colors = [
+        ("black", COLOR_BLACK), ("red", COLOR_RED), ("green", COLOR_GREEN), ("yellow", COLOR_YELLOW),
+        ("blue", COLOR_BLUE), ("magenta", COLOR_MAGENTA), ("cyan", COLOR_CYAN), ("white", COLOR_WHITE),
+    ];
+    colors.iter().find(|(n, _)| *n == name).map(|&(_, c)| c)
+}
+
+// Settings being read, with what was wrong with them
+struct Reader {
+    table: toml::Table,
+    errors: Vec<String>,
+}
+
+impl Reader {
+    // The value of a setting, removed so that what is left at the end is unknown
+    fn take(&mut self, section: &str, name: &str) -> Option<toml::Value> {
+        let table = self.table.get_mut(section)?.as_table_mut()?;
+        table.remove(name)
+    }
+
+    // Read a setting into `setting`, if it is there and `parse` accepts it
+    fn read<T>(&mut self, section: &str, name: &str, expected: &str, setting: &mut T,
+        parse: impl Fn(&toml::Value) -> Option<T>) {
+        let Some(value) = self.take(section, name) else {
+            return;
+        };
+        match parse(&value) {
+            Some(value) => *setting = value,
+            None => self.errors.push(format!("[{}] {}: expected {}, not {}", section, name, expected, value)),
+        }
+    }
+
+    fn read_bool(&mut self, section: &str, name: &str, setting: &mut bool) {
+        self.read(section, name, "true or false", setting, toml::Value::as_bool);
+    }
+
+    fn read_colors(&mut self, name: &str, setting: &mut (i16, i16)) {
+        // The colours are black, red, green, yellow, blue, magenta, cyan and white
+        self.read("colors", name, "colours like \"white on blue\"", setting, |v| {
+                let (fg, bg) = v.as_str()?.split_once(" on ")?;
+                Some((parse_color(fg.trim())?, parse_color(bg.trim())?))
+            });
+    }
+
+    // Read a section of keys for `commands`, none of which may be a `fixed` key.
+    // A key given to two commands keeps the first one.
+    fn read_keys<C: Copy + PartialEq>(&mut self, section: &str, commands: &[(C, &str, &[&str])], fixed: &[i32])
+        -> Vec<(i32, C, String)> {
+        let mut keys: Vec<(i32, C, String)> = Vec::new();
+        for &(command, name, defaults) in commands {
+            let mut names: Vec<String> = defaults.iter().map(|k| k.to_string()).collect();
+            self.read(section, name, "a key name or a list of them", &mut names, |v| match v {
+                toml::Value::String(s) => Some(vec![s.clone()]),
+                toml::Value::Array(a) => a.iter().map(|k| k.as_str().map(str::to_string)).collect(),
+                _ => None,
+            });
+            for key_name in names {
+                let Some(key) = parse_key(&key_name) else {
+                    self.errors.push(format!("[{}] {}: unknown key '{}'", section, name, key_name));
+                    continue;
+                };
+                if fixed.contains(&key) {
+                    self.errors.push(format!("[{}] {}: '{}' has another use that can't be changed", section, name, key_name));
+                } else if let Some(&(_, other, _)) = keys.iter().find(|(k, _, _)| *k == key) {
+                    let other_name = commands.iter().find(|c| c.0 == other).map_or("", |c| c.1);
+                    self.errors.push(format!("[{}] {}: '{}' is already the key for {}", section, name, key_name, other_name));
+                } else {
+                    keys.push((key, command, key_name));
+                }
+            }
+        }
+        // Other names, such as an editor's commands, are not commands whose keys can be changed
+        if let Some(table) = self.table.get_mut(section).and_then(|v| v.as_table_mut()) {
+            let names: Vec<&str> = commands.iter().map(|c| c.1).collect();
+            for unknown in std::mem::take(table).keys() {
+                self.errors.push(format!("[{}] {}: can't be changed, only {}",
+                    section, unknown, names.join(", ")));
+            }
+        }
+        keys
+    }
+
+    // Report the settings that weren't read
+    fn report_unknown(&mut self) {
+        for (section, value) in &self.table {
+            match value.as_table() {
+                Some(table) => self.errors.extend(table.keys().map(|name| format!("[{}] {}: unknown setting", section, name))),
+                None => self.errors.push(format!("{}: unknown setting", section)),
+            }
+        }
+    }
+}
+
+impl Settings {
+    // Read the settings from a file's text, with what was wrong with them
+    fn parse(text: &str) -> Result<(Self, Vec<String>), String> {
+        let table: toml::Table = text.parse().map_err(|e: toml::de::Error| e.to_string())?;
+        let mut reader = Reader { table, errors: Vec::new() };
+        let mut settings = Settings::default();
+        reader.read_colors("text", &mut settings.text_colors);
+        reader.read_colors("highlight", &mut settings.highlight_colors);
+        settings.keys = reader.read_keys("keys", COMMANDS, &FIXED_KEYS);
+        let viewer_fixed: Vec<i32> = VIEWER_FIXED_CHARS.chars().map(|c| c as i32).chain(VIEWER_FIXED_KEYS).collect();
+        settings.view_keys = reader.read_keys("viewer_keys", VIEW_COMMANDS, &viewer_fixed);
+        reader.read("panel", "width", "a percentage from 20 to 80", &mut settings.panel_width,
+            |v| v.as_integer().filter(|n| (20..=80).contains(n)).map(|n| n as i32));
+        reader.read("panel", "sort", "name, extension, size or time", &mut settings.sort,
+            |v| Sort::ALL.into_iter().find(|s| Some(s.name()) == v.as_str()));
+        reader.read_bool("panel", "show_hidden", &mut settings.show_hidden);
+        reader.read("viewer", "tab_width", "a number from 1 to 16", &mut settings.tab_width,
+            |v| v.as_integer().filter(|n| (1..=16).contains(n)).map(|n| n as usize));
+        reader.read_bool("viewer", "line_numbers", &mut settings.line_numbers);
+        reader.read_bool("viewer", "highlighting", &mut settings.highlighting);
+        reader.read_bool("viewer", "external", &mut settings.external_viewer);
+        reader.read_bool("editor", "external", &mut settings.external_editor);
+        reader.read_bool("confirm", "quit", &mut settings.confirm_quit);
+        reader.read_bool("confirm", "overwrite", &mut settings.confirm_overwrite);
+        reader.report_unknown();
+        Ok((settings, reader.errors))
+    }
+
+    // The command for a key in the directory panel
+    pub fn command(&self, key: i32) -> Option<Command> {
+        self.keys.iter().find(|(k, _, _)| *k == key).map(|&(_, command, _)| command)
+    }
+
+    // The command for a key in the viewer and the views over it
+    pub fn view_command(&self, key: i32) -> Option<ViewCommand> {
+        self.view_keys.iter().find(|(k, _, _)| *k == key).map(|&(_, command, _)| command)
+    }
+
+    // The names of a viewer command's keys, for its hint on the bottom line
+    pub fn view_key_names(&self, command: ViewCommand) -> String {
+        let names: Vec<&str> = self.view_keys.iter()
+            .filter(|(_, c, _)| *c == command)
+            .map(|(_, _, name)| name.as_str())
+            .collect();
+        names.join("/")
+    }
+
+    // The name of the first key for a command, if it has one
+    pub fn key_name(&self, command: Command) -> Option<&str> {
+        self.keys.iter().find(|(_, c, _)| *c == command).map(|(_, _, name)| name.as_str())
+    }
+}
+
+// Read the settings file and use its settings from now on. Returns what was
+// wrong with it, with the file name. If it can't be read at all, the settings
+// in use are kept.
+pub fn load() -> Vec<String> {
+    let Some(path) = config_file("config.toml") else {
+        return Vec::new();
+    };
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return vec![format!("{}: {}", path.display(), e)],
+    };
+    match Settings::parse(&text) {
+        Ok((settings, errors)) => {
+            *CURRENT.write().unwrap() = Some(Arc::new(settings));
+            errors.into_iter().map(|e| format!("{}: {}", path.display(), e)).collect()
+        }
+        Err(e) => vec![format!("{}: {}", path.display(), e.trim_end())],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> (Settings, Vec<String>) {
+        Settings::parse(text).unwrap()
+    }
+
+    #[test]
+    fn key_names() {
+        assert_eq!(parse_key("q"), Some('q' as i32));
+        assert_eq!(parse_key("!"), Some('!' as i32));
+        assert_eq!(parse_key("Esc"), Some(27));
+        assert_eq!(parse_key("F1"), Some(KEY_F0 + 1));
+        assert_eq!(parse_key("F12"), Some(KEY_F0 + 12));
+        assert_eq!(parse_key("Shift-F4"), Some(KEY_F0 + 16));
+        assert_eq!(parse_key("Ctrl-R"), Some(18));
+        assert_eq!(parse_key("Ctrl-a"), Some(1));
+        for bad in ["", " ", "\u{e9}", "F0", "F13", "Shift-F13", "Shift-q", "Ctrl-1", "Ctrl-", "Ctrl-AB", "esc"] {
+            assert_eq!(parse_key(bad), None, "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn empty_file_is_the_defaults() {
+        let (settings, errors) = parse("");
+        assert!(errors.is_empty());
+        assert!(settings.text_colors == (COLOR_WHITE, COLOR_BLUE));
+        assert_eq!(settings.command('q' as i32), Some(Command::Quit));
+        assert_eq!(settings.key_name(Command::HexEdit), Some("Shift-F4"));
+        assert_eq!(settings.view_command(27), Some(ViewCommand::Close));
+        assert_eq!(settings.view_key_names(ViewCommand::Close), "q/Esc");
+    }
+
+    #[test]
+    fn colors() {
+        let (settings, errors) = parse("[colors]\ntext = \"green  on black\"\nhighlight = \"pink on blue\"\n");
+        assert!(settings.text_colors == (COLOR_GREEN, COLOR_BLACK));
+        assert!(settings.highlight_colors == (COLOR_YELLOW, COLOR_BLUE));
+        assert_eq!(errors, ["[colors] highlight: expected colours like \"white on blue\", not \"pink on blue\""]);
+        let (_, errors) = parse("[colors]\ntext = 3\nhighlight = \"white\"\n");
+        assert_eq!(errors.len(), 2);
+    }
+
+    #[test]
+    fn keys() {
+        let (settings, errors) = parse("[keys]\nquit = [\"F10\", \"x\"]\nsort = \"Ctrl-S\"\n");
+        assert!(errors.is_empty());
+        assert_eq!(settings.command(KEY_F0 + 10), Some(Command::Quit));
+        assert_eq!(settings.command('x' as i32), Some(Command::Quit));
+        assert_eq!(settings.command('q' as i32), None);
+        assert_eq!(settings.command(19), Some(Command::Sort));
+        assert_eq!(settings.key_name(Command::Quit), Some("F10"));
+    }
+
+    #[test]
+    fn bad_keys() {
+        let (settings, errors) = parse("[keys]\nquit = \"Up\"\ndiff = \"c\"\nshell = \"Hyper-X\"\nview = 3\nsave = \"F2\"\n");
+        assert_eq!(errors, [
+            "[keys] quit: 'Up' has another use that can't be changed",
+            "[keys] compare: 'c' is already the key for diff",
+            "[keys] shell: unknown key 'Hyper-X'",
+            "[keys] view: expected a key name or a list of them, not 3",
+            "[keys] save: can't be changed, only quit, mark, diff, compare, shell, menu, view, edit, hex_edit, toggle_hidden, sort, reload_config",
+        ]);
+        // The first command keeps a key given twice, others keep their defaults
+        assert_eq!(settings.command('c' as i32), Some(Command::Diff));
+        assert_eq!(settings.command(KEY_F0 + 3), Some(Command::View));
+        assert_eq!(settings.key_name(Command::Quit), None);
+    }
+
+    #[test]
+    fn viewer_keys() {
+        let (settings, errors) = parse("[viewer_keys]\nclose = \"x\"\ndown = [\"j\", \"Down\"]\nup = \"f\"\nend = \"Tab\"\n");
+        assert_eq!(errors, [
+            "[viewer_keys] up: 'f' has another use that can't be changed",
+            "[viewer_keys] end: 'Tab' has another use that can't be changed",
+        ]);
+        assert_eq!(settings.view_command('x' as i32), Some(ViewCommand::Close));
+        assert_eq!(settings.view_command('q' as i32), None);
+        assert_eq!(settings.view_command('j' as i32), Some(ViewCommand::Down));
+        assert_eq!(settings.view_key_names(ViewCommand::Down), "j/Down");
+        assert_eq!(settings.view_key_names(ViewCommand::Up), "");
+    }
+
+    #[test]
+    fn viewer_keys_in_use() {
+        // Every key the viewer and its views act on by itself
+        let in_use = "0123456789abefghlmnpstvwFRS+-".chars().map(|c| c.to_string())
+            .chain(["Space", "Tab", "Enter", "PgUp", "PgDn"].map(String::from));
+        for key in in_use {
+            let (settings, errors) = parse(&format!("[viewer_keys]\ndown = \"{}\"\n", key));
+            assert_eq!(errors, [format!("[viewer_keys] down: '{}' has another use that can't be changed", key)]);
+            assert_eq!(settings.view_key_names(ViewCommand::Down), "");
+        }
+        let (settings, errors) = parse("[viewer_keys]\nclose = \"r\"\n");
+        assert!(errors.is_empty());
+        assert_eq!(settings.view_command('r' as i32), Some(ViewCommand::Close));
+    }
+
+    #[test]
+    fn values_out_of_range() {
+        let (settings, errors) = parse("[panel]\nwidth = 90\nsort = \"size\"\n[viewer]\ntab_width = 0\nline_numbers = \"yes\"\n");
+        assert_eq!(settings.panel_width, 50);
+        assert_eq!(settings.sort, Sort::Size);
+        assert_eq!(settings.tab_width, DEFAULT_TAB_WIDTH);
+        assert!(!settings.line_numbers);
+        assert_eq!(errors, [
+            "[panel] width: expected a percentage from 20 to 80, not 90",
+            "[viewer] tab_width: expected a number from 1 to 16, not 0",
+            "[viewer] line_numbers: expected true or false, not \"yes\"",
+        ]);
+    }
+
+    #[test]
+    fn unknown_settings() {
+        let (_, errors) = parse("verbose = true\n[panel]\nheight = 3\n[sound]\n");
+        assert_eq!(errors, ["[panel] height: unknown setting", "verbose: unknown setting"]);
+        assert!(Settings::parse("[panel\n").is_err());
+    }
+
+    #[test]
+    fn sort_cycles() {
+        let mut sort = Sort::Name;
+        for _ in 0..Sort::ALL.len() {
+            sort = sort.next();
+        }
+        assert_eq!(sort, Sort::Name);
+    }
+}
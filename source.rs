@@ -0,0 +1,471 @@
+// Where the viewer's text comes from.
+// Regular files are read in place. Streams that can't seek (a command's output,
+// a FIFO, standard input, a compressed file's decompressed contents) are copied
+// to a temporary file by a background thread as the data arrives, so the
+// viewer can scroll back through everything received so far, up to
+// MAX_STREAM_BYTES. Character devices such as /dev/zero never end, so they
+// are refused.
+
+use crate::decompress::{self, Compression};
+use ncurses::*;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::{FileExt, FileTypeExt, MetadataExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+// Bytes copied from a stream at a time
+const STREAM_CHUNK: usize = 64 * 1024;
+
+// Bytes before the old end of a file compared to tell appending from rewriting
+const TAIL_BYTES: u64 = 4096;
+
+// A stream or a compressed file's contents are kept up to this size, so they can't fill the disk
+const MAX_STREAM_BYTES: u64 = 4 * 1024 * 1024 * 1024;
+
+// How often the wait for a FIFO's writer checks whether the viewer was closed (milliseconds)
+const FIFO_POLL_MS: i32 = 200;
+
+pub type SourceReader = BufReader<Box<dyn Source>>;
+
+// What happened to a source since it was last polled
+pub enum Change {
+    Unchanged,
+    Grew, // Data was appended
+    Replaced, // Truncated, rewritten or rotated: reload to see the new contents
+}
+
+pub trait Source: Read + Seek {
+    // Shown in the viewer's title
+    fn title(&self) -> String;
+
+    // File name of the text, for choosing syntax highlighting
+    fn text_path(&self) -> Option<PathBuf>;
+
+    // Number of bytes that can be read now
+    fn len(&self) -> io::Result<u64>;
+
+    // False while a stream may still deliver more data by itself
+    fn is_complete(&self) -> bool;
+
+    // The stream was cut off at MAX_STREAM_BYTES
+    fn is_truncated(&self) -> bool;
+
+    // Look for changes since the last poll (or reload)
+    fn poll(&mut self, w_debug: WINDOW) -> io::Result<Change>;
+
+    // Start again with the current contents after a Change::Replaced
+    fn reload(&mut self, w_debug: WINDOW) -> io::Result<()>;
+}
+
+// Open a path for viewing: a regular file directly, a FIFO and the like as a stream
+pub fn open_path(w_debug: WINDOW, path: &Path) -> io::Result<Box<dyn Source>> {
+    let file_type = fs::metadata(path)?.file_type();
+    if file_type.is_file() {
+        Ok(Box::new(FileSource::open(w_debug, path)?))
+    } else if file_type.is_char_device() {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "a device, which can't be viewed"))
+    } else {
+        // A FIFO has to wait for a writer, so it is opened in the background
+        let owned_path = path.to_path_buf();
+        let name = path.display().to_string();
+        Ok(Box::new(StreamSource::spawn(name, None, move |cancel| {
+            let file = match file_type.is_fifo() {
+                true => open_fifo(&owned_path, cancel)?,
+                false => File::open(owned_path)?,
+            };
+            Ok(Box::new(file) as Box<dyn Read + Send>)
+        })?))
+    }
+}
+
+// Open a FIFO and wait for a writer, giving up when `cancel` is set.
+// Opening it without O_NONBLOCK would block until a writer comes, even after the viewer closed.
+fn open_fifo(path: &Path, cancel: &AtomicBool) -> io::Result<File> {
+    let file = OpenOptions::new().read(true).custom_flags(libc::O_NONBLOCK).open(path)?;
+    let fd = file.as_raw_fd();
+    // Readable once a writer has written something or come and gone
+    loop {
+        if cancel.load(Ordering::Acquire) {
+            return Err(io::Error::new(io::ErrorKind::Interrupted, "closed while waiting for a writer"));
+        }
+        let mut poll_fd = libc::pollfd { fd, events: libc::POLLIN, revents: 0 };
+        match unsafe { libc::poll(&mut poll_fd, 1, FIFO_POLL_MS) } {
+            0 => {}
+            n if n > 0 => break,
+            _ => {
+                let e = io::Error::last_os_error();
+                if e.kind() != io::ErrorKind::Interrupted {
+                    return Err(e);
+                }
+            }
+        }
+    }
+    // Blocking reads from here on
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags < 0 || unsafe { libc::fcntl(fd, libc::F_SETFL, flags & !libc::O_NONBLOCK) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(file)
+}
+
+// Identity, size and modification time of a file.
+// Used to notice when a file grows, shrinks or is replaced.
+#[derive(Clone, Copy, PartialEq)]
+pub struct FileStamp {
+    dev: u64,
+    ino: u64,
+    pub size: u64,
+    mtime: (i64, i64), // Seconds, nanoseconds
+}
+
+impl FileStamp {
+    pub fn of(metadata: &fs::Metadata) -> Self {
+        FileStamp {
+            dev: metadata.dev(),
+            ino: metadata.ino(),
+            size: metadata.len(),
+            mtime: (metadata.mtime(), metadata.mtime_nsec()),
+        }
+    }
+
+    // Same file (not necessarily the same contents)
+    pub fn same_file(&self, other: &FileStamp) -> bool {
+        self.dev == other.dev && self.ino == other.ino
+    }
+}
+
+pub struct FileSource {
+    path: PathBuf, // Path of the file on disk
+    file: File, // The file itself
+    stamp: FileStamp, // Identity and size of the file on disk
+    tail: Vec<u8>, // The last bytes up to stamp.size, as they were read
+    decompressed: Option<(Compression, StreamSource)>, // How the file is compressed, and its contents
+}
+
+impl FileSource {
+    // Open a file for viewing, decompressing it if needed
+    pub fn open(w_debug: WINDOW, path: &Path) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+        let stamp = FileStamp::of(&file.metadata()?);
+        let magic = decompress::read_magic(&mut file)?;
+        let mut source = FileSource {
+            path: path.to_path_buf(),
+            file,
+            stamp,
+            tail: Vec::new(),
+            decompressed: None,
+        };
+        // A file cut short meanwhile has no tail to compare: its next growth is a rewrite
+        source.tail = source.read_tail(stamp.size).unwrap_or_default();
+        let Some(compression) = Compression::detect(path, &magic) else {
+            return Ok(source);
+        };
+
+        let _ = waddstr(w_debug, &format!("Decompressing {} ({})\n", path.display(), compression.name()));
+        wrefresh(w_debug);
+        match decompress::decompressed(compression, source.file.try_clone()?) {
+            Ok(decoder) => {
+                // The rest is decompressed in the background, and shown as it arrives
+                let name = path.display().to_string();
+                let stream = StreamSource::spawn(name, None, move |_| Ok(decoder))?;
+                source.decompressed = Some((compression, stream));
+            }
+            Err(e) => {
+                // Only the extension said it was compressed: show it as it is
+                let _ = waddstr(w_debug, &format!("Decompress failed, showing raw file: {}\n", e));
+                source.file.seek(SeekFrom::Start(0))?;
+            }
+        }
+        Ok(source)
+    }
+}
+
+impl FileSource {
+    // The last bytes of the file before `end`
+    fn read_tail(&self, end: u64) -> io::Result<Vec<u8>> {
+        let start = end.saturating_sub(TAIL_BYTES);
+        let mut tail = vec![0u8; (end - start) as usize];
+        self.file.read_exact_at(&mut tail, start)?;
+        Ok(tail)
+    }
+
+    // Whether the file only grew: what was there before is unchanged,
+    // as far as its last bytes tell
+    fn only_appended(&self) -> bool {
+        self.read_tail(self.stamp.size).is_ok_and(|tail| tail == self.tail)
+    }
+}
+
+impl Read for FileSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match &mut self.decompressed {
+            Some((_, stream)) => stream.read(buf),
+            None => self.file.read(buf),
+        }
+    }
+}
+
+impl Seek for FileSource {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match &mut self.decompressed {
+            Some((_, stream)) => stream.seek(pos),
+            None => self.file.seek(pos),
+        }
+    }
+}
+
+impl Source for FileSource {
+    fn title(&self) -> String {
+        match &self.decompressed {
+            Some((c, stream)) if !stream.is_complete() => format!("{} ({}, decompressing)", self.path.display(), c.name()),
+            Some((c, _)) => format!("{} ({})", self.path.display(), c.name()),
+            None => self.path.display().to_string(),
+        }
+    }
+
+    // A compressed file's name without the compression extension
+    fn text_path(&self) -> Option<PathBuf> {
+        match self.decompressed {
+            Some(_) => Some(self.path.with_extension("")),
+            None => Some(self.path.clone()),
+        }
+    }
+
+    fn len(&self) -> io::Result<u64> {
+        match &self.decompressed {
+            Some((_, stream)) => stream.len(),
+            None => Ok(self.file.metadata()?.len()),
+        }
+    }
+
+    fn is_complete(&self) -> bool {
+        self.decompressed.as_ref().is_none_or(|(_, stream)| stream.is_complete())
+    }
+
+    fn is_truncated(&self) -> bool {
+        self.decompressed.as_ref().is_some_and(|(_, stream)| stream.is_truncated())
+    }
+
+    // Check the file for appended data, truncation, rewriting or rotation.
+    // The open file is kept until reload(), so a rotated file can still be read.
+    fn poll(&mut self, w_debug: WINDOW) -> io::Result<Change> {
+        // The path may be missing for a moment while a log is rotated
+        let Ok(metadata) = fs::metadata(&self.path) else {
+            return Ok(Change::Unchanged);
+        };
+        let current = FileStamp::of(&metadata);
+        if current == self.stamp {
+            match &mut self.decompressed {
+                // More of it decompressed
+                Some((_, stream)) => stream.poll(w_debug),
+                None => Ok(Change::Unchanged),
+            }
+        } else if current.same_file(&self.stamp) && current.size > self.stamp.size && self.decompressed.is_none()
+            && self.only_appended() {
+            self.stamp = current;
+            self.tail = self.read_tail(current.size).unwrap_or_default();
+            Ok(Change::Grew)
+        } else {
+            // A compressed file has to be decompressed again after any change
+            Ok(Change::Replaced)
+        }
+    }
+
+    // Open the file at the same path again
+    fn reload(&mut self, w_debug: WINDOW) -> io::Result<()> {
+        let _ = waddstr(w_debug, &format!("RELOAD: {} size:{}\n", self.path.display(), fs::metadata(&self.path)?.len()));
+        wrefresh(w_debug);
+        *self = FileSource::open(w_debug, &self.path)?;
+        Ok(())
+    }
+}
+
+pub struct StreamSource {
+    name: String, // Shown in the title
+    spool: File, // Everything received so far
+    pos: u64, // Read position in the spool
+    received: Arc<AtomicU64>, // Bytes written to the spool by the copying thread
+    finished: Arc<AtomicBool>, // The stream has ended
+    truncated: Arc<AtomicBool>, // The stream was cut off at MAX_STREAM_BYTES
+    cancel: Arc<AtomicBool>, // Tells the copying thread to stop, when the viewer closes
+    error: Arc<Mutex<Option<String>>>, // Why the copying stopped early, until it is reported
+    polled_len: u64, // Bytes received at the last poll
+    child: Option<Child>, // Command writing the stream, killed when the viewer closes
+}
+
+impl StreamSource {
+    // Copy at most MAX_STREAM_BYTES from the reader returned by `open` in a
+    // background thread. `open` is given the flag set when the viewer closes.
+    pub fn spawn<F>(name: String, child: Option<Child>, open: F) -> io::Result<Self>
+    where
+        F: FnOnce(&AtomicBool) -> io::Result<Box<dyn Read + Send>> + Send + 'static,
+    {
+        let spool = tempfile::tempfile()?;
+        let writer = spool.try_clone()?;
+        let received = Arc::new(AtomicU64::new(0));
+        let finished = Arc::new(AtomicBool::new(false));
+        let truncated = Arc::new(AtomicBool::new(false));
+        let cancel = Arc::new(AtomicBool::new(false));
+        let error = Arc::new(Mutex::new(None));
+
+        let (thread_received, thread_finished, thread_truncated) = (received.clone(), finished.clone(), truncated.clone());
+        let (thread_cancel, thread_error) = (cancel.clone(), error.clone());
+        std::thread::spawn(move || {
+            let mut offset = 0;
+            let stop = |message: String| *thread_error.lock().unwrap() = Some(message);
+            match open(&thread_cancel) {
+                Ok(mut input) => {
+                    let mut buf = vec![0u8; STREAM_CHUNK];
+                    // Stop at the end of the stream, on a read or spool error, or when cancelled
+                    while !thread_cancel.load(Ordering::Acquire) {
+                        if offset >= MAX_STREAM_BYTES {
+                            stop(format!("Stopped after {} MiB", offset / 1024 / 1024));
+                            thread_truncated.store(true, Ordering::Release);
+                            break;
+                        }
+                        let n = match input.read(&mut buf[..STREAM_CHUNK.min((MAX_STREAM_BYTES - offset) as usize)]) {
+                            Ok(0) => break,
+                            Ok(n) => n,
+                            Err(e) => {
+                                stop(format!("Read error, showing what came before: {}", e));
+                                break;
+                            }
+                        };
+                        if let Err(e) = writer.write_all_at(&buf[..n], offset) {
+                            stop(format!("Can't keep more: {}", e));
+                            break;
+                        }
+                        offset += n as u64;
+                        thread_received.store(offset, Ordering::Release);
+                    }
+                }
+                Err(e) => {
+                    // Show why there is nothing to see
+                    let message = format!("{}\n", e);
+                    if writer.write_all_at(message.as_bytes(), 0).is_ok() {
+                        thread_received.store(message.len() as u64, Ordering::Release);
+                    }
+                }
+            }
+            thread_finished.store(true, Ordering::Release);
+        });
+
+        Ok(StreamSource {
+            name,
+            spool,
+            pos: 0,
+            received,
+            finished,
+            truncated,
+            cancel,
+            error,
+            polled_len: 0,
+            child,
+        })
+    }
+
+    // Run a shell command in `dir` and view its output (stdout and stderr)
+    pub fn command(command: &str, dir: &Path) -> io::Result<Self> {
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(format!("exec 2>&1; {}", command))
+            .current_dir(dir)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let stdout = child.stdout.take().unwrap();
+        StreamSource::spawn(command.to_string(), Some(child), move |_| {
+            Ok(Box::new(stdout) as Box<dyn Read + Send>)
+        })
+    }
+}
+
+impl Read for StreamSource {
+    // Reads stop at the data received so far, like at the end of a growing file
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.received.load(Ordering::Acquire).saturating_sub(self.pos);
+        let want = buf.len().min(available as usize);
+        if want == 0 {
+            return Ok(0);
+        }
+        let n = self.spool.read_at(&mut buf[..want], self.pos)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for StreamSource {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new_pos = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::Current(d) => self.pos.checked_add_signed(d),
+            SeekFrom::End(d) => self.received.load(Ordering::Acquire).checked_add_signed(d),
+        };
+        match new_pos {
+            Some(n) => {
+                self.pos = n;
+                Ok(n)
+            }
+            None => Err(io::Error::new(io::ErrorKind::InvalidInput, "seek before start of stream")),
+        }
+    }
+}
+
+impl Source for StreamSource {
+    fn title(&self) -> String {
+        if self.is_complete() {
+            self.name.clone()
+        } else {
+            format!("{} (receiving)", self.name)
+        }
+    }
+
+    fn text_path(&self) -> Option<PathBuf> {
+        None
+    }
+
+    fn len(&self) -> io::Result<u64> {
+        Ok(self.received.load(Ordering::Acquire))
+    }
+
+    fn is_complete(&self) -> bool {
+        self.finished.load(Ordering::Acquire)
+    }
+
+    fn is_truncated(&self) -> bool {
+        self.truncated.load(Ordering::Acquire)
+    }
+
+    fn poll(&mut self, w_debug: WINDOW) -> io::Result<Change> {
+        if let Some(message) = self.error.lock().unwrap().take() {
+            let _ = waddstr(w_debug, &format!("{}: {}\n", self.name, message));
+            wrefresh(w_debug);
+        }
+        let len = self.len()?;
+        if len > self.polled_len {
+            self.polled_len = len;
+            Ok(Change::Grew)
+        } else {
+            Ok(Change::Unchanged)
+        }
+    }
+
+    // Everything received is already there
+    fn reload(&mut self, _w_debug: WINDOW) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for StreamSource {
+    fn drop(&mut self) {
+        self.cancel.store(true, Ordering::Release);
+        if let Some(child) = &mut self.child {
+            child.kill().ok();
+            child.wait().ok();
+        }
+    }
+}
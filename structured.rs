@@ -0,0 +1,89 @@
+// Alternative views of a whole file in the viewer:
+// a collapsible tree for JSON and a table for CSV and TSV.
+
+use crate::json_tree::JsonTree;
+use crate::table_view::TableView;
+use ncurses::*;
+use std::path::Path;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Format {
+    Json,
+    Csv,
+    Tsv,
+}
+
+impl Format {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Format::Json => "JSON",
+            Format::Csv => "CSV",
+            Format::Tsv => "TSV",
+        }
+    }
+
+    // Choose a format from the file name
+    pub fn detect(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "json" => Some(Format::Json),
+            "csv" => Some(Format::Csv),
+            "tsv" | "tab" => Some(Format::Tsv),
+            _ => None,
+        }
+    }
+
+    // Parse the text into a view. The error says why the text doesn't fit the format.
+    pub fn open(&self, text: &str) -> Result<Box<dyn StructuredView>, String> {
+        Ok(match self {
+            Format::Json => Box::new(JsonTree::parse(text)?),
+            Format::Csv => Box::new(TableView::parse(text, ',')?),
+            Format::Tsv => Box::new(TableView::parse(text, '\t')?),
+        })
+    }
+}
+
+pub trait StructuredView {
+    // Draw the visible part into the viewer's text area
+    fn draw(&mut self, window: WINDOW, tab_width: usize);
+
+    // Position shown on the status line
+    fn status(&self) -> String;
+
+    // Move around or change what is shown
+    fn handle_key(&mut self, ch: i32, page_rows: usize);
+}
+
+// Selected row in a list taller than the window
+#[derive(Default)]
+pub struct ListCursor {
+    pub selected: usize,
+    pub top: usize, // First visible row
+}
+
+impl ListCursor {
+    // Move with the arrow, page and Home/End keys. Returns false for other keys.
+    pub fn handle_key(&mut self, ch: i32, len: usize, page_rows: usize) -> bool {
+        let last = len.saturating_sub(1);
+        match ch {
+            KEY_UP => self.selected = self.selected.saturating_sub(1),
+            KEY_DOWN => self.selected = (self.selected + 1).min(last),
+            KEY_PPAGE => self.selected = self.selected.saturating_sub(page_rows.max(1)),
+            KEY_NPAGE => self.selected = (self.selected + page_rows.max(1)).min(last),
+            KEY_HOME => self.selected = 0,
+            KEY_END => self.selected = last,
+            _ => return false,
+        }
+        true
+    }
+
+    // Scroll so the selected row is on screen, after the list or window changed
+    pub fn scroll_into_view(&mut self, len: usize, page_rows: usize) {
+        self.selected = self.selected.min(len.saturating_sub(1));
+        let page_rows = page_rows.max(1);
+        if self.selected < self.top {
+            self.top = self.selected;
+        } else if self.selected >= self.top + page_rows {
+            self.top = self.selected + 1 - page_rows;
+        }
+    }
+}
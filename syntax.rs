@@ -0,0 +1,540 @@
+// Syntax highlighting for the viewer, one row at a time.
+// Comments and strings can span lines, so highlighting a row starts from the
+// State left at the end of the row before it. The viewer remembers the state
+// at each row start so it can highlight rows again when scrolling back.
+
+use crate::ansi::Style;
+use ncurses::*;
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Language {
+    Rust,
+    C,
+    Python,
+    Shell,
+    Json,
+    Yaml,
+    Toml,
+    Markdown,
+}
+
+// What is still open at the start of a row
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum State {
+    Normal,
+    Comment(u32), // Inside a block comment, nested this deep
+    Str { close: &'static str, escapes: bool }, // Inside a string ending with `close`
+    Fence, // Inside a Markdown code block
+}
+
+// What a piece of text is
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+    Plain,
+    Keyword,
+    Type,
+    Str,
+    Number,
+    Comment,
+    Key, // Object key or setting name
+    Heading,
+    Code, // Code in Markdown
+}
+
+impl Kind {
+    // How the kind is drawn on the viewer's background
+    pub fn style(&self) -> Style {
+        match self {
+            Kind::Plain => Style::default(),
+            Kind::Keyword => Style::colored(COLOR_YELLOW, true),
+            Kind::Type => Style::colored(COLOR_GREEN, true),
+            Kind::Str | Kind::Code => Style::colored(COLOR_GREEN, false),
+            Kind::Number => Style::colored(COLOR_CYAN, true),
+            Kind::Comment => Style::colored(COLOR_CYAN, false),
+            Kind::Key => Style::colored(COLOR_YELLOW, false),
+            Kind::Heading => Style::bold(),
+        }
+    }
+}
+
+const RUST_KEYWORDS: &[&str] = &[
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum",
+    "extern", "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move",
+    "mut", "pub", "ref", "return", "self", "Self", "static", "struct", "super", "trait", "true",
+    "type", "unsafe", "use", "where", "while",
+];
+
+const C_KEYWORDS: &[&str] = &[
+    "auto", "break", "case", "char", "const", "continue", "default", "do", "double", "else",
+    "enum", "extern", "float", "for", "goto", "if", "inline", "int", "long", "register",
+    "restrict", "return", "short", "signed", "sizeof", "static", "struct", "switch", "typedef",
+    "union", "unsigned", "void", "volatile", "while", "NULL", "bool", "true", "false",
+];
+
+const PYTHON_KEYWORDS: &[&str] = &[
+    "False", "None", "True", "and", "as", "assert", "async", "await", "break", "class",
+    "continue", "def", "del", "elif", "else", "except", "finally", "for", "from", "global", "if",
+    "import", "in", "is", "lambda", "nonlocal", "not", "or", "pass", "raise", "return", "self",
+    "try", "while", "with", "yield",
+];
+
+const SHELL_KEYWORDS: &[&str] = &[
+    "case", "do", "done", "elif", "else", "esac", "export", "fi", "for", "function", "if", "in",
+    "local", "readonly", "return", "select", "then", "until", "while",
+];
+
+const VALUE_KEYWORDS: &[&str] = &["true", "false", "null", "yes", "no", "on", "off", "inf", "nan"];
+
+impl Language {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Language::Rust => "Rust",
+            Language::C => "C",
+            Language::Python => "Python",
+            Language::Shell => "Shell",
+            Language::Json => "JSON",
+            Language::Yaml => "YAML",
+            Language::Toml => "TOML",
+            Language::Markdown => "Markdown",
+        }
+    }
+
+    // Choose a language from the file name, or else from a #! line
+    pub fn detect(path: Option<&Path>, first_line: &str) -> Option<Self> {
+        let extension = path.and_then(|p| p.extension()).and_then(|e| e.to_str()).unwrap_or("");
+        let language = match extension.to_ascii_lowercase().as_str() {
+            "rs" => Some(Language::Rust),
+            "c" | "h" | "cc" | "cpp" | "cxx" | "hpp" | "hh" => Some(Language::C),
+            "py" | "pyw" => Some(Language::Python),
+            "sh" | "bash" | "zsh" | "ksh" => Some(Language::Shell),
+            "json" => Some(Language::Json),
+            "yaml" | "yml" => Some(Language::Yaml),
+            "toml" => Some(Language::Toml),
+            "md" | "markdown" => Some(Language::Markdown),
+            _ => None,
+        };
+        if language.is_some() {
+            return language;
+        }
+        // #!/usr/bin/env python3, #!/bin/sh, ...
+        let interpreter = first_line.strip_prefix("#!")?.split_whitespace()
+            .map(|word| word.rsplit('/').next().unwrap_or(word))
+            .find(|word| *word != "env" && !word.starts_with('-'))?;
+        if interpreter.starts_with("python") {
+            Some(Language::Python)
+        } else if ["sh", "bash", "dash", "zsh", "ksh"].contains(&interpreter) {
+            Some(Language::Shell)
+        } else {
+            None
+        }
+    }
+
+    fn keywords(&self) -> &'static [&'static str] {
+        match self {
+            Language::Rust => RUST_KEYWORDS,
+            Language::C => C_KEYWORDS,
+            Language::Python => PYTHON_KEYWORDS,
+            Language::Shell => SHELL_KEYWORDS,
+            Language::Json | Language::Yaml | Language::Toml => VALUE_KEYWORDS,
+            Language::Markdown => &[],
+        }
+    }
+
+    fn line_comment(&self) -> Option<&'static str> {
+        match self {
+            Language::Rust | Language::C => Some("//"),
+            Language::Python | Language::Shell | Language::Yaml | Language::Toml => Some("#"),
+            Language::Json | Language::Markdown => None,
+        }
+    }
+
+    // Split a row into highlighted pieces, given the state at its start.
+    // Returns the pieces and the state at the start of the next row.
+    pub fn highlight(&self, state: State, row: &str) -> (Vec<(Kind, String)>, State) {
+        let mut out = Pieces::default();
+        let state = match self {
+            Language::Markdown => markdown(state, row, &mut out),
+            _ => self.code(state, row, &mut out),
+        };
+        (out.pieces, state)
+    }
+
+    fn code(&self, mut state: State, row: &str, out: &mut Pieces) -> State {
+        let mut i = 0;
+        let line_start = row.len() - row.trim_start().len();
+        while i < row.len() {
+            let rest = &row[i..];
+            match state {
+                State::Comment(depth) => {
+                    let (n, depth) = block_comment_len(rest, depth, *self == Language::Rust);
+                    out.push(Kind::Comment, &rest[..n]);
+                    i += n;
+                    state = if depth == 0 { State::Normal } else { State::Comment(depth) };
+                    continue;
+                }
+                State::Str { close, escapes } => {
+                    let (n, closed) = string_len(rest, close, escapes);
+                    out.push(Kind::Str, &rest[..n]);
+                    i += n;
+                    if closed {
+                        state = State::Normal;
+                    }
+                    continue;
+                }
+                State::Normal | State::Fence => {}
+            }
+
+            let c = rest.chars().next().unwrap();
+            let prev = row[..i].chars().next_back();
+            let word_start = !prev.is_some_and(|p| p.is_alphanumeric() || p == '_');
+
+            // Comments
+            if let Some(prefix) = self.line_comment()
+                && rest.starts_with(prefix)
+                && (*self != Language::Shell || prev.is_none_or(char::is_whitespace))
+            {
+                out.push(Kind::Comment, rest);
+                break;
+            }
+            if matches!(self, Language::Rust | Language::C) && rest.starts_with("/*") {
+                state = State::Comment(1);
+                out.push(Kind::Comment, "/*");
+                i += 2;
+                continue;
+            }
+            // C preprocessor lines
+            if *self == Language::C && c == '#' && i == line_start {
+                out.push(Kind::Keyword, rest);
+                break;
+            }
+            // TOML table headers
+            if *self == Language::Toml && c == '[' && i == line_start {
+                out.push(Kind::Heading, rest);
+                break;
+            }
+
+            // Strings
+            if let Some((open, close, escapes)) = self.string_start(rest, word_start) {
+                let (n, closed) = string_len(&rest[open.len()..], close, escapes);
+                let n = open.len() + n;
+                let after = row[i + n..].trim_start();
+                // A quoted key is followed by a colon
+                let is_key = closed && match self {
+                    Language::Json | Language::Yaml => after.starts_with(':'),
+                    Language::Toml => after.starts_with('='),
+                    _ => false,
+                };
+                out.push(if is_key { Kind::Key } else { Kind::Str }, &rest[..n]);
+                i += n;
+                if !closed && self.multiline_string(close) {
+                    state = State::Str { close, escapes };
+                }
+                continue;
+            }
+
+            // Numbers
+            if c.is_ascii_digit() && word_start {
+                let n = rest.find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '.')).unwrap_or(rest.len());
+                out.push(Kind::Number, &rest[..n]);
+                i += n;
+                continue;
+            }
+
+            // Words
+            if c.is_alphabetic() || c == '_' || (c == '$' && *self == Language::Shell) {
+                let n = c.len_utf8() + rest[c.len_utf8()..]
+                    .find(|c: char| !(c.is_alphanumeric() || c == '_' || (c == '-' && self.dashed_keys())))
+                    .unwrap_or(rest.len() - c.len_utf8());
+                let word = &rest[..n];
+                let after = row[i + n..].trim_start();
+                let kind = if self.dashed_keys() && i == line_start_of_key(row)
+                    && (after.starts_with(':') || (*self == Language::Toml && after.starts_with('=')))
+                {
+                    Kind::Key
+                } else if self.keywords().contains(&word) {
+                    Kind::Keyword
+                } else if c == '$' {
+                    Kind::Key // Shell variable
+                } else if *self == Language::Rust && after.starts_with('!') {
+                    Kind::Keyword // Macro
+                } else if *self == Language::Rust && c.is_uppercase() {
+                    Kind::Type
+                } else {
+                    Kind::Plain
+                };
+                out.push(kind, word);
+                i += n;
+                continue;
+            }
+
+            // Anything else is plain text
+            out.push(Kind::Plain, &rest[..c.len_utf8()]);
+            i += c.len_utf8();
+        }
+        state
+    }
+
+    // Languages where settings are bare words at the start of a line
+    fn dashed_keys(&self) -> bool {
+        matches!(self, Language::Yaml | Language::Toml)
+    }
+
+    // If a string starts here: its opening, its closing and whether backslash escapes apply
+    fn string_start(&self, rest: &str, word_start: bool) -> Option<(&'static str, &'static str, bool)> {
+        const RAW: [(&str, &str); 4] = [("r###\"", "\"###"), ("r##\"", "\"##"), ("r#\"", "\"#"), ("r\"", "\"")];
+        match self {
+            Language::Rust => {
+                if word_start && let Some(&(open, close)) = RAW.iter().find(|(open, _)| rest.starts_with(open)) {
+                    return Some((open, close, false));
+                }
+                if rest.starts_with('"') {
+                    return Some(("\"", "\"", true));
+                }
+                // 'a' and '\n' are characters, 'a without a closing quote is a lifetime
+                let mut chars = rest.chars();
+                if chars.next() == Some('\'') {
+                    let quoted = match chars.next() {
+                        Some('\\') => true,
+                        Some(_) => chars.next() == Some('\''),
+                        None => false,
+                    };
+                    if quoted {
+                        return Some(("'", "'", true));
+                    }
+                }
+                None
+            }
+            Language::Python => {
+                // Prefixes like r, b and f are left plain
+                for (quote, escapes) in [("\"\"\"", true), ("'''", true), ("\"", true), ("'", true)] {
+                    if rest.starts_with(quote) {
+                        return Some((quote, quote, escapes));
+                    }
+                }
+                None
+            }
+            Language::Toml => {
+                for (quote, escapes) in [("\"\"\"", true), ("'''", false), ("\"", true), ("'", false)] {
+                    if rest.starts_with(quote) {
+                        return Some((quote, quote, escapes));
+                    }
+                }
+                None
+            }
+            Language::Shell | Language::Yaml => match rest.chars().next() {
+                Some('"') => Some(("\"", "\"", true)),
+                Some('\'') if *self == Language::Yaml || word_start => Some(("'", "'", false)),
+                _ => None,
+            },
+            Language::C | Language::Json => match rest.chars().next() {
+                Some('"') => Some(("\"", "\"", true)),
+                Some('\'') if *self == Language::C => Some(("'", "'", true)),
+                _ => None,
+            },
+            Language::Markdown => None,
+        }
+    }
+
+    // Whether a string with this closing quote may continue on the next line
+    fn multiline_string(&self, close: &str) -> bool {
+        match self {
+            Language::Rust => close != "'",
+            Language::Python | Language::Toml => close.len() == 3,
+            Language::Shell | Language::Yaml => true,
+            Language::C | Language::Json | Language::Markdown => false,
+        }
+    }
+}
+
+// Where a YAML or TOML key would start: after the indent and any "- "
+fn line_start_of_key(row: &str) -> usize {
+    let trimmed = row.trim_start();
+    let indent = row.len() - trimmed.len();
+    match trimmed.strip_prefix("- ") {
+        Some(item) => indent + 2 + (item.len() - item.trim_start().len()),
+        None => indent,
+    }
+}
+
+// Length of a block comment's text up to and including its end, and the
+// nesting depth left. Rust comments nest, C comments don't.
+fn block_comment_len(text: &str, mut depth: u32, nested: bool) -> (usize, u32) {
+    let mut i = 0;
+    while i < text.len() {
+        if text[i..].starts_with("*/") {
+            i += 2;
+            depth -= 1;
+            if depth == 0 {
+                return (i, 0);
+            }
+        } else if nested && text[i..].starts_with("/*") {
+            i += 2;
+            depth += 1;
+        } else {
+            i += text[i..].chars().next().unwrap().len_utf8();
+        }
+    }
+    (text.len(), depth)
+}
+
+// Length of a string's text up to and including `close`, and whether it was closed
+fn string_len(text: &str, close: &str, escapes: bool) -> (usize, bool) {
+    let mut chars = text.char_indices();
+    while let Some((i, c)) = chars.next() {
+        if escapes && c == '\\' {
+            chars.next();
+        } else if text[i..].starts_with(close) {
+            return (i + close.len(), true);
+        }
+    }
+    (text.len(), false)
+}
+
+// Markdown: headings, quotes, list markers, code blocks and `code`
+fn markdown(state: State, row: &str, out: &mut Pieces) -> State {
+    let trimmed = row.trim_start();
+    if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+        out.push(Kind::Code, row);
+        return if state == State::Fence { State::Normal } else { State::Fence };
+    }
+    if state == State::Fence {
+        out.push(Kind::Code, row);
+        return State::Fence;
+    }
+    if trimmed.starts_with('#') {
+        out.push(Kind::Heading, row);
+        return State::Normal;
+    }
+    if trimmed.starts_with('>') {
+        out.push(Kind::Comment, row);
+        return State::Normal;
+    }
+
+    let indent = row.len() - trimmed.len();
+    let marker_len = if ["- ", "* ", "+ "].iter().any(|m| trimmed.starts_with(m)) {
+        1
+    } else {
+        // 1. and 1)
+        let digits = trimmed.find(|c: char| !c.is_ascii_digit()).unwrap_or(0);
+        match trimmed[digits..].chars().next() {
+            Some('.' | ')') if digits > 0 => digits + 1,
+            _ => 0,
+        }
+    };
+    out.push(Kind::Plain, &row[..indent]);
+    out.push(Kind::Keyword, &trimmed[..marker_len]);
+
+    // Inline `code`
+    let mut rest = &trimmed[marker_len..];
+    while let Some(start) = rest.find('`') {
+        let Some(len) = rest[start + 1..].find('`') else {
+            break;
+        };
+        out.push(Kind::Plain, &rest[..start]);
+        out.push(Kind::Code, &rest[start..start + len + 2]);
+        rest = &rest[start + len + 2..];
+    }
+    out.push(Kind::Plain, rest);
+    State::Normal
+}
+
+// Pieces of a row, with neighbours of the same kind joined
+#[derive(Default)]
+struct Pieces {
+    pieces: Vec<(Kind, String)>,
+}
+
+impl Pieces {
+    fn push(&mut self, kind: Kind, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        match self.pieces.last_mut() {
+            Some((last, s)) if *last == kind => s.push_str(text),
+            _ => self.pieces.push((kind, text.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Highlight rows one after another, as the viewer does
+    fn highlight(language: Language, rows: &[&str]) -> Vec<Vec<(Kind, String)>> {
+        let mut state = State::Normal;
+        rows.iter().map(|row| {
+            let (pieces, next) = language.highlight(state, row);
+            state = next;
+            pieces
+        }).collect()
+    }
+
+    fn pieces(list: &[(Kind, &str)]) -> Vec<(Kind, String)> {
+        list.iter().map(|&(kind, text)| (kind, text.to_string())).collect()
+    }
+
+    #[test]
+    fn block_comment_across_rows() {
+        let rows = highlight(Language::C, &["int a; /* one", "two", "three */ int b;"]);
+        assert_eq!(rows[0], pieces(&[(Kind::Keyword, "int"), (Kind::Plain, " a; "), (Kind::Comment, "/* one")]));
+        assert_eq!(rows[1], pieces(&[(Kind::Comment, "two")]));
+        assert_eq!(rows[2], pieces(&[(Kind::Comment, "three */"), (Kind::Plain, " "), (Kind::Keyword, "int"), (Kind::Plain, " b;")]));
+    }
+
+    #[test]
+    fn nested_comments_in_rust_only() {
+        let (_, state) = Language::Rust.highlight(State::Normal, "/* a /* b */");
+        assert_eq!(state, State::Comment(1));
+        let (_, state) = Language::C.highlight(State::Normal, "/* a /* b */");
+        assert_eq!(state, State::Normal);
+    }
+
+    #[test]
+    fn escaped_quotes() {
+        let rows = highlight(Language::Rust, &[r#"let s = "a \"b\" c\\"; x"#]);
+        assert_eq!(rows[0], pieces(&[(Kind::Keyword, "let"), (Kind::Plain, " s = "),
+            (Kind::Str, r#""a \"b\" c\\""#), (Kind::Plain, "; x")]));
+        // A raw string has no escapes and goes on to the next row
+        let rows = highlight(Language::Rust, &[r#"r"a\"#, r#"b" c"#]);
+        assert_eq!(rows[0], pieces(&[(Kind::Str, r#"r"a\"#)]));
+        assert_eq!(rows[1], pieces(&[(Kind::Str, r#"b""#), (Kind::Plain, " c")]));
+    }
+
+    #[test]
+    fn keyword_prefix_is_a_word() {
+        let rows = highlight(Language::Python, &["format if iffy _if"]);
+        assert_eq!(rows[0], pieces(&[(Kind::Plain, "format "), (Kind::Keyword, "if"), (Kind::Plain, " iffy _if")]));
+    }
+
+    #[test]
+    fn multibyte_next_to_tokens() {
+        let rows = highlight(Language::Rust, &["é\"ü\"ß/*ä*/ñ//ö", "'é' 'a"]);
+        assert_eq!(rows[0], pieces(&[(Kind::Plain, "é"), (Kind::Str, "\"ü\""), (Kind::Plain, "ß"),
+            (Kind::Comment, "/*ä*/"), (Kind::Plain, "ñ"), (Kind::Comment, "//ö")]));
+        // 'é' is a character, 'a a lifetime
+        assert_eq!(rows[1], pieces(&[(Kind::Str, "'é'"), (Kind::Plain, " 'a")]));
+        // A comment ending on a multibyte character
+        assert_eq!(Language::C.highlight(State::Comment(1), "€"), (pieces(&[(Kind::Comment, "€")]), State::Comment(1)));
+    }
+
+    #[test]
+    fn keys_and_markdown() {
+        let rows = highlight(Language::Json, &[r#"{"name": "x", "n": 12}"#]);
+        assert_eq!(rows[0], pieces(&[(Kind::Plain, "{"), (Kind::Key, r#""name""#), (Kind::Plain, ": "),
+            (Kind::Str, r#""x""#), (Kind::Plain, ", "), (Kind::Key, r#""n""#), (Kind::Plain, ": "),
+            (Kind::Number, "12"), (Kind::Plain, "}")]));
+        let rows = highlight(Language::Markdown, &["```", "# not a heading", "```", "# Heading"]);
+        assert_eq!(rows[1], pieces(&[(Kind::Code, "# not a heading")]));
+        assert_eq!(rows[3], pieces(&[(Kind::Heading, "# Heading")]));
+    }
+
+    #[test]
+    fn detect() {
+        assert_eq!(Language::detect(Some(Path::new("a/b.RS")), ""), Some(Language::Rust));
+        assert_eq!(Language::detect(None, "#!/usr/bin/env -S python3 -u"), Some(Language::Python));
+        assert_eq!(Language::detect(Some(Path::new("run")), "#!/bin/bash"), Some(Language::Shell));
+        assert_eq!(Language::detect(Some(Path::new("notes.txt")), "hello"), None);
+    }
+}
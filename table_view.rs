@@ -0,0 +1,217 @@
+// CSV and TSV files shown as a table.
+// The first row is the header and stays at the top; columns are aligned and
+// the table scrolls sideways a whole column at a time.
+
+use crate::ansi::Style;
+use crate::display_line::{display_row, draw_styled, DEFAULT_TAB_WIDTH};
+use crate::structured::{ListCursor, StructuredView};
+use crate::syntax::Kind;
+use ncurses::*;
+use unicode_width::UnicodeWidthStr;
+
+// Longer cells are cut to this many columns
+const MAX_COLUMN_WIDTH: usize = 40;
+
+// Drawn between columns
+const SEPARATOR: &str = " │ ";
+
+pub struct TableView {
+    header: Vec<String>,
+    rows: Vec<Vec<String>>, // Data rows, without the header
+    widths: Vec<usize>, // Display width of each column
+    cursor: ListCursor, // Selected data row
+    first_column: usize, // Leftmost visible column
+}
+
+impl TableView {
+    pub fn parse(text: &str, delimiter: char) -> Result<Self, String> {
+        let mut rows = parse_rows(text, delimiter)?;
+        if rows.is_empty() {
+            return Err("No rows".to_string());
+        }
+        let header = rows.remove(0);
+        let n_columns = rows.iter().map(|r| r.len()).max().unwrap_or(0).max(header.len());
+        let mut widths = vec![1; n_columns];
+        for row in std::iter::once(&header).chain(&rows) {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                // As drawn, with line breaks in caret notation
+                *width = (*width).max(display_row(cell, DEFAULT_TAB_WIDTH, MAX_COLUMN_WIDTH).width());
+            }
+        }
+        Ok(TableView {
+            header,
+            rows,
+            widths,
+            cursor: ListCursor::default(),
+            first_column: 0,
+        })
+    }
+
+    // One row of cells, padded to the column widths, from the first visible column
+    fn row_pieces(&self, cells: &[String], style: Style, tab_width: usize) -> Vec<(Style, String)> {
+        let mut pieces = Vec::new();
+        for (column, &width) in self.widths.iter().enumerate().skip(self.first_column) {
+            if column > self.first_column {
+                pieces.push((Kind::Comment.style(), SEPARATOR.to_string()));
+            }
+            let cell = cells.get(column).map_or("", |c| c.as_str());
+            let text = display_row(cell, tab_width, width);
+            let padding = " ".repeat(width - text.width().min(width));
+            // Numbers line up on the right
+            let text = if cell.trim().parse::<f64>().is_ok() {
+                format!("{}{}", padding, text)
+            } else {
+                format!("{}{}", text, padding)
+            };
+            pieces.push((style, text));
+        }
+        pieces
+    }
+}
+
+impl StructuredView for TableView {
+    fn draw(&mut self, window: WINDOW, tab_width: usize) {
+        let height = getmaxy(window) as usize;
+        let width = getmaxx(window) as usize;
+        let page_rows = height.saturating_sub(1);
+        self.cursor.scroll_into_view(self.rows.len(), page_rows);
+
+        // Header row, frozen at the top
+        let header_style = Style::colored(COLOR_YELLOW, true);
+        let used = draw_styled(window, 0, 0, width, tab_width, self.row_pieces(&self.header, header_style, tab_width), A_UNDERLINE);
+        wattron(window, A_UNDERLINE);
+        let _ = waddstr(window, &" ".repeat(width - used));
+        wattroff(window, A_UNDERLINE);
+
+        for (screen_row, i) in (self.cursor.top..self.rows.len()).take(page_rows).enumerate() {
+            let selected = i == self.cursor.selected;
+            let extra = if selected { A_REVERSE } else { 0 };
+            let pieces = self.row_pieces(&self.rows[i], Style::default(), tab_width);
+            let used = draw_styled(window, screen_row as i32 + 1, 0, width, tab_width, pieces, extra);
+            if selected {
+                wattron(window, A_REVERSE);
+                let _ = waddstr(window, &" ".repeat(width - used));
+                wattroff(window, A_REVERSE);
+            }
+        }
+    }
+
+    fn status(&self) -> String {
+        format!(" Row {}/{}  Column {}/{}  Table ",
+            self.cursor.selected + 1, self.rows.len(), self.first_column + 1, self.widths.len())
+    }
+
+    fn handle_key(&mut self, ch: i32, page_rows: usize) {
+        // The header takes one row
+        if self.cursor.handle_key(ch, self.rows.len(), page_rows.saturating_sub(1)) {
+            return;
+        }
+        match ch {
+            KEY_LEFT => self.first_column = self.first_column.saturating_sub(1),
+            KEY_RIGHT if self.first_column + 1 < self.widths.len() => self.first_column += 1,
+            _ => {}
+        }
+    }
+}
+
+// Split text into rows of fields. Fields in double quotes may contain the
+// delimiter, line breaks and doubled quotes; TSV fields are taken as they are.
+fn parse_rows(text: &str, delimiter: char) -> Result<Vec<Vec<String>>, String> {
+    let quoting = delimiter != '\t';
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut line = 1;
+    let mut chars = text.chars().peekable();
+    let mut at_field_start = true;
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoting && at_field_start => {
+                // Quoted field
+                let start_line = line;
+                loop {
+                    match chars.next() {
+                        Some('"') if chars.peek() == Some(&'"') => {
+                            chars.next();
+                            field.push('"');
+                        }
+                        Some('"') => break,
+                        Some(c) => {
+                            if c == '\n' {
+                                line += 1;
+                            }
+                            field.push(c);
+                        }
+                        None => return Err(format!("Unterminated quote starting on line {}", start_line)),
+                    }
+                }
+                match chars.peek() {
+                    Some(&c) if c == delimiter || c == '\n' || c == '\r' => {}
+                    None => {}
+                    Some(_) => return Err(format!("Unexpected text after a closing quote on line {}", line)),
+                }
+                at_field_start = false;
+            }
+            c if c == delimiter => {
+                row.push(std::mem::take(&mut field));
+                at_field_start = true;
+            }
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+                at_field_start = true;
+                line += 1;
+            }
+            c => {
+                field.push(c);
+                at_field_start = false;
+            }
+        }
+    }
+    // Last line without a line break
+    if !at_field_start || !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rows() {
+        let cases: &[(&str, char, &[&[&str]])] = &[
+            ("a,b\n1,2\n", ',', &[&["a", "b"], &["1", "2"]]),
+            ("a,b\r\n1,2", ',', &[&["a", "b"], &["1", "2"]]),
+            ("\"x,y\",\"say \"\"hi\"\"\"\n", ',', &[&["x,y", "say \"hi\""]]),
+            ("\"two\nlines\",b\r\nc,d\n", ',', &[&["two\nlines", "b"], &["c", "d"]]),
+            ("a,b,c\n1\n,,\n", ',', &[&["a", "b", "c"], &["1"], &["", "", ""]]),
+            ("a;\"\"\n", ';', &[&["a", ""]]),
+            ("a\t\"b\"\n", '\t', &[&["a", "\"b\""]]),
+            ("a,b\n\n", ',', &[&["a", "b"], &[""]]),
+            ("", ',', &[]),
+        ];
+        for &(text, delimiter, expected) in cases {
+            let rows = parse_rows(text, delimiter).unwrap();
+            assert_eq!(rows, expected, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn bad_quotes() {
+        assert_eq!(parse_rows("a\n\"b,c\n", ',').unwrap_err(), "Unterminated quote starting on line 2");
+        assert_eq!(parse_rows("a\n\"b\"c\n", ',').unwrap_err(), "Unexpected text after a closing quote on line 2");
+    }
+
+    #[test]
+    fn ragged_table() {
+        let table = TableView::parse("name,n\nlonger name,1,extra\nx\n", ',').unwrap();
+        assert_eq!(table.header, ["name", "n"]);
+        assert_eq!(table.rows.len(), 2);
+        assert_eq!(table.widths, [11, 1, 5]);
+        assert!(TableView::parse("", ',').is_err());
+    }
+}
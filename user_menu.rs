@@ -0,0 +1,215 @@
+// The F2 user menu: shell commands for project-specific actions such as build,
+// deploy or run the tests. Entries come from a .nc.menu file in the current
+// directory, then from ~/.config/norton_commander_rust/menu. An entry is a line
+// with a hotkey and a title, followed by indented lines of commands. A line
+// starting with '+' before an entry lists patterns, as in the associations
+// file, that the entry at the cursor must match for the entry to be offered:
+//   + *.rs,Cargo.toml
+//   b  Build
+//       cargo build
+// In the commands, %f is the entry at the cursor, %d the current directory,
+// %s the marked entries (or the one at the cursor), %D the other panel's
+// directory (the current one, as there is a single panel) and %% a '%'.
+// A directory's menu file is only read if it belongs to the user and only
+// they can write to it.
+
+use crate::associations::{self, expand, quote_path};
+use crate::external;
+use crate::modal_dialog;
+use crate::settings::config_file;
+use ncurses::*;
+use std::fs;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+// Name of the menu file in a directory
+const DIR_MENU_FILE: &str = ".nc.menu";
+
+struct MenuEntry {
+    hotkey: char,
+    title: String,
+    condition: Vec<String>, // Patterns the entry at the cursor must match, if any
+    commands: String,
+}
+
+// Read the entries of a menu file, adding what couldn't be understood to `errors`
+fn parse(text: &str, path: &Path, errors: &mut Vec<String>) -> Vec<MenuEntry> {
+    let mut entries: Vec<MenuEntry> = Vec::new();
+    let mut condition = Vec::new();
+    let mut in_bad_entry = false; // Its commands are skipped
+    for (i, line) in text.lines().enumerate() {
+        let error = |message: &str| format!("{}:{}: {}", path.display(), i + 1, message);
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(patterns) = line.strip_prefix('+') {
+            condition = patterns.split(',').map(|p| p.trim().to_string()).filter(|p| !p.is_empty()).collect();
+            continue;
+        }
+        if line.starts_with(char::is_whitespace) {
+            // A command of the entry above
+            match entries.last_mut() {
+                _ if in_bad_entry => {}
+                Some(entry) => {
+                    entry.commands.push_str(line.trim_start());
+                    entry.commands.push('\n');
+                }
+                None => errors.push(error("A command before the first entry")),
+            }
+            continue;
+        }
+        let mut chars = line.chars();
+        let hotkey = chars.next().unwrap_or(' ');
+        let title = chars.as_str().trim();
+        // The condition belongs to this entry, even a bad one
+        let condition = std::mem::take(&mut condition);
+        in_bad_entry = !hotkey.is_ascii_graphic() || title.is_empty();
+        if in_bad_entry {
+            errors.push(error("Expected a hotkey and a title"));
+            continue;
+        }
+        entries.push(MenuEntry {
+            hotkey,
+            title: title.to_string(),
+            condition,
+            commands: String::new(),
+        });
+    }
+    for entry in entries.iter().filter(|e| e.commands.is_empty()) {
+        errors.push(format!("{}: '{}' has no commands", path.display(), entry.title));
+    }
+    entries.retain(|e| !e.commands.is_empty());
+    entries
+}
+
+// Whether a directory's menu file can be trusted: it is the user's own
+// and nobody else can change it, as in a checked-out project
+fn trusted(metadata: &fs::Metadata) -> bool {
+    metadata.uid() == unsafe { libc::getuid() } && metadata.mode() & 0o022 == 0
+}
+
+// The entries of the menu files for `dir`, and what was wrong with them
+fn load(dir: &Path) -> (Vec<MenuEntry>, Vec<String>) {
+    let mut entries = Vec::new();
+    let mut errors = Vec::new();
+    let dir_menu = dir.join(DIR_MENU_FILE);
+    let dir_menu = match fs::metadata(&dir_menu) {
+        Ok(metadata) if !trusted(&metadata) => {
+            errors.push(format!("{} is ignored: it belongs to someone else or others can write to it",
+                dir_menu.display()));
+            None
+        }
+        _ => Some(dir_menu),
+    };
+    for path in [dir_menu, config_file("menu")].into_iter().flatten() {
+        match fs::read_to_string(&path) {
+            Ok(text) => entries.extend(parse(&text, &path, &mut errors)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => errors.push(format!("{}: {}", path.display(), e)),
+        }
+    }
+    (entries, errors)
+}
+
+// Whether an entry is offered for the entry at the cursor
+fn applies(entry: &MenuEntry, selected: Option<&Path>) -> bool {
+    if entry.condition.is_empty() {
+        return true;
+    }
+    let Some(path) = selected else {
+        return false;
+    };
+    let name = path.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+    associations::matches_any(&entry.condition, path, &name, &mut None)
+}
+
+// Show the user menu for the current directory and run the chosen entry.
+// `selected` is the entry at the cursor, `marked` the marked entries.
+pub fn user_menu_modal(w_debug: WINDOW, parent: WINDOW, dir: &Path, selected: Option<&Path>, marked: &[PathBuf]) {
+    let (entries, errors) = load(dir);
+    if !errors.is_empty() {
+        // Over the whole screen, as the lines are long
+        modal_dialog::message(stdscr(), "User menu", &errors.join("\n"));
+        for window in [w_debug, parent] {
+            touchwin(window);
+            wrefresh(window);
+        }
+    }
+    let entries: Vec<&MenuEntry> = entries.iter().filter(|e| applies(e, selected)).collect();
+    if entries.is_empty() {
+        let text = format!("No menu entries here\nAdd them to {} or ~/.config/norton_commander_rust/menu", DIR_MENU_FILE);
+        modal_dialog::message(parent, "User menu", &text);
+        return;
+    }
+    let items: Vec<String> = entries.iter().map(|e| format!("{}  {}", e.hotkey, e.title)).collect();
+    let items: Vec<&str> = items.iter().map(String::as_str).collect();
+    let hotkeys: Vec<char> = entries.iter().map(|e| e.hotkey).collect();
+    let Some(i) = modal_dialog::choose_by_key(parent, "User menu", &items, &hotkeys) else {
+        return;
+    };
+
+    let file = selected.map(quote_path).unwrap_or_default();
+    let marked = match marked.is_empty() {
+        true => file.clone(),
+        false => marked.iter().map(|p| quote_path(p)).collect::<Vec<_>>().join(" "),
+    };
+    let values = [('f', file), ('d', quote_path(dir)), ('s', marked), ('D', quote_path(dir))];
+    let commands = expand(&entries[i].commands, &values);
+    external::run_and_wait(w_debug, &commands, dir);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_menu(text: &str) -> (Vec<MenuEntry>, Vec<String>) {
+        let mut errors = Vec::new();
+        let entries = parse(text, Path::new("menu"), &mut errors);
+        (entries, errors)
+    }
+
+    #[test]
+    fn entries_and_commands() {
+        let (entries, errors) = parse_menu("# Build\nb  Build\n    cargo build\n\tcargo test\n\nc Clean\n  cargo clean\n");
+        assert!(errors.is_empty());
+        assert_eq!(entries.len(), 2);
+        assert_eq!((entries[0].hotkey, entries[0].title.as_str()), ('b', "Build"));
+        assert_eq!(entries[0].commands, "cargo build\ncargo test\n");
+        assert_eq!((entries[1].hotkey, entries[1].commands.as_str()), ('c', "cargo clean\n"));
+    }
+
+    #[test]
+    fn conditions() {
+        let (entries, _) = parse_menu("+ *.rs, Cargo.toml ,\nb Build\n  cargo build\nl List\n  ls\n");
+        assert_eq!(entries[0].condition, ["*.rs", "Cargo.toml"]);
+        assert!(entries[1].condition.is_empty());
+        assert!(applies(&entries[0], Some(Path::new("/src/main.rs"))));
+        assert!(applies(&entries[0], Some(Path::new("/src/CARGO.TOML"))));
+        assert!(!applies(&entries[0], Some(Path::new("/src/README.md"))));
+        assert!(!applies(&entries[0], None));
+        assert!(applies(&entries[1], None));
+    }
+
+    #[test]
+    fn bad_entries() {
+        let text = "  echo orphan\nx\n  echo skipped\n+ *.c\n\u{e9} Accent\n  echo skipped\nr Run\n  ./run\nn No commands\n";
+        let (entries, errors) = parse_menu(text);
+        assert_eq!(errors, [
+            "menu:1: A command before the first entry",
+            "menu:2: Expected a hotkey and a title",
+            "menu:5: Expected a hotkey and a title",
+            "menu: 'No commands' has no commands",
+        ]);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].title, "Run");
+        // The condition went with the bad entry
+        assert!(entries[0].condition.is_empty());
+    }
+
+    #[test]
+    fn empty_menu() {
+        let (entries, errors) = parse_menu("");
+        assert!(entries.is_empty() && errors.is_empty());
+    }
+}
@@ -2,306 +2,1662 @@
 // Navigate with arrow keys and Page Up/Down.
 // Press Esc to close the window.
 
+use crate::ansi;
+use crate::clipboard;
+use crate::display_line::{display_row, draw_styled};
+use crate::encoding::Encoding;
+use crate::filter::Filter;
+use crate::hex;
+use crate::line_index::LineIndex;
+use crate::modal_dialog;
+use crate::positions::{self, SavedPosition};
+use crate::settings::{self, ViewCommand};
+use crate::source::{self, Change, Source, SourceReader};
+use crate::structured::Format;
+use crate::syntax::{self, Language};
 use ncurses::*;
-use std::collections::VecDeque;
-use std::fs::File;
+use std::collections::{BTreeMap, VecDeque};
+use std::fs;
 use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use unicode_width::UnicodeWidthStr;
 
-fn find_prev_line_start(w_debug: WINDOW, reader: &mut BufReader<File>, file_pos: u64) -> std::io::Result<u64> {
-    if file_pos == 0 {
+// How many bytes at the start of the file are sampled to guess encoding and line endings
+const SAMPLE_SIZE: u64 = 64 * 1024;
+
+// Lines longer than this many bytes are split into several rows
+const MAX_ROW_BYTES: usize = 4096;
+
+// Backward line search reads this much at a time
+const SEARCH_CHUNK: u64 = 64 * 1024;
+
+// and gives up looking for the start of a line after this many bytes
+const MAX_BACK_SEARCH: u64 = 1024 * 1024;
+
+// Files up to this size are counted in full when line numbers are turned on,
+// so the gutter has its final width from the start
+const EAGER_COUNT_BYTES: u64 = 8 * 1024 * 1024;
+
+// Rows are highlighted from the nearest remembered state at most this far back,
+// beyond that they start afresh as if nothing was open
+const MAX_SYNTAX_SCAN: u64 = 1024 * 1024;
+
+// Files up to this size can be shown as a JSON tree or a table
+const MAX_STRUCTURED_BYTES: u64 = 32 * 1024 * 1024;
+
+// The filter reads this much of the file between screen updates
+const FILTER_SCAN_BUDGET: u64 = 4 * 1024 * 1024;
+
+// Lines are counted this much at a time towards a top row whose number isn't known
+const LINE_COUNT_BUDGET: u64 = 16 * 1024 * 1024;
+
+// Rows at most this far past the counted part are numbered while drawing,
+// further ones show '?' until count_lines gets there
+const GUTTER_COUNT_BYTES: u64 = 1024 * 1024;
+
+// How often follow mode and streams check for new data (milliseconds)
+const FOLLOW_POLL_MS: i32 = 500;
+
+// How often a file is checked for changes otherwise
+const CHANGE_POLL_MS: i32 = 1000;
+
+// Line terminator style, guessed from the start of the file
+#[derive(Clone, Copy, PartialEq)]
+enum LineEnding {
+    Unknown, // No line terminator in the sample
+    Lf,      // Unix
+    CrLf,    // DOS / Windows
+    Cr,      // Classic Mac
+    Mixed,   // More than one style
+}
+
+impl LineEnding {
+    fn detect(sample: &str) -> Self {
+        let (mut lf, mut crlf, mut cr) = (0, 0, 0);
+        let mut chars = sample.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '\r' if chars.peek() == Some(&'\n') => {
+                    crlf += 1;
+                    chars.next();
+                }
+                '\r' => cr += 1,
+                '\n' => lf += 1,
+                _ => {}
+            }
+        }
+        match (lf > 0, crlf > 0, cr > 0) {
+            (false, false, false) => LineEnding::Unknown,
+            (true, false, false) => LineEnding::Lf,
+            (false, true, false) => LineEnding::CrLf,
+            (false, false, true) => LineEnding::Cr,
+            _ => LineEnding::Mixed,
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            LineEnding::Unknown => "-",
+            LineEnding::Lf => "LF",
+            LineEnding::CrLf => "CRLF",
+            LineEnding::Cr => "CR",
+            LineEnding::Mixed => "Mixed",
+        }
+    }
+}
+
+// Read the start of the file, then rewind
+fn read_sample(reader: &mut SourceReader) -> io::Result<Vec<u8>> {
+    let mut sample = Vec::new();
+    reader.seek(SeekFrom::Start(0))?;
+    reader.take(SAMPLE_SIZE).read_to_end(&mut sample)?;
+    reader.seek(SeekFrom::Start(0))?;
+    Ok(sample)
+}
+
+// Find where the row before the one starting at `file_pos` begins.
+// Searches back in chunks for the previous line feed. A line longer than
+// MAX_ROW_BYTES is shown as several rows, so the start of its last row
+// before `file_pos` is returned.
+fn find_prev_line_start(w_debug: WINDOW, reader: &mut SourceReader, encoding: Encoding, file_pos: u64) -> std::io::Result<u64> {
+    // Line starts are aligned to whole code units
+    let unit = encoding.unit_size() as u64;
+    let file_pos = file_pos - file_pos % unit;
+    if file_pos < unit {
         // Already at start of file
-        waddstr(w_debug, &format!("find_prev_line_start: already at front\n"));
+        let _ = waddstr(w_debug, "find_prev_line_start: already at front\n");
         return Ok(0);
     }
 
-    // Choose how far back to step (at most 4096 or to start of file)
-    // -1 to avoid re-reading current line's newline
-    let backstep = 4096.min((file_pos - 1) as usize) as u64;
-    let seek_pos = file_pos - backstep - 1;
-    reader.seek(SeekFrom::Start(seek_pos))?;
+    // Search backward a chunk at a time
+    // -unit to avoid re-reading current line's newline
+    let newline = encoding.newline();
+    let mut end = file_pos - unit;
+    let mut line_start = None;
+    let mut buf = Vec::new();
+    while end > 0 && file_pos - end < MAX_BACK_SEARCH {
+        let seek_pos = end.saturating_sub(SEARCH_CHUNK);
+        reader.seek(SeekFrom::Start(seek_pos))?;
+        buf.resize((end - seek_pos) as usize, 0);
+        let n = read_full(reader, &mut buf)?;
+        if let Some(rel_unit) = buf[..n].chunks_exact(unit as usize).rposition(|c| c == newline) {
+            // newline found — line starts right after it
+            line_start = Some(seek_pos + (rel_unit as u64 + 1) * unit);
+            break;
+        }
+        end = seek_pos;
+    }
 
-    let mut buf = vec![0u8; backstep as usize];
-    let n = reader.read(&mut buf)?;
-    let slice = &buf[..n];
+    let line_start = match line_start {
+        Some(pos) => pos,
+        // no newline — in the first line of the file
+        None if end == 0 => 0,
+        None => {
+            // Too far back to look: step back one row's worth
+            let _ = waddstr(w_debug, &format!("find_prev_line_start: no newline in {} bytes before {}\n", MAX_BACK_SEARCH, file_pos));
+            return row_start_near(reader, encoding, file_pos - MAX_ROW_BYTES as u64);
+        }
+    };
+    let _ = waddstr(w_debug, &format!("find_prev_line_start: line at {} fp={}\n", line_start, file_pos));
 
-    // search backward through slice
-    if let Some(rel_idx) = slice.iter().rposition(|&b| b == b'\n') {
-        // newline found — line starts right after it
-        waddstr(w_debug, &format!("find_prev_line_start: found {} + {} = {}\n", seek_pos, rel_idx, seek_pos + rel_idx as u64 + 1));
-        Ok(seek_pos + rel_idx as u64 + 1 as u64)
+    // Walk the rows of a long line to find the one just before file_pos
+    let mut row_start = line_start;
+    let mut line = String::new();
+    loop {
+        reader.seek(SeekFrom::Start(row_start))?;
+        match read_text_line(reader, encoding, &mut line)? {
+            0 => break,
+            n_bytes if row_start + n_bytes as u64 >= file_pos => break,
+            n_bytes => row_start += n_bytes as u64,
+        }
+    }
+    Ok(row_start)
+}
+
+// A row boundary at or just after `pos`, not in the middle of a character
+fn row_start_near(reader: &mut SourceReader, encoding: Encoding, pos: u64) -> io::Result<u64> {
+    let unit = encoding.unit_size() as u64;
+    let pos = pos - pos % unit;
+    if encoding != Encoding::Utf8 {
+        return Ok(pos);
+    }
+    // Skip UTF-8 continuation bytes
+    let mut buf = [0u8; 4];
+    reader.seek(SeekFrom::Start(pos))?;
+    let n = read_full(reader, &mut buf)?;
+    let skip = buf[..n].iter().take(3).take_while(|&&b| b & 0xc0 == 0x80).count();
+    Ok(pos + skip as u64)
+}
+
+// Length of `bytes` without an incomplete UTF-8 character at the end
+fn utf8_complete_len(bytes: &[u8]) -> usize {
+    // Find the first byte of the last character
+    let Some(lead) = bytes.iter().rev().take(4).position(|&b| b & 0xc0 != 0x80) else {
+        return bytes.len();
+    };
+    let lead = bytes.len() - 1 - lead;
+    let char_len = match bytes[lead] {
+        0xc0..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf7 => 4,
+        _ => 1,
+    };
+    if lead > 0 && lead + char_len > bytes.len() {
+        lead
     } else {
-        // no newline — in middle of first line, or we didn't go back far enough
-        waddstr(w_debug, &format!("find_prev_line_start: not found in pos {} .. {} fp={}\n", seek_pos, seek_pos+n as u64, file_pos));
-        Ok(0)
+        bytes.len()
     }
 }
 
-fn calc_extents() -> (i32, i32, i32, i32) {
+// Where the panels go: the directory panel and the viewer take the right part of
+// the screen, as wide as the settings say, and the debug window the rest
+pub fn calc_extents() -> (i32, i32, i32, i32) {
 
     let scr_rows = getmaxy(stdscr());
     let scr_cols = getmaxx(stdscr());
+    let startcol = (scr_cols * (100 - settings::current().panel_width) / 100).max(0); // just to be safe, nonnegative
     let height   = scr_rows.max(3);            // clamp minimum height
-    let width    = (scr_cols - scr_cols / 2).max(4); // clamp minimum width
+    let width    = (scr_cols - startcol).max(4); // clamp minimum width
     let startrow = 0;
-    let startcol = (scr_cols / 2).max(0);      // just to be safe, nonnegative
     (height, width, startrow, startcol)
 }
 
-fn resize(w_debug: WINDOW, superwindow: WINDOW, window: WINDOW, file_path: &Path) {
+// Create the border window and the text area inside it, where calc_extents puts them.
+// The diff view uses the same layout.
+pub fn new_frame() -> (WINDOW, WINDOW) {
+    let (height, width, startrow, startcol) = calc_extents();
+    let superwindow = newwin(height, width, startrow, startcol);
+    let window = newwin(height-2, width-2, startrow+1, startcol+1);
+    scrollok(window, false);
+    keypad(window, true);
+    wattron(window, COLOR_PAIR(1));
+    wbkgd(window, COLOR_PAIR(1));
+    (superwindow, window)
+}
+
+// Fit the windows made by new_frame to the terminal after it was resized
+pub fn resize_frame(w_debug: WINDOW, superwindow: WINDOW, window: WINDOW) {
     let (height, width, startrow, startcol) = calc_extents();
 
-    wresize(w_debug, height, width);
+    // The debug window has the rest of the screen
+    wresize(w_debug, height, startcol.max(1));
 
     wresize(superwindow, height, width);
     mvwin(superwindow, startrow, startcol);
 
     wresize(window, height - 2, width - 2);
     mvwin(window, startrow + 1, startcol + 1);
+}
+
+fn rtrim(line: &mut String) {
+    // Remove trailing newline if present
+    if line.ends_with('\n') {
+        line.pop(); // remove '\n'
+        if line.ends_with('\r') {
+            line.pop(); // remove '\r' for Windows CRLF
+        }
+    }
+}
+
+// Read one line from the current position, without its line terminator.
+// At most MAX_ROW_BYTES are read; the rest of a longer line is the next row.
+// Invalid sequences are replaced rather than treated as an error.
+// Returns the number of bytes consumed, 0 at EOF.
+fn read_text_line(reader: &mut SourceReader, encoding: Encoding, line: &mut String) -> io::Result<usize> {
+    let at_start = reader.stream_position()? == 0;
+    let mut bytes = Vec::new();
+    let n_bytes = if encoding.unit_size() == 1 {
+        let n = (&mut *reader).take(MAX_ROW_BYTES as u64).read_until(b'\n', &mut bytes)?;
+        if n == MAX_ROW_BYTES && bytes.last() != Some(&b'\n') && encoding == Encoding::Utf8 {
+            // Don't split a character between rows
+            bytes.truncate(utf8_complete_len(&bytes));
+        }
+        bytes.len()
+    } else {
+        // Read whole code units until a newline unit
+        let newline = encoding.newline();
+        while bytes.len() < MAX_ROW_BYTES {
+            let mut unit = [0u8; 2];
+            let n = read_full(reader, &mut unit)?;
+            bytes.extend_from_slice(&unit[..n]);
+            if n < unit.len() || unit == newline {
+                break;
+            }
+        }
+        bytes.len()
+    };
+    line.clear();
+    line.push_str(&encoding.decode(&bytes));
+    if at_start && line.starts_with('\u{feff}') {
+        // Byte order mark
+        line.remove(0);
+    }
+    rtrim(line);
+    Ok(n_bytes)
+}
 
-    // Redraw border and title
-    box_(superwindow, 0, 0);
-    mvwaddstr(superwindow, 0, 2, &format!(" {} ", file_path.display()));
-    mvwaddstr(superwindow, height - 1, 2, "Up/Down to scroll, Esc or 'q' to close");
+// Show a status on the bottom border, and a hint on the right if there is room
+pub fn draw_bottom_line(superwindow: WINDOW, status: &str, hint: &str) {
+    let height = getmaxy(superwindow);
+    let width = getmaxx(superwindow);
+    // Erase the previous status
+    mvwhline(superwindow, height - 1, 1, ACS_HLINE(), width - 2);
+    let _ = mvwaddnstr(superwindow, height - 1, 2, status, width - 4);
+    // Instructions, if there is room for them
+    let hint_col = width - 2 - hint.len() as i32;
+    if hint_col > 2 + status.len() as i32 {
+        let _ = mvwaddstr(superwindow, height - 1, hint_col, hint);
+    }
     wrefresh(superwindow);
 }
 
-fn expand_cols(window: WINDOW, line_offsets: &mut VecDeque<u64>, reader: &mut BufReader<File>, n_cols: &mut i32) -> bool {
+// Language of the text, from its file name or its first line
+fn detect_language(source: &dyn Source, encoding: Encoding, sample: &[u8]) -> Option<Language> {
+    let text = encoding.decode(sample);
+    let first_line = text.trim_start_matches('\u{feff}').lines().next().unwrap_or("");
+    Language::detect(source.text_path().as_deref(), first_line)
+}
 
-    let old_n_cols = *n_cols;
-    let new_n_cols = getmaxx(window);
-    *n_cols = new_n_cols;
+// Whether text looks like it was written with ANSI colours (ESC [)
+fn has_ansi_escapes(sample: &[u8]) -> bool {
+    sample.windows(2).any(|w| w == b"\x1b[")
+}
 
-    if old_n_cols < new_n_cols {
-        // Window grew wider
-        line_offsets.truncate(1);
-        expand_rows(window, line_offsets, reader);
-        true
+// Fill `buf` unless EOF comes first. Returns the number of bytes read.
+fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut n = 0;
+    while n < buf.len() {
+        match reader.read(&mut buf[n..])? {
+            0 => break,
+            k => n += k,
+        }
     }
-    else {
-        false
+    Ok(n)
+}
+
+// Part of the text marked with 's', or a byte range typed after 'S'
+#[derive(Clone, Copy)]
+struct Selection {
+    anchor: (u64, u64), // Start and end of the row where 's' was pressed
+    start: u64, // First selected byte
+    end: u64, // After the last selected byte
+    extending: bool, // The selection follows the top row until 's' is pressed again
+}
+
+// Parse "start-end", in decimal or 0x hex, the end not included
+fn parse_byte_range(input: &str, file_size: u64) -> Result<(u64, u64), String> {
+    let (start, end) = input.split_once('-').ok_or("Type the range as start-end")?;
+    let (start, end) = (hex::parse_offset(start)?, hex::parse_offset(end)?.min(file_size));
+    if start >= end {
+        return Err("The range is empty".to_string());
     }
+    Ok((start, end))
 }
 
-fn expand_rows(window: WINDOW, line_offsets: &mut VecDeque<u64>, reader: &mut BufReader<File>) -> bool {
+struct Viewer {
+    superwindow: WINDOW, // Border, title and status line
+    window: WINDOW, // Text area inside the border
+    reader: SourceReader, // File or stream being viewed
+    file_size: u64, // Length of the text received so far
+    receiving: bool, // A stream is still delivering data
+    encoding: Encoding, // Encoding used to decode the text
+    line_ending: LineEnding, // Guessed line terminator
+    // The file position of each visible line
+    // There will be one more element representing the next line after the bottom row.
+    line_offsets: VecDeque<u64>,
+    top_line: Option<u64>, // Line number of the top row (1-based), if known
+    counting_lines: bool, // top_line is being counted between keys
+    n_cols: i32, // Width of the text area
+    following: bool, // Follow mode: keep showing data appended to the file
+    tab_width: usize, // Distance between tab stops
+    line_index: LineIndex, // Line numbers of file offsets
+    gutter_width: i32, // Width of the line number column, 0 when hidden
+    ansi: bool, // Show ANSI colour sequences as colours instead of as text
+    language: Option<Language>, // Language for syntax highlighting
+    highlighting: bool, // Syntax highlighting is on
+    // Highlighting state at the start of rows, keyed by their line_offsets entries
+    syntax_states: BTreeMap<u64, syntax::State>,
+    last_filter: String, // Offered again the next time the filter prompt opens
+    changed: bool, // The file was rewritten, truncated or replaced since it was loaded
+    bookmarks: [Option<(u64, Option<u64>)>; 10], // Top offset and line number, set with 'm' and a digit
+    selection: Option<Selection>,
+    last_selection_path: Option<PathBuf>, // File the selection was last saved or appended to
+}
 
-    // Check preconditions
-    if is_scrollok(window) {
-        panic!("expand_rows: is_scrollok is true");
+impl Viewer {
+    // Create the viewer windows for an open source
+    fn new(source: Box<dyn Source>) -> io::Result<Self> {
+        let file_size = source.len()?;
+        let receiving = !source.is_complete();
+        let mut reader = BufReader::new(source);
+
+        // Sample the start of the file for the status line
+        let sample = read_sample(&mut reader)?;
+        let encoding = Encoding::detect(&sample);
+        let language = detect_language(reader.get_ref().as_ref(), encoding, &sample);
+
+        let (superwindow, window) = new_frame();
+
+        Ok(Viewer {
+            superwindow,
+            window,
+            reader,
+            file_size,
+            receiving,
+            encoding,
+            line_ending: LineEnding::detect(&encoding.decode(&sample)),
+            line_offsets: VecDeque::from([0]),
+            top_line: Some(1),
+            counting_lines: false,
+            n_cols: getmaxx(window),
+            following: false,
+            tab_width: settings::current().tab_width,
+            line_index: LineIndex::new(encoding),
+            gutter_width: 0,
+            ansi: has_ansi_escapes(&sample),
+            language,
+            highlighting: settings::current().highlighting,
+            syntax_states: BTreeMap::new(),
+            last_filter: String::new(),
+            changed: false,
+            bookmarks: [None; 10],
+            selection: None,
+            last_selection_path: None,
+        })
     }
-    if line_offsets.len() == 0 {
-        panic!("expand_rows: line_offsets is empty");
+
+    // Draw the border, title and status line
+    fn draw_frame(&self) {
+        // Box around window
+        box_(self.superwindow, 0, 0);
+        // Title with filename
+        let _ = mvwaddstr(self.superwindow, 0, 2, &format!(" {} ", self.reader.get_ref().title()));
+        self.draw_status();
     }
-    let n_lines = (1 + getmaxy(window) - line_offsets.len() as i32).max(0) as usize;
-    if n_lines > 0 {
-        // Move to the last visible line
-        reader.seek(SeekFrom::Start(*line_offsets.back().unwrap()));
+
+    // Show the position in the file on the bottom border
+    fn draw_status(&self) {
+        let top = *self.line_offsets.front().unwrap();
+        let bot = *self.line_offsets.back().unwrap();
+        let file_size = self.file_size;
+        let percent = (bot.min(file_size) * 100).checked_div(file_size).unwrap_or(100);
+        let line = match self.top_line {
+            Some(n) => format!("Line {}", n),
+            None => "Line ?".to_string(),
+        };
+        let mode = match self.language {
+            _ if self.ansi => "ANSI",
+            Some(language) if self.highlighting => language.name(),
+            _ => "Text",
+        };
+        let follow = if self.following { " Follow" } else { "" };
+        let truncated = match self.reader.get_ref().is_truncated() {
+            true => format!("  Truncated at {} bytes", file_size),
+            false => String::new(),
+        };
+        let changed = if self.changed { "  CHANGED, R reloads" } else { "" };
+        let mark = match self.bookmarks.iter().position(|b| b.is_some_and(|(offset, _)| offset == top)) {
+            Some(n) => format!("  Mark {}", n),
+            None => String::new(),
+        };
+        let selected = match self.selection {
+            Some(s) if s.extending => format!("  Selecting {} bytes, s ends", s.end - s.start),
+            Some(s) => format!("  Selected {} bytes, w writes", s.end - s.start),
+            None => String::new(),
+        };
+        let status = format!(" {}/{}  {}{}  {}%  {}  {}  {}{}{}{}{} ", top, file_size, line, mark,
+            percent, self.encoding.name(), self.line_ending.label(), mode, follow, truncated, changed, selected);
+        draw_bottom_line(self.superwindow, &status, &close_hint("close"));
     }
-    for _ in 0 .. n_lines {
-        let pos = *line_offsets.back().unwrap();
-        let mut line = String::new();
-        if let Ok(n_bytes) = reader.read_line(&mut line) {
-            if n_bytes == 0 {
-                break; // EOF
-            }
-            // Remove trailing newline
-            rtrim(&mut line);
-            // Draw the row
-            mvwaddnstr(window, line_offsets.len() as i32 - 1, 0, &line, getmaxx(window));
 
-            // mark where the next line will begin
-            line_offsets.push_back(pos + n_bytes as u64);
+    fn resize(&mut self, w_debug: WINDOW) {
+        resize_frame(w_debug, self.superwindow, self.window);
+
+        // Redraw border and title
+        self.draw_frame();
+    }
+
+    fn expand_cols(&mut self) -> bool {
+
+        let old_n_cols = self.n_cols;
+        let new_n_cols = self.text_cols();
+        self.n_cols = new_n_cols;
+
+        if old_n_cols < new_n_cols {
+            // Window grew wider
+            self.line_offsets.truncate(1);
+            self.expand_rows();
+            true
         }
         else {
-            // Some IO error?
-            break;
+            false
+        }
+    }
+
+    fn expand_rows(&mut self) -> bool {
+        let window = self.window;
+
+        // Check preconditions
+        if is_scrollok(window) {
+            panic!("expand_rows: is_scrollok is true");
+        }
+        if self.line_offsets.is_empty() {
+            panic!("expand_rows: line_offsets is empty");
+        }
+        let n_lines = (1 + getmaxy(window) - self.line_offsets.len() as i32).max(0) as usize;
+        for _ in 0 .. n_lines {
+            let pos = *self.line_offsets.back().unwrap();
+            // Move to the last visible line (drawing the line number moves the reader)
+            let _ = self.reader.seek(SeekFrom::Start(pos));
+            let mut line = String::new();
+            if let Ok(n_bytes) = read_text_line(&mut self.reader, self.encoding, &mut line) {
+                if n_bytes == 0 {
+                    break; // EOF
+                }
+                // Draw the row
+                self.draw_row(self.line_offsets.len() as i32 - 1, pos, n_bytes, &line);
+
+                // mark where the next line will begin
+                self.line_offsets.push_back(pos + n_bytes as u64);
+            }
+            else {
+                // Some IO error?
+                break;
+            }
+        }
+        n_lines != 0
+    }
+
+    // Whether the row at `pos` begins a line, rather than continuing a long one
+    fn starts_line(&mut self, pos: u64) -> bool {
+        let newline = self.encoding.newline();
+        if pos < newline.len() as u64 {
+            return true;
+        }
+        let mut buf = [0u8; 2];
+        let buf = &mut buf[..newline.len()];
+        self.reader.seek(SeekFrom::Start(pos - newline.len() as u64)).is_ok()
+            && read_full(&mut self.reader, buf).is_ok()
+            && buf == newline
+    }
+
+    // Draw one line of text on the given row: `n_bytes` bytes at `pos`, decoded as `line`
+    fn draw_row(&mut self, row: i32, pos: u64, n_bytes: usize, line: &str) {
+        if self.gutter_width > 0 {
+            // Number the first row of each line, leave continuation rows blank
+            let number = match self.starts_line(pos) {
+                true if self.line_index.knows(pos.saturating_sub(GUTTER_COUNT_BYTES)) =>
+                    self.line_index.line_at(&mut self.reader, pos).map(|n| n.to_string()).unwrap_or_default(),
+                true => {
+                    self.counting_lines = true;
+                    "?".to_string()
+                }
+                false => String::new(),
+            };
+            wattron(self.window, COLOR_PAIR(2));
+            let _ = mvwaddstr(self.window, row, 0, &format!("{:>1$} ", number, self.gutter_width as usize - 1));
+            wattron(self.window, COLOR_PAIR(1));
+        }
+        let pieces = if self.ansi {
+            Some(ansi::parse(line))
+        } else if self.highlighting && let Some(language) = self.language {
+            let state = self.syntax_state_at(pos);
+            let (pieces, next_state) = language.highlight(state, line);
+            self.syntax_states.insert(pos + n_bytes as u64, next_state);
+            Some(pieces.into_iter().map(|(kind, text)| (kind.style(), text)).collect())
+        } else {
+            None
+        };
+        let selected = self.selection.is_some_and(|s| pos < s.end && pos + n_bytes as u64 > s.start);
+        let extra = if selected { A_REVERSE } else { 0 };
+        let used = if let Some(pieces) = pieces {
+            draw_styled(self.window, row, self.gutter_width, self.text_cols() as usize, self.tab_width, pieces, extra)
+        } else {
+            let text = display_row(line, self.tab_width, self.text_cols() as usize);
+            wattron(self.window, extra);
+            let _ = mvwaddstr(self.window, row, self.gutter_width, &text);
+            wattroff(self.window, extra);
+            text.width()
+        };
+        if selected {
+            // Highlight the whole width
+            wattron(self.window, A_REVERSE);
+            let _ = waddstr(self.window, &" ".repeat((self.text_cols() as usize).saturating_sub(used)));
+            wattroff(self.window, A_REVERSE);
         }
     }
-    return n_lines != 0;
-}
 
-fn contract_rows(window: WINDOW, line_offsets: &mut VecDeque<u64>) -> bool {
-    // Discard rows from the bottom if needed
-    let desired_len = 1 + getmaxy(window) as usize;
-    if line_offsets.len() >= desired_len {
-        line_offsets.truncate(desired_len);
+    // Highlighting state at the start of the row at `pos`.
+    // Rows after the nearest remembered state are highlighted (without drawing)
+    // up to `pos`, so multi-line comments and strings are right after scrolling up.
+    fn syntax_state_at(&mut self, pos: u64) -> syntax::State {
+        if let Some(&state) = self.syntax_states.get(&pos) {
+            return state;
+        }
+        let (mut from, mut state) = match self.syntax_states.range(..pos).next_back() {
+            Some((&from, &state)) if pos - from <= MAX_SYNTAX_SCAN => (from, state),
+            _ if pos <= MAX_SYNTAX_SCAN => (0, syntax::State::Normal),
+            _ => return syntax::State::Normal,
+        };
+        let Some(language) = self.language else {
+            return state;
+        };
+        let mut line = String::new();
+        while from < pos {
+            if self.reader.seek(SeekFrom::Start(from)).is_err() {
+                break;
+            }
+            match read_text_line(&mut self.reader, self.encoding, &mut line) {
+                Ok(n_bytes) if n_bytes > 0 => {
+                    state = language.highlight(state, &line).1;
+                    from += n_bytes as u64;
+                    self.syntax_states.insert(from, state);
+                }
+                _ => break,
+            }
+        }
+        // Rows found by searching backwards may not line up with rows read forwards
+        if from != pos {
+            state = self.syntax_states.range(..=pos).next_back().map_or(syntax::State::Normal, |(_, &s)| s);
+        }
+        state
+    }
+
+    // Width of the text area, not counting the line numbers
+    fn text_cols(&self) -> i32 {
+        (getmaxx(self.window) - self.gutter_width).max(1)
+    }
+
+    // Widen the line numbers to fit the highest line seen so far.
+    // Returns true if the width changed and the page must be redrawn.
+    fn update_gutter(&mut self) -> bool {
+        if self.gutter_width == 0 {
+            return false;
+        }
+        let digits = self.line_index.lines_known().max(999).to_string().len() as i32;
+        if digits + 1 == self.gutter_width {
+            return false;
+        }
+        self.gutter_width = digits + 1;
+        self.n_cols = self.text_cols();
         true
-    } else {
-        false
     }
-}
 
-fn rtrim(line: &mut String) {
-    // Remove trailing newline if present
-    if line.ends_with('\n') {
-        line.pop(); // remove '\n'
-        if line.ends_with('\r') {
-            line.pop(); // remove '\r' for Windows CRLF
+    fn toggle_line_numbers(&mut self) {
+        if self.gutter_width > 0 {
+            self.gutter_width = 0;
+        } else {
+            // Count the lines of small files up front
+            let file_size = self.file_size;
+            if file_size <= EAGER_COUNT_BYTES {
+                self.line_index.line_at(&mut self.reader, file_size).ok();
+            }
+            self.gutter_width = 1;
+            self.update_gutter();
         }
+        self.n_cols = self.text_cols();
+        self.redraw();
     }
-}
 
-fn scroll_down(w_debug: WINDOW, window: WINDOW, line_offsets: &mut VecDeque<u64>, reader: &mut BufReader<File>) {
-    // Rust note: copy the element, otherwise we'd hold an immut reference to the list.
-    let bot_file_pos = *line_offsets.back().unwrap();
-    // Read a line
-    reader.seek(SeekFrom::Start(bot_file_pos));
-    let mut line = String::new();
-    let line_n_bytes = reader.read_line(&mut line).unwrap();
-    if line_n_bytes == 0 {
-        // EOF: cannot scroll down
-        beep();
+    // Where the file is shown and how, to open it the same way next time
+    fn saved_position(&self) -> SavedPosition {
+        SavedPosition {
+            offset: *self.line_offsets.front().unwrap(),
+            line: self.top_line,
+            encoding: self.encoding.name().to_string(),
+            tab_width: self.tab_width,
+            line_numbers: self.gutter_width > 0,
+            ansi: self.ansi,
+            highlighting: self.highlighting,
+        }
+    }
+
+    // Show the file as it was when it was last closed
+    fn restore(&mut self, saved: &SavedPosition) {
+        if let Some(&encoding) = Encoding::ALL.iter().find(|e| e.name() == saved.encoding)
+            && encoding != self.encoding
+        {
+            self.encoding = encoding;
+            if let Ok(sample) = read_sample(&mut self.reader) {
+                self.line_ending = LineEnding::detect(&encoding.decode(&sample));
+            }
+            self.line_index = LineIndex::new(encoding);
+        }
+        self.tab_width = saved.tab_width.max(1);
+        self.ansi = saved.ansi;
+        self.highlighting = saved.highlighting;
+        if saved.line_numbers {
+            self.toggle_line_numbers();
+        }
+        let unit = self.encoding.unit_size() as u64;
+        if saved.offset < self.file_size && saved.offset.is_multiple_of(unit) {
+            self.jump_to(saved.offset, saved.line);
+        } else {
+            self.jump_to(0, Some(1));
+        }
+    }
+
+    // Draw the page again from the same top row
+    fn redraw(&mut self) {
+        let top_line = self.top_line;
+        self.jump_to(*self.line_offsets.front().unwrap(), top_line);
+    }
+
+    fn contract_rows(&mut self) -> bool {
+        // Discard rows from the bottom if needed
+        let desired_len = 1 + getmaxy(self.window) as usize;
+        if self.line_offsets.len() >= desired_len {
+            self.line_offsets.truncate(desired_len);
+            true
+        } else {
+            false
+        }
+    }
+
+    fn scroll_down(&mut self, w_debug: WINDOW) {
+        let window = self.window;
+        // Rust note: copy the element, otherwise we'd hold an immut reference to the list.
+        let bot_file_pos = *self.line_offsets.back().unwrap();
+        // Read a line
+        let _ = self.reader.seek(SeekFrom::Start(bot_file_pos));
+        let mut line = String::new();
+        let line_n_bytes = read_text_line(&mut self.reader, self.encoding, &mut line).unwrap_or(0);
+        if line_n_bytes == 0 {
+            // EOF: cannot scroll down
+            beep();
+        }
+        else {
+            // Remove the old top row
+            self.line_offsets.pop_front();
+            // Add the new bottom row
+            self.line_offsets.push_back(bot_file_pos + line_n_bytes as u64);
+            if self.starts_line(*self.line_offsets.front().unwrap()) {
+                self.top_line = self.top_line.map(|n| n + 1);
+            }
+
+            scrollok(window, true);     // Temporarily enable scroll
+            wscrl(window, 1);
+            scrollok(window, false);
+            // Draw the bottom row
+            self.draw_row(getmaxy(window) - 1, bot_file_pos, line_n_bytes, &line);
+            wrefresh(window);
+            self.draw_status();
+
+            let _ = waddstr(w_debug, &format!("KDOWN top:{} bot:{} n:{}\n", self.line_offsets.front().unwrap(), self.line_offsets.back().unwrap(), self.line_offsets.len()));
+            wrefresh(w_debug);
+        }
     }
-    else {
-        // Remove the old top row
-        line_offsets.pop_front();
-        // Add the new bottom row
-        line_offsets.push_back(bot_file_pos + line_n_bytes as u64);
 
-        // Remove trailing newline if present
-        if line.ends_with('\n') {
-            line.pop(); // remove '\n'
-            if line.ends_with('\r') {
-                line.pop(); // remove '\r' for Windows CRLF
+    fn scroll_up(&mut self, w_debug: WINDOW) {
+        let window = self.window;
+        // Find the line before the top one
+        // if line_offsets.front() and ...
+        if *self.line_offsets.front().unwrap() > 0 && let Ok(new_pos) = find_prev_line_start(w_debug, &mut self.reader, self.encoding, *self.line_offsets.front().unwrap()) {
+
+            // Advance bottom row
+            let old_pos = *self.line_offsets.front().unwrap();
+            self.line_offsets.pop_back();
+            self.line_offsets.push_front(new_pos);
+            if new_pos == 0 {
+                self.top_line = Some(1);
+            } else if self.starts_line(old_pos) {
+                self.top_line = self.top_line.map(|n| n - 1);
+            }
+
+            let _ = waddstr(w_debug, &format!("KUP top:{} bot:{} N:{}\n",
+                *self.line_offsets.front().unwrap(), *self.line_offsets.back().unwrap(), self.line_offsets.len()));
+            wrefresh(w_debug);
+            let _ = self.reader.seek(SeekFrom::Start(new_pos));
+            // Read one new line at top
+            let mut line = String::new();
+            if let Ok(line_n_bytes) = read_text_line(&mut self.reader, self.encoding, &mut line) {
+
+                scrollok(window, true); // temporarily enable scrolling
+                wscrl(window, -1);
+                scrollok(window, false);
+                // Draw the top row
+                self.draw_row(0, new_pos, line_n_bytes, &line);
+                wrefresh(window);
             }
+            self.draw_status();
         }
+    }
 
-        scrollok(window, true);     // Temporarily enable scroll
-        wscrl(window, 1);
-        scrollok(window, false);
-        // Draw the bottom row
-        mvwaddnstr(window, getmaxy(window) - 1, 0, &line, getmaxx(window));
-        wrefresh(window);
+    // Show the file from the given line start
+    fn jump_to(&mut self, offset: u64, line: Option<u64>) {
+        self.line_offsets.clear();
+        self.line_offsets.push_back(offset);
+        // Counting the lines up to a far offset can take long, so that is left
+        // to count_lines, between keys. Drawing rows without numbers also starts it.
+        self.top_line = match line {
+            Some(n) => Some(n),
+            None if self.line_index.knows(offset) => self.line_index.line_at(&mut self.reader, offset).ok(),
+            None => None,
+        };
+        self.counting_lines = self.top_line.is_none();
+        werase(self.window);
+        self.expand_rows();
+        wrefresh(self.window);
+        self.draw_status();
+    }
 
-        waddstr(w_debug, &format!("KDOWN top:{} bot:{} n:{}\n", line_offsets.front().unwrap(), line_offsets.back().unwrap(), line_offsets.len()));
+    // Show the last page of the file
+    fn jump_to_end(&mut self, w_debug: WINDOW) {
+        let mut top = self.file_size;
+        for _ in 0 .. getmaxy(self.window) {
+            if top == 0 {
+                break;
+            }
+            match find_prev_line_start(w_debug, &mut self.reader, self.encoding, top) {
+                Ok(pos) => top = pos,
+                Err(_) => break,
+            }
+        }
+        self.jump_to(top, None);
+    }
+
+    // Set bookmark `n` at the top row
+    fn set_bookmark(&mut self, w_debug: WINDOW, n: usize) {
+        let top = *self.line_offsets.front().unwrap();
+        self.bookmarks[n] = Some((top, self.top_line));
+        let _ = waddstr(w_debug, &format!("BOOKMARK {}: {}\n", n, top));
         wrefresh(w_debug);
+        self.draw_status();
     }
-}
 
-fn scroll_up(w_debug: WINDOW, window: WINDOW, line_offsets: &mut VecDeque<u64>, reader: &mut BufReader<File>) {
-    // Find the line before the top one
-    // if line_offsets.front() and ...
-    if *line_offsets.front().unwrap() > 0 && let Ok(new_pos) = find_prev_line_start(w_debug, reader, *line_offsets.front().unwrap()) {
+    // Show bookmark `n` at the top
+    fn jump_to_bookmark(&mut self, n: usize) {
+        match self.bookmarks[n] {
+            Some((offset, line)) if offset < self.file_size || offset == 0 => self.jump_to(offset, line),
+            _ => {
+                beep();
+            }
+        }
+    }
 
-        // Advance bottom row
-        line_offsets.pop_back();
-        line_offsets.push_front(new_pos);
+    // List the bookmarks with the start of each line, and jump to the chosen one
+    fn choose_bookmark(&mut self) {
+        let preview_cols = (getmaxx(self.superwindow) as usize).saturating_sub(20).max(10);
+        let mut numbers = Vec::new();
+        let mut items = Vec::new();
+        let mut line = String::new();
+        for (n, &(offset, line_number)) in self.bookmarks.iter().enumerate()
+            .filter_map(|(n, b)| b.as_ref().map(|b| (n, b)))
+        {
+            line.clear();
+            if self.reader.seek(SeekFrom::Start(offset)).is_ok() {
+                read_text_line(&mut self.reader, self.encoding, &mut line).ok();
+            }
+            let line_number = line_number.map_or("?".to_string(), |n| n.to_string());
+            numbers.push(n);
+            items.push(format!("{}  {:>7}  {}", n, line_number, display_row(&line, self.tab_width, preview_cols)));
+        }
+        if items.is_empty() {
+            beep();
+            return;
+        }
+        let items: Vec<&str> = items.iter().map(String::as_str).collect();
+        let choice = modal_dialog::choose(self.superwindow, "Bookmarks", &items, 0);
+        self.repaint();
+        if let Some(i) = choice {
+            self.jump_to_bookmark(numbers[i]);
+        }
+    }
+
+    // 's' starts a selection at the top row, which then grows or shrinks with
+    // scrolling, and pressing 's' again ends it
+    fn toggle_selecting(&mut self) {
+        match &mut self.selection {
+            Some(s) if s.extending => s.extending = false,
+            _ => {
+                let top = *self.line_offsets.front().unwrap();
+                let end = self.line_offsets.get(1).copied().unwrap_or(top);
+                if end <= top {
+                    beep(); // Nothing below the end of the file
+                    return;
+                }
+                self.selection = Some(Selection { anchor: (top, end), start: top, end, extending: true });
+            }
+        }
+        self.redraw();
+    }
+
+    // Stretch a selection being made from its anchor to the top row.
+    // Returns true if the selection changed.
+    fn update_selection(&mut self) -> bool {
+        let top = *self.line_offsets.front().unwrap();
+        let top_end = self.line_offsets.get(1).copied().unwrap_or(top);
+        let Some(s) = self.selection.as_mut().filter(|s| s.extending) else {
+            return false;
+        };
+        let (start, end) = (s.anchor.0.min(top), s.anchor.1.max(top_end));
+        let changed = (start, end) != (s.start, s.end);
+        (s.start, s.end) = (start, end);
+        changed
+    }
+
+    // Ask for a range of bytes to select, and show its start
+    fn prompt_selection(&mut self, w_debug: WINDOW) {
+        let initial = self.selection.map(|s| format!("{}-{}", s.start, s.end)).unwrap_or_default();
+        let input = modal_dialog::prompt(self.window, "Select bytes: start-end", &initial);
+        self.repaint();
+        let Some(input) = input else {
+            return;
+        };
+        match parse_byte_range(&input, self.file_size) {
+            Ok((start, end)) => {
+                self.selection = Some(Selection { anchor: (start, end), start, end, extending: false });
+                // The row containing the start
+                let unit = self.encoding.unit_size() as u64;
+                let top = find_prev_line_start(w_debug, &mut self.reader, self.encoding, start + unit).unwrap_or(0);
+                self.jump_to(top, None);
+            }
+            Err(e) => {
+                modal_dialog::message(self.window, "Select", &e);
+                self.repaint();
+            }
+        }
+    }
+
+    // Save the selection to a new file, append it to a file, or copy it to the clipboard
+    fn selection_menu(&mut self) {
+        let Some(selection) = self.selection else {
+            beep();
+            return;
+        };
+        let n_bytes = selection.end - selection.start;
+        let title = format!("{} bytes", n_bytes);
+        let items = ["Save to a new file", "Append to a file", "Copy to the clipboard", "Clear the selection"];
+        let choice = modal_dialog::choose(self.window, &title, &items, 0);
+        self.repaint();
+        let result = match choice {
+            Some(action @ (0 | 1)) => {
+                let append = action == 1;
+                let Some(path) = self.prompt_selection_path(append) else {
+                    return;
+                };
+                self.write_selection(selection, &path, append).map(|()| {
+                    let verb = if append { "Appended" } else { "Saved" };
+                    format!("{} {} bytes to {}", verb, n_bytes, path.display())
+                })
+            }
+            Some(2) => self.read_selection_text(selection)
+                .and_then(|text| clipboard::copy(&text))
+                .map(|()| format!("Sent {} bytes to the terminal's clipboard", n_bytes)),
+            Some(3) => {
+                self.selection = None;
+                self.redraw();
+                return;
+            }
+            _ => return,
+        };
+        let text = result.unwrap_or_else(|e| e.to_string());
+        modal_dialog::message(self.window, "Selection", &text);
+        self.repaint();
+    }
+
+    // Ask where to write the selection. Relative paths are taken from the file's directory.
+    fn prompt_selection_path(&mut self, append: bool) -> Option<PathBuf> {
+        let dir = self.reader.get_ref().text_path()
+            .and_then(|p| p.parent().map(Path::to_path_buf))
+            .or_else(|| std::env::current_dir().ok())
+            .unwrap_or_default();
+        let initial = match &self.last_selection_path {
+            Some(path) => path.clone(),
+            None => dir.join("selection.txt"),
+        };
+        let title = if append { "Append to file" } else { "Save to new file" };
+        let input = modal_dialog::prompt(self.window, title, &initial.to_string_lossy());
+        self.repaint();
+        let input = input?;
+        if input.is_empty() {
+            return None;
+        }
+        let path = dir.join(input);
+        self.last_selection_path = Some(path.clone());
+        Some(path)
+    }
 
-        waddstr(w_debug, &format!("KUP top:{} bot:{} N:{}\n",
-            *line_offsets.front().unwrap(), *line_offsets.back().unwrap(), line_offsets.len()));
+    // Copy the selected bytes as they are. A new file must not exist yet; appending needs an existing file.
+    fn write_selection(&mut self, selection: Selection, path: &Path, append: bool) -> io::Result<()> {
+        let mut options = fs::OpenOptions::new();
+        if append {
+            options.append(true);
+        } else {
+            options.write(true).create_new(true);
+        }
+        let mut file = options.open(path)?;
+        self.reader.seek(SeekFrom::Start(selection.start))?;
+        let n_bytes = selection.end - selection.start;
+        let copied = io::copy(&mut (&mut self.reader).take(n_bytes), &mut file)?;
+        if copied < n_bytes {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "The file became shorter"));
+        }
+        Ok(())
+    }
+
+    // The selected text, decoded
+    fn read_selection_text(&mut self, selection: Selection) -> io::Result<String> {
+        let n_bytes = selection.end - selection.start;
+        if n_bytes > clipboard::MAX_CLIPBOARD_BYTES as u64 {
+            return Err(io::Error::other(format!("Can't copy more than {} KiB", clipboard::MAX_CLIPBOARD_BYTES / 1024)));
+        }
+        let mut bytes = Vec::new();
+        self.reader.seek(SeekFrom::Start(selection.start))?;
+        (&mut self.reader).take(n_bytes).read_to_end(&mut bytes)?;
+        let text = self.encoding.decode(&bytes);
+        Ok(text.strip_prefix('\u{feff}').map(str::to_string).unwrap_or(text))
+    }
+
+    // Let the user pick the encoding from a menu
+    fn choose_encoding(&mut self, w_debug: WINDOW) {
+        let names: Vec<&str> = Encoding::ALL.iter().map(|e| e.name()).collect();
+        let current = Encoding::ALL.iter().position(|&e| e == self.encoding).unwrap_or(0);
+        let choice = modal_dialog::choose(self.superwindow, "Encoding", &names, current);
+        self.repaint();
+        if let Some(i) = choice {
+            self.set_encoding(w_debug, Encoding::ALL[i]);
+        }
+    }
+
+    fn set_encoding(&mut self, w_debug: WINDOW, encoding: Encoding) {
+        let old_unit = self.encoding.unit_size();
+        self.encoding = encoding;
+        if let Ok(sample) = read_sample(&mut self.reader) {
+            self.line_ending = LineEnding::detect(&encoding.decode(&sample));
+        }
+        let _ = waddstr(w_debug, &format!("ENCODING: {}\n", encoding.name()));
         wrefresh(w_debug);
-        reader.seek(SeekFrom::Start(new_pos));
-        // Read one new line at top
+        self.line_index = LineIndex::new(encoding);
+        self.syntax_states.clear();
+        if encoding.unit_size() == old_unit {
+            // Line starts are unchanged, redraw in place
+            self.redraw();
+        } else {
+            self.jump_to(0, Some(1));
+        }
+    }
+
+    // Show the file as a JSON tree or a table until the user goes back to the text.
+    // Returns true if the user closed the viewer instead.
+    fn structured_view(&mut self, w_debug: WINDOW) -> bool {
+        let Some(format) = self.reader.get_ref().text_path().and_then(|p| Format::detect(&p)) else {
+            beep();
+            return false;
+        };
+        let parsed = self.read_all_text().and_then(|text| format.open(&text));
+        let mut view = match parsed {
+            Ok(view) => view,
+            Err(e) => {
+                // Stay with the plain text
+                let _ = waddstr(w_debug, &format!("STRUCTURED: {}\n", e));
+                wrefresh(w_debug);
+                modal_dialog::message(self.window, &format!("Not shown as {}", format.name()), &e);
+                self.repaint();
+                return false;
+            }
+        };
+
+        let close = loop {
+            werase(self.window);
+            view.draw(self.window, self.tab_width);
+            wrefresh(self.window);
+            draw_bottom_line(self.superwindow, &view.status(), &format!(" v text {}", close_hint("close")));
+            match wgetch(self.window) {
+                // Timeout while following: nothing to do here
+                ERR => {}
+                // 'v' to go back to the text
+                118 => break false,
+                KEY_RESIZE => self.resize(w_debug),
+                ch => {
+                    // The list moves with the same keys as the text
+                    let ch = match view_key(ch) {
+                        Some(ViewCommand::Close) => break true,
+                        Some(ViewCommand::Up) => KEY_UP,
+                        Some(ViewCommand::Down) => KEY_DOWN,
+                        Some(ViewCommand::Home) => KEY_HOME,
+                        Some(ViewCommand::End) => KEY_END,
+                        None => ch,
+                    };
+                    view.handle_key(ch, getmaxy(self.window) as usize);
+                }
+            }
+        };
+        if !close {
+            self.n_cols = self.text_cols();
+            self.draw_frame();
+            self.redraw();
+        }
+        close
+    }
+
+    // Ask for a filter and show only the lines it keeps, numbered as in the file.
+    // Enter goes back to the text at the top line shown, Esc or 'q' to where it was.
+    fn filter_view(&mut self, w_debug: WINDOW) {
+        let Some(mut filter) = self.prompt_filter() else {
+            return;
+        };
+        let mut top = 0; // Index of the top row in the matches
+        let mut at_end = false; // Showing the last page, after End
         let mut line = String::new();
-        if let Ok(_line_n_bytes) = reader.read_line(&mut line) {
+        let jump = loop {
+            // Look for matches to fill the page, or all of them after End,
+            // a bit at a time so keys still work
+            let height = getmaxy(self.window) as usize;
+            let wanted = if at_end { usize::MAX } else { top + height + 1 };
+            if !filter.is_done() && filter.matches().len() < wanted
+                && let Err(e) = filter.scan(&mut self.reader, self.encoding, wanted, FILTER_SCAN_BUDGET)
+            {
+                let _ = waddstr(w_debug, &format!("FILTER: {}\n", e));
+                wrefresh(w_debug);
+                break None;
+            }
+            let scanning = !filter.is_done() && filter.matches().len() < wanted;
+            if at_end {
+                top = filter.matches().len().saturating_sub(height);
+            }
 
-            // Remove trailing newline if present
-            if line.ends_with('\n') {
-                line.pop(); // remove '\n'
-                if line.ends_with('\r') {
-                    line.pop(); // remove '\r' for Windows CRLF
+            // Draw the page, with the line numbers from the file
+            let matches = &filter.matches()[top.min(filter.matches().len())..];
+            let visible = &matches[..matches.len().min(height)];
+            let highest = visible.last().map_or(0, |&(_, n)| n);
+            let gutter = highest.to_string().len().max(3) + 1;
+            let text_cols = (getmaxx(self.window) as usize).saturating_sub(gutter).max(1);
+            werase(self.window);
+            for (row, &(offset, number)) in visible.iter().enumerate() {
+                wattron(self.window, COLOR_PAIR(2));
+                let _ = mvwaddstr(self.window, row as i32, 0, &format!("{:>1$} ", number, gutter - 1));
+                wattron(self.window, COLOR_PAIR(1));
+                if self.reader.seek(SeekFrom::Start(offset)).is_ok()
+                    && read_text_line(&mut self.reader, self.encoding, &mut line).is_ok()
+                {
+                    let pieces = match self.ansi {
+                        true => ansi::parse(&line),
+                        false => vec![(Default::default(), line.clone())],
+                    };
+                    draw_styled(self.window, row as i32, gutter as i32, text_cols, self.tab_width, pieces, 0);
                 }
             }
+            wrefresh(self.window);
 
-            scrollok(window, true); // temporarily enable scrolling
-            wscrl(window, -1);
-            scrollok(window, false);
-            // Draw the top row
-            mvwaddnstr(window, 0, 0, &line, getmaxx(window));
-            wrefresh(window);
+            let total = filter.matches().len();
+            let progress = match scanning {
+                true => format!("  scanning {}%", (filter.scanned_to() * 100).checked_div(self.file_size).unwrap_or(100)),
+                false => String::new(),
+            };
+            let status = format!(" Filter {}  Match {}/{}{}{} ", filter.description(),
+                (top + 1).min(total), total, if filter.is_done() { "" } else { "+" }, progress);
+            draw_bottom_line(self.superwindow, &status, &format!(" f filter  Enter go to line {}", close_hint("text")));
+
+            // Don't wait for a key while there is more to scan
+            wtimeout(self.window, if scanning { 0 } else { -1 });
+            let ch = wgetch(self.window);
+            if ch != ERR && ch != KEY_RESIZE {
+                at_end = false;
+            }
+            match ch {
+                ERR => {}
+                // 'f' for another filter
+                102 => {
+                    if let Some(new_filter) = self.prompt_filter() {
+                        filter = new_filter;
+                        top = 0;
+                    }
+                }
+                // Enter to show the text from the top line shown
+                KEY_ENTER | 10 | 13 => break filter.matches().get(top).copied(),
+                KEY_RESIZE => self.resize(w_debug),
+                // The same keys as the text to scroll, Escape or 'q' to go back to it
+                _ => match view_key(ch) {
+                    Some(ViewCommand::Down) if top + height < filter.matches().len() => top += 1,
+                    Some(ViewCommand::Up) if top > 0 => top -= 1,
+                    Some(ViewCommand::Down | ViewCommand::Up) => {
+                        beep();
+                    }
+                    Some(ViewCommand::Home) => top = 0,
+                    // Find all the matches, then show the last page
+                    Some(ViewCommand::End) => at_end = true,
+                    Some(ViewCommand::Close) => break None,
+                    None => {}
+                },
+            }
+        };
+
+        self.update_timeout();
+        self.n_cols = self.text_cols();
+        self.draw_frame();
+        match jump {
+            Some((offset, number)) => self.jump_to(offset, Some(number)),
+            None => self.redraw(),
+        }
+    }
+
+    // Ask for a filter: text, /regex/, and '!' in front to invert.
+    // Returns None if the user cancelled or the filter is not valid.
+    fn prompt_filter(&mut self) -> Option<Filter> {
+        let input = modal_dialog::prompt(self.window, "Filter: text, /regex/, !not", &self.last_filter);
+        self.repaint();
+        let input = input?;
+        match Filter::parse(&input) {
+            Ok(filter) => {
+                self.last_filter = input;
+                Some(filter)
+            }
+            Err(e) => {
+                modal_dialog::message(self.window, "Filter", &e);
+                self.repaint();
+                None
+            }
+        }
+    }
+
+    // The whole text, decoded, for the structured views
+    fn read_all_text(&mut self) -> Result<String, String> {
+        if self.file_size > MAX_STRUCTURED_BYTES {
+            return Err(format!("The file is larger than {} MiB", MAX_STRUCTURED_BYTES / 1024 / 1024));
+        }
+        let mut bytes = Vec::new();
+        self.reader.seek(SeekFrom::Start(0)).map_err(|e| e.to_string())?;
+        (&mut self.reader).take(MAX_STRUCTURED_BYTES).read_to_end(&mut bytes).map_err(|e| e.to_string())?;
+        let text = self.encoding.decode(&bytes);
+        Ok(text.strip_prefix('\u{feff}').map(str::to_string).unwrap_or(text))
+    }
+
+    // Redraw everything after a popup was closed
+    fn repaint(&self) {
+        touchwin(self.superwindow);
+        wrefresh(self.superwindow);
+        touchwin(self.window);
+        wrefresh(self.window);
+    }
+
+    fn set_following(&mut self, w_debug: WINDOW, following: bool) {
+        self.following = following;
+        self.update_timeout();
+        if following {
+            if self.changed {
+                // Follow the file now at the path
+                self.reload(w_debug);
+            }
+            self.jump_to_end(w_debug);
+        } else {
+            self.draw_status();
+        }
+    }
+
+    // Wake up periodically to look for new data, more often while following or receiving
+    fn update_timeout(&self) {
+        if self.counting_lines {
+            // Count lines without waiting, keys still come first
+            wtimeout(self.window, 0);
+        } else if self.following || self.receiving {
+            wtimeout(self.window, FOLLOW_POLL_MS);
+        } else {
+            wtimeout(self.window, CHANGE_POLL_MS);
+        }
+    }
+
+    // Count some more lines towards the top row, if its number isn't known,
+    // or else towards the bottom row for the line numbers shown as '?'
+    fn count_lines(&mut self) {
+        if !self.counting_lines {
+            return;
+        }
+        // Scrolling meanwhile moves the target
+        let top = *self.line_offsets.front().unwrap();
+        let target = match self.top_line {
+            None => top,
+            Some(_) => *self.line_offsets.back().unwrap(),
+        };
+        match self.line_index.scan_towards(&mut self.reader, target, LINE_COUNT_BUDGET) {
+            Ok(false) => return,
+            Ok(true) => {
+                if self.top_line.is_none() {
+                    self.top_line = self.line_index.line_at(&mut self.reader, top).ok();
+                }
+                self.counting_lines = false;
+                if self.gutter_width > 0 {
+                    // Number the rows, which may start counting again up to the bottom
+                    self.update_gutter();
+                    self.redraw();
+                    return;
+                }
+            }
+            Err(_) => self.counting_lines = false, // The lines stay unknown
+        }
+        self.draw_status();
+    }
+
+    // Check the source for appended data, truncation or rotation
+    fn poll_source(&mut self, w_debug: WINDOW) {
+        let old_size = self.file_size;
+        match self.reader.get_mut().poll(w_debug) {
+            Ok(Change::Unchanged) => {}
+            Ok(Change::Replaced) => {
+                let len = self.reader.get_ref().len().unwrap_or(0);
+                if self.following || len < self.file_size {
+                    // Following, or the open file itself was truncated so the
+                    // text on screen is gone: load what is there now
+                    self.reload(w_debug);
+                } else if !self.changed {
+                    // A new file at the path, or rewritten in place:
+                    // keep showing what was loaded until the user reloads
+                    self.changed = true;
+                    self.draw_status();
+                }
+            }
+            Ok(Change::Grew) => {
+                self.file_size = self.reader.get_ref().len().unwrap_or(old_size);
+                // The last row may have been incomplete
+                self.syntax_states.split_off(&old_size);
+                if old_size == 0 {
+                    // First data from a stream: now there is something to sample
+                    self.detect_text();
+                }
+                if self.following {
+                    // Show the new last page
+                    self.jump_to_end(w_debug);
+                } else if *self.line_offsets.back().unwrap() >= old_size {
+                    // The page reached the old end: fill it up
+                    self.redraw();
+                } else {
+                    self.draw_status();
+                }
+            }
+            Err(e) => {
+                let _ = waddstr(w_debug, &format!("POLL: {}\n", e));
+                wrefresh(w_debug);
+            }
+        }
+
+        if self.receiving && self.reader.get_ref().is_complete() {
+            // The stream has ended
+            self.receiving = false;
+            self.update_timeout();
+            self.draw_frame();
+            self.repaint();
+        }
+    }
+
+    // Load the current contents of the file, near the same position
+    fn reload(&mut self, w_debug: WINDOW) {
+        let old_top = *self.line_offsets.front().unwrap();
+        if let Err(e) = self.reader.get_mut().reload(w_debug) {
+            let _ = waddstr(w_debug, &format!("RELOAD: {}\n", e));
+            wrefresh(w_debug);
+            return;
+        }
+        // Seeking drops what the reader buffered from the old contents
+        self.reader.seek(SeekFrom::Start(0)).ok();
+        self.changed = false;
+        self.selection = None;
+        self.file_size = self.reader.get_ref().len().unwrap_or(0);
+        // A compressed file is decompressed again in the background
+        self.receiving = !self.reader.get_ref().is_complete();
+        self.update_timeout();
+        self.line_index = LineIndex::new(self.encoding);
+        self.syntax_states.clear();
+        self.draw_frame();
+        if self.following {
+            // A rotated or truncated file is new: show it from the start, so
+            // nothing written since is skipped, and follow it from there
+            self.jump_to(0, Some(1));
+        } else if old_top >= self.file_size {
+            self.jump_to_end(w_debug);
+        } else if old_top == 0 {
+            self.jump_to(0, Some(1));
+        } else {
+            // The row around the old top offset
+            let unit = self.encoding.unit_size() as u64;
+            let top = find_prev_line_start(w_debug, &mut self.reader, self.encoding, old_top + unit).unwrap_or(0);
+            self.jump_to(top, None);
+        }
+    }
+
+    // Guess encoding and line endings from the start of the text
+    fn detect_text(&mut self) {
+        if let Ok(sample) = read_sample(&mut self.reader) {
+            self.encoding = Encoding::detect(&sample);
+            self.line_ending = LineEnding::detect(&self.encoding.decode(&sample));
+            self.line_index = LineIndex::new(self.encoding);
+            self.ansi = has_ansi_escapes(&sample);
+            self.language = detect_language(self.reader.get_ref().as_ref(), self.encoding, &sample);
+            self.syntax_states.clear();
+        }
+    }
+
+    // Print the cached line offsets to the debug window
+    fn dump_offsets(&self, w_debug: WINDOW, label: &str) {
+        let _ = waddstr(w_debug, &format!("{}N:{} H:{}", label, self.line_offsets.len(), getmaxy(self.window)));
+        for i in &self.line_offsets {
+            let _ = waddstr(w_debug, &format!(" {}", i));
         }
+        let _ = waddstr(w_debug, "\n");
+        wrefresh(w_debug);
+    }
+}
+
+impl Drop for Viewer {
+    fn drop(&mut self) {
+        delwin(self.window);
+        delwin(self.superwindow);
     }
 }
 
+// The keys that scroll or close, the same in the text and in the views over
+// it: Up, Down, Home, End, and Escape or 'q' unless the settings say otherwise
+pub fn view_key(ch: i32) -> Option<ViewCommand> {
+    settings::current().view_command(ch)
+}
+
+// The hint for the keys that close, e.g. " q/Esc close "
+pub fn close_hint(what: &str) -> String {
+    format!(" {} {} ", settings::current().view_key_names(ViewCommand::Close), what)
+}
+
+// How the user left the viewer
+#[derive(Clone, Copy, PartialEq)]
+pub enum ViewerExit {
+    Close,
+    Next, // Ctrl-Right: view the next file
+    Previous, // Ctrl-Left: view the previous file
+}
+
 pub fn view_file_modal(w_debug: WINDOW, file_path: &Path) {
+    view_path_modal(w_debug, file_path, false, false);
+}
 
-    let file = match File::open(file_path) {
-        Ok(f) => f,
+// View `files[index]`, moving on to the next or previous file on Ctrl-Right/Left.
+// Returns the index of the file that was shown last.
+pub fn view_files_modal(w_debug: WINDOW, files: &[PathBuf], mut index: usize) -> usize {
+    loop {
+        let has_previous = index > 0;
+        let has_next = index + 1 < files.len();
+        match view_path_modal(w_debug, &files[index], has_previous, has_next) {
+            ViewerExit::Next if has_next => index += 1,
+            ViewerExit::Previous if has_previous => index -= 1,
+            _ => return index,
+        }
+    }
+}
+
+fn view_path_modal(w_debug: WINDOW, file_path: &Path, has_previous: bool, has_next: bool) -> ViewerExit {
+    match source::open_path(w_debug, file_path) {
+        Ok(source) => view_modal(w_debug, source, Some(file_path), has_previous, has_next),
         Err(e) => {
-            waddstr(
+            let _ = waddstr(
                 w_debug,
                 &format!("Error opening file {}: {}\n", file_path.display(), e),
             );
             wrefresh(w_debug);
-            return;
+            ViewerExit::Close
+        }
+    }
+}
+
+// View the output of a shell command run in `dir`
+pub fn view_command_modal(w_debug: WINDOW, command: &str, dir: &Path) {
+    match source::StreamSource::command(command, dir) {
+        Ok(source) => view_source_modal(w_debug, Box::new(source), None),
+        Err(e) => {
+            let _ = waddstr(w_debug, &format!("Error running {}: {}\n", command, e));
+            wrefresh(w_debug);
+        }
+    }
+}
+
+// View an open source. A file at `remember` opens where it was left last time.
+pub fn view_source_modal(w_debug: WINDOW, source: Box<dyn Source>, remember: Option<&Path>) {
+    view_modal(w_debug, source, remember, false, false);
+}
+
+// Show a source until the user closes the viewer or moves to another file.
+// A file at `remember` opens where it was left last time, if it is unchanged.
+// `has_previous` and `has_next` say whether Ctrl-Left and Ctrl-Right have a file to go to.
+fn view_modal(w_debug: WINDOW, source: Box<dyn Source>, remember: Option<&Path>, has_previous: bool, has_next: bool) -> ViewerExit {
+    let title = source.title();
+    let mut viewer = match Viewer::new(source) {
+        Ok(v) => v,
+        Err(e) => {
+            let _ = waddstr(w_debug, &format!("Error reading {}: {}\n", title, e));
+            wrefresh(w_debug);
+            return ViewerExit::Close;
         }
     };
 
-    let mut reader = BufReader::new(file);
+    // Load and display the visible portion
+    let saved = remember.map(positions::load).transpose().unwrap_or_else(|e| {
+        let _ = waddstr(w_debug, &format!("POSITIONS: {}\n", e));
+        None
+    }).flatten();
+    viewer.draw_frame();
+    match saved {
+        Some(saved) => viewer.restore(&saved),
+        None if settings::current().line_numbers => viewer.toggle_line_numbers(),
+        None => {
+            viewer.expand_rows();
+            wrefresh(viewer.window);
+        }
+    }
+    viewer.update_timeout();
 
-    let (height, width, startrow, startcol) = calc_extents();
-    let superwindow = newwin(height, width, startrow, startcol);
-    let window = newwin(height-2, width-2, startrow+1, startcol+1);
-    let mut n_cols = width-2;
-    scrollok(window, false);
-    keypad(window, true);
-    wattron(window, COLOR_PAIR(1));
-    wbkgd(window, COLOR_PAIR(1));
+    viewer.dump_offsets(w_debug, "OPEN ");
 
-    // Box around window
-    box_(superwindow, 0, 0);
-    // Title with filename
-    mvwaddstr(superwindow, 0, 2, &format!(" {} ", file_path.display()));
-    // Instructions
-    mvwaddstr(superwindow, height-1, 2, "Up/Down to scroll, Esc or 'q' to close");
-    wrefresh(superwindow);
+    let exit = loop {
+        let ch = wgetch(viewer.window);
+        if viewer.following && ch != ERR && ch != KEY_RESIZE {
+            // Any key stops following
+            viewer.set_following(w_debug, false);
+        }
+        match ch {
+            // Timeout while following, receiving or counting lines
+            ERR => {
+                viewer.count_lines();
+                viewer.poll_source(w_debug);
+            }
 
-    // The file position of each visible line
-    // There will be one more element representing the next line after the bottom row.
-    let mut line_offsets: VecDeque<u64>= VecDeque::from([0]);
+            // 'e' to choose the encoding
+            101 => {
+                viewer.choose_encoding(w_debug);
+            }
 
-    // Load and display the visible portion
-    expand_rows(window, &mut line_offsets, &mut reader);
-    wrefresh(window);
+            // 't' to cycle the tab width
+            116 => {
+                viewer.tab_width = match viewer.tab_width {
+                    8 => 4,
+                    4 => 2,
+                    _ => 8,
+                };
+                viewer.redraw();
+            }
 
-    waddstr(w_debug, &format!("OPEN N:{} offsets:", line_offsets.len()));
-    for i in &line_offsets {
-        waddstr(w_debug, &format!(" {}", i));
-    }
-    waddstr(w_debug, "\n");
-    wrefresh(w_debug);
+            // 'a' to switch between ANSI colours and plain text
+            97 => {
+                viewer.ansi = !viewer.ansi;
+                viewer.redraw();
+            }
 
-    loop {
-        match wgetch(window) {
-            KEY_DOWN => {
-                scroll_down(w_debug, window, &mut line_offsets, &mut reader);
+            // 'v' to show a JSON file as a tree, or a CSV or TSV file as a table
+            118 if viewer.structured_view(w_debug) => {
+                break ViewerExit::Close;
             }
 
-            KEY_UP => {
-                scroll_up(w_debug, window, &mut line_offsets, &mut reader);
+            // 'f' to show only the lines matching a filter
+            102 => {
+                viewer.filter_view(w_debug);
             }
 
-            114 => {
-                waddstr(w_debug, &format!("N:{} H:{}", line_offsets.len(), getmaxy(window)));
-                for i in &line_offsets {
-                    waddstr(w_debug, &format!(" {}", i));
+            // 'h' to turn syntax highlighting on or off
+            104 => {
+                viewer.highlighting = !viewer.highlighting;
+                viewer.redraw();
+            }
+
+            // 'n' to show or hide line numbers
+            110 => {
+                viewer.toggle_line_numbers();
+            }
+
+            // 'm' and a digit to set a bookmark.
+            // Wait for the digit, the timeout is restored below.
+            109 => {
+                draw_bottom_line(viewer.superwindow, " Mark: press a digit 0-9 ", "");
+                wtimeout(viewer.window, -1);
+                let digit = wgetch(viewer.window);
+                match digit {
+                    48..=57 => viewer.set_bookmark(w_debug, (digit - 48) as usize),
+                    _ => {
+                        beep();
+                        viewer.draw_status();
+                    }
                 }
-                waddstr(w_debug, "\n");
-                wrefresh(w_debug);
+            }
+
+            // A digit to go to that bookmark
+            48..=57 => {
+                viewer.jump_to_bookmark((ch - 48) as usize);
+            }
+
+            // 'b' to list the bookmarks
+            98 => {
+                viewer.choose_bookmark();
+            }
+
+            // 's' to start or end selecting rows from the top row
+            115 => {
+                viewer.toggle_selecting();
+            }
+
+            // 'S' to select a range of bytes
+            83 => {
+                viewer.prompt_selection(w_debug);
+            }
+
+            // 'w' to save, append or copy the selection
+            119 => {
+                viewer.selection_menu();
+            }
+
+            // 'R' to reload a file that changed on disk
+            82 => {
+                viewer.reload(w_debug);
+            }
+
+            // 'F' to follow the end of a growing file, like tail -f
+            70 => {
+                viewer.set_following(w_debug, true);
             }
 
             // Handle terminal resize
             KEY_RESIZE => {
-                resize(w_debug, superwindow, window, &file_path);
-                expand_cols(window, &mut line_offsets, &mut reader, &mut n_cols)
-                || expand_rows(window, &mut line_offsets, &mut reader)
-                || contract_rows(window, &mut line_offsets);
-                wrefresh(window);
-                waddstr(w_debug, &format!("N:{} H:{}", line_offsets.len(), getmaxy(window)));
-                for i in &line_offsets {
-                    waddstr(w_debug, &format!(" {}", i));
+                viewer.resize(w_debug);
+                if !viewer.expand_cols() && !viewer.expand_rows() {
+                    viewer.contract_rows();
                 }
-                waddstr(w_debug, "\n");
-                wrefresh(w_debug);
+                wrefresh(viewer.window);
+                viewer.draw_status();
+                viewer.dump_offsets(w_debug, "");
             }
 
-            // Escape or 'q' to quit
-            113 | 27 => {
-                break;
+            // Escape clears the selection first
+            27 if viewer.selection.is_some() => {
+                viewer.selection = None;
+                viewer.redraw();
             }
-            _ => {}
+
+            _ => match view_key(ch) {
+                Some(ViewCommand::Down) => viewer.scroll_down(w_debug),
+                Some(ViewCommand::Up) => viewer.scroll_up(w_debug),
+                // Home and End to jump to the start or the end
+                Some(ViewCommand::Home) => viewer.jump_to(0, Some(1)),
+                Some(ViewCommand::End) => viewer.jump_to_end(w_debug),
+                Some(ViewCommand::Close) => break ViewerExit::Close,
+                // Ctrl-Right and Ctrl-Left to view the next or previous file.
+                // These keys have no fixed codes, so they are told apart by name.
+                None => match keyname(ch).as_deref() {
+                    Some("kRIT5") if has_next => break ViewerExit::Next,
+                    Some("kLFT5") if has_previous => break ViewerExit::Previous,
+                    Some("kRIT5" | "kLFT5") => {
+                        beep();
+                    }
+                    _ => {}
+                },
+            },
         }
+
+        // Scrolling may have found more lines than the line numbers have room for,
+        // or moved the end of a selection being made
+        if viewer.update_gutter() | viewer.update_selection() {
+            viewer.redraw();
+        }
+        viewer.update_timeout();
+    };
+
+    if let Some(path) = remember
+        && let Err(e) = positions::save(path, &viewer.saved_position())
+    {
+        let _ = waddstr(w_debug, &format!("POSITIONS: {}\n", e));
+        wrefresh(w_debug);
     }
-    delwin(window);
-    delwin(superwindow);
+    exit
 }